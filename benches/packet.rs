@@ -0,0 +1,253 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use dns_starter_rust::{
+    array_buffer::ArrayBuffer,
+    cache,
+    domain_name::DomainName,
+    packet::DNSPacketBuilder,
+    proto::{self, FromPacketBytes, QClass, QType},
+    question::Question,
+    resource::{Resource, ResourceData},
+    suffix_matcher::SuffixMatcher,
+};
+
+/// Counts heap allocations, so benchmarks can report how many a single operation makes.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// The number of heap allocations made while running `f`. Other threads of the benchmark
+/// harness are counted too, so take the smallest of a few runs.
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn a_record(name: &DomainName, addr: [u8; 4]) -> Resource {
+    Resource(
+        name.clone(),
+        Arc::new(ResourceData::A {
+            ttl: 300,
+            addr: addr.into(),
+        }),
+    )
+}
+
+/// A response to codecrafters.io A with `answers` records.
+fn response(answers: u8) -> DNSPacketBuilder {
+    let name = DomainName::from_static("codecrafters.io");
+    (0..answers).fold(
+        DNSPacketBuilder::query(1234).add_question(Question::new(
+            QType::A,
+            QClass::IN,
+            name.clone(),
+        )),
+        |builder, i| builder.add_answer(a_record(&name, [10, 0, 0, i])),
+    )
+}
+
+fn parse(c: &mut Criterion) {
+    let mut buffer = ArrayBuffer::new();
+    response(8).build_into(&mut buffer);
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("response", |b| {
+        b.iter(|| {
+            let packet = proto::Packet::parse(black_box(&buffer), 0)
+                .unwrap()
+                .unwrap();
+            for answer in packet.answers() {
+                black_box(answer.data().unwrap());
+            }
+        })
+    });
+
+    // Long owner names that share no suffix, so they are not compressed. Finding the fixed size
+    // fields used to mean walking every label of the name on each access.
+    let mut builder = DNSPacketBuilder::query(1);
+    for i in 0..32 {
+        let name: DomainName = format!("host.a.deeply.nested.zone.in.region{i}")
+            .parse()
+            .unwrap();
+        builder = builder.add_answer(a_record(&name, [10, 0, 0, 1]));
+    }
+    let mut many = ArrayBuffer::new();
+    builder.build_into(&mut many);
+    let packet = proto::Packet::parse(&many, 0).unwrap().unwrap();
+    group.bench_function("iterate_resources", |b| {
+        b.iter(|| {
+            for answer in packet.answers() {
+                black_box((answer.typ(), answer.class(), answer.ttl()));
+                black_box(answer.data().unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn build(c: &mut Criterion) {
+    let mut buffer = ArrayBuffer::new();
+
+    let mut group = c.benchmark_group("build");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("response", |b| {
+        b.iter(|| {
+            buffer.clear();
+            response(8).build_into(&mut buffer);
+            black_box(&buffer);
+        })
+    });
+
+    // Only writing the records is measured, as adding them to the builder grows its lists.
+    // Addresses are written without allocating, so a response full of A records makes the same
+    // amount of allocations as one with a single record. The counts are printed when
+    // BENCH_ALLOCATIONS is set.
+    let print_allocations = std::env::var_os("BENCH_ALLOCATIONS").is_some();
+    for answers in [1, 32] {
+        let allocated = (0..10)
+            .map(|_| {
+                let builder = response(answers);
+                allocations(|| {
+                    buffer.clear();
+                    builder.build_into(&mut buffer)
+                })
+            })
+            .min()
+            .unwrap();
+        if print_allocations {
+            eprintln!("build/a_records/{answers}: {allocated} allocations per response");
+        }
+        group.bench_with_input(
+            BenchmarkId::new("a_records", answers),
+            &answers,
+            |b, &answers| {
+                b.iter_batched(
+                    || response(answers),
+                    |builder| {
+                        buffer.clear();
+                        builder.build_into(&mut buffer);
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+
+    // Every name shares the suffix of the ones before it, which makes compression look through
+    // a growing list of written names.
+    for names in [8, 32, 128] {
+        let records: Vec<_> = (0..names)
+            .map(|i| {
+                let name: DomainName = format!("host{i}.region.example.com").parse().unwrap();
+                a_record(&name, [10, 0, 0, 1])
+            })
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::new("repeated_suffixes", names),
+            &records,
+            |b, records| {
+                b.iter(|| {
+                    buffer.clear();
+                    records
+                        .iter()
+                        .fold(
+                            DNSPacketBuilder::query(1),
+                            |builder, Resource(name, data)| {
+                                builder.add_answer(Resource(name.clone(), Arc::clone(data)))
+                            },
+                        )
+                        .build_into(&mut buffer);
+                    black_box(&buffer);
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn cache_get(c: &mut Criterion) {
+    let name = DomainName::from_static("codecrafters.io");
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let cache = runtime.block_on(async {
+        let (cache, operator) = cache::new();
+        tokio::spawn(operator.listen());
+        let mut bulk = cache.bulk();
+        for i in 0..4 {
+            let Resource(name, data) = a_record(&name, [10, 0, 0, i]);
+            bulk = bulk
+                .insert(&name, ResourceData::clone(&data))
+                .await
+                .unwrap();
+        }
+        bulk.publish().await.unwrap();
+        cache
+    });
+
+    let mut group = c.benchmark_group("cache");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("get", |b| {
+        b.iter(|| black_box(cache.get((&name, QType::A))))
+    });
+    group.finish();
+}
+
+/// Matching names of more and more labels against a blocklist of many names. The time grows with
+/// the labels of the name, not with the size of the list.
+fn suffix_match(c: &mut Criterion) {
+    let mut matcher: SuffixMatcher = (0..10_000)
+        .map(|i| format!("host{i}.example.com").parse().unwrap())
+        .collect();
+    // The names are inserted as well, so matching them walks the trie down to their last label.
+    let names: Vec<(usize, DomainName)> = [3, 6, 12, 24]
+        .into_iter()
+        .map(|labels| {
+            let name = (3..labels)
+                .map(|i| format!("l{i}."))
+                .chain(["host5000.example.com".to_string()])
+                .collect::<String>();
+            (labels, name.parse().unwrap())
+        })
+        .collect();
+    for (_, name) in &names {
+        matcher.insert_exact(name);
+    }
+
+    let mut group = c.benchmark_group("suffix_match");
+    group.throughput(Throughput::Elements(1));
+    for (labels, name) in &names {
+        group.bench_with_input(BenchmarkId::from_parameter(labels), name, |b, name| {
+            b.iter(|| black_box(matcher.matches(name)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, build, cache_get, suffix_match);
+criterion_main!(benches);
@@ -0,0 +1,24 @@
+#![no_main]
+
+use dns_starter_rust::proto::{DomainName, FromPacketBytes, Label};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks where in the rest the name starts, so pointers can go both ways.
+    let Some((&offset, bytes)) = data.split_first() else {
+        return;
+    };
+    let offset = offset as usize;
+    if let Ok(Some(label)) = Label::parse(bytes, offset) {
+        for label in label {
+            if label.is_err() {
+                break;
+            }
+        }
+    }
+    if let Ok(Some(name)) = DomainName::parse(bytes, offset) {
+        let _ = name.size_in_packet();
+        let _ = format!("{name} {name:?}");
+        let _ = name.iter().count();
+    }
+});
@@ -0,0 +1,34 @@
+#![no_main]
+
+use dns_starter_rust::{
+    presentation,
+    proto::{FromPacketBytes, Packet},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Packet::validate_verbose(data);
+    let Ok(Some(packet)) = Packet::parse(data, 0) else {
+        return;
+    };
+    // Formatting walks every section and reads every field of each entry.
+    let _ = format!("{packet:?}");
+    for question in packet.questions() {
+        let _ = format!("{question}");
+    }
+    for record in packet
+        .answers()
+        .chain(packet.authority())
+        .chain(packet.additional())
+    {
+        let _ = format!("{record}");
+        let _ = record.as_bytes();
+    }
+    // Rendering converts every record into owned data, expanding compressed names.
+    let _ = presentation::render(&packet);
+    if let Some(edns) = packet.edns() {
+        let _ = edns.options();
+        let _ = edns.extended_error();
+        let _ = edns.client_subnet();
+    }
+});
@@ -0,0 +1,26 @@
+#![no_main]
+
+use dns_starter_rust::proto::{EdnsView, FromPacketBytes, Resource};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&offset, bytes)) = data.split_first() else {
+        return;
+    };
+    let Ok(Some(resource)) = Resource::parse(bytes, offset as usize) else {
+        return;
+    };
+    let _ = (
+        resource.typ(),
+        resource.class(),
+        resource.ttl(),
+        resource.data(),
+    );
+    let _ = format!("{resource} {resource:?}");
+    let _ = resource.data_cursor().read_name();
+    if let Some(edns) = EdnsView::from_resource(resource) {
+        let _ = edns.options();
+        let _ = edns.extended_error();
+        let _ = edns.client_subnet();
+    }
+});
@@ -93,6 +93,17 @@ impl ArrayBuffer {
             .unwrap_or(&mut [])
     }
 
+    /// The bytes from `start` to `end` in the layout of the [fmt::Binary] dump, with every line
+    /// starting with the offset of its first byte in the buffer.
+    ///
+    /// Panics if the range is out of bounds, like slicing would.
+    pub fn hexdump_range(&self, start: usize, end: usize) -> String {
+        let mut dump = String::new();
+        write_hexdump(&mut dump, &self.as_slice()[start..end], Some(start))
+            .expect("Writing to a String to succeed");
+        dump
+    }
+
     pub fn set_len(&mut self, new_len: usize) {
         self.len = new_len;
     }
@@ -279,40 +290,53 @@ impl fmt::Debug for ArrayBuffer {
 const LINE_ITEM_COUNT: usize = 16;
 impl fmt::Binary for ArrayBuffer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut buffer = self.as_slice();
-        loop {
-            let slice = &buffer[..usize::min(LINE_ITEM_COUNT, buffer.len())];
-            buffer = &buffer[slice.len()..];
-
-            for i in 0..LINE_ITEM_COUNT {
-                if let Some(byte) = slice.get(i) {
-                    if *byte < 16 {
-                        write!(f, "0")?;
-                    }
-                    write!(f, "{byte:x?} ")?;
-                } else {
-                    write!(f, "   ")?;
+        write_hexdump(f, self.as_slice(), None)
+    }
+}
+
+/// Write `buffer` as lines of hex and ASCII, prefixed by offsets counted from `offset` if given.
+fn write_hexdump(
+    f: &mut impl fmt::Write,
+    mut buffer: &[u8],
+    mut offset: Option<usize>,
+) -> fmt::Result {
+    loop {
+        let slice = &buffer[..usize::min(LINE_ITEM_COUNT, buffer.len())];
+        buffer = &buffer[slice.len()..];
+
+        if let Some(offset) = &mut offset {
+            write!(f, "{offset:04x}  ")?;
+            *offset += slice.len();
+        }
+
+        for i in 0..LINE_ITEM_COUNT {
+            if let Some(byte) = slice.get(i) {
+                if *byte < 16 {
+                    write!(f, "0")?;
                 }
+                write!(f, "{byte:x?} ")?;
+            } else {
+                write!(f, "   ")?;
             }
+        }
 
-            for i in 0..LINE_ITEM_COUNT {
-                if let Some(byte) = slice.get(i) {
-                    if byte.is_ascii_alphanumeric() || *byte == b'-' {
-                        write!(f, "{}", *byte as char)?;
-                    } else {
-                        write!(f, ".")?;
-                    }
+        for i in 0..LINE_ITEM_COUNT {
+            if let Some(byte) = slice.get(i) {
+                if byte.is_ascii_alphanumeric() || *byte == b'-' {
+                    write!(f, "{}", *byte as char)?;
                 } else {
-                    write!(f, " ")?;
+                    write!(f, ".")?;
                 }
+            } else {
+                write!(f, " ")?;
             }
-            write!(f, "\n")?;
-            if buffer.is_empty() {
-                break;
-            }
         }
-        Ok(())
+        write!(f, "\n")?;
+        if buffer.is_empty() {
+            break;
+        }
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -364,4 +388,20 @@ mod tests {
         buf.put_u32(0x00010001);
         assert_eq!(&[0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1], buf.as_slice());
     }
+
+    #[test]
+    fn hexdump_of_a_range_has_offsets() {
+        let mut buf = ArrayBuffer::new();
+        buf.put_slice(&[0xff; 14]);
+        buf.put_slice(b"\x03www\x07example\x03com\x00");
+        buf.put_u16(1);
+        buf.put_u16(1);
+        assert_eq!(
+            buf.hexdump_range(14, 35),
+            concat!(
+                "000e  03 77 77 77 07 65 78 61 6d 70 6c 65 03 63 6f 6d .www.example.com\n",
+                "001e  00 00 01 00 01                                  .....           \n",
+            )
+        );
+    }
 }
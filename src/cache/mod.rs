@@ -1,21 +1,29 @@
-use std::sync::Arc;
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
 
 use evmap_derive::ShallowCopy;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
-use crate::{domain_name::DomainName, proto::{Type, QType}, resource::ResourceData};
+use crate::{
+    domain_name::DomainName,
+    proto::{Class, ClientSubnet, QClass, QType, Type},
+    resource::{Resource, ResourceData},
+};
+
+pub mod snapshot;
 
 
 pub fn new() -> (EVCache, EVCacheOperator) {
     let (thr, thw) = evmap::new();
     let (dnir, dniw) = evmap::new();
     let (dnatir, dnatiw) = evmap::new();
+    let (air, aiw) = evmap::new();
     let (ccs, ccr) = mpsc::channel(200);
 
     let cache = EVCache {
         table_handle: thr,
         domain_name_index: dnir,
         domain_name_and_type_index: dnatir,
+        address_index: air,
         control_channel: ccs,
     };
 
@@ -23,6 +31,7 @@ pub fn new() -> (EVCache, EVCacheOperator) {
         table_handle: thw,
         domain_name_index: dniw,
         domain_name_and_type_index: dnatiw,
+        address_index: aiw,
         control_channel: ccr,
     };
 
@@ -31,13 +40,55 @@ pub fn new() -> (EVCache, EVCacheOperator) {
 
 
 #[derive(Debug, ShallowCopy, Clone, Hash, PartialEq, Eq)]
-struct CacheKey(Arc<(DomainName, Type, Arc<[u8]>)>);
+struct CacheKey(Arc<CacheKeyFields>);
+
+/// The name, type, class, scope and data of a record.
+type CacheKeyFields = (DomainName, Type, Class, Option<Scope>, Arc<[u8]>);
+
+/// The network a record was answered for with EDNS Client Subnet (RFC 7871). A record with a
+/// scope is only returned to clients within it, so answers for different networks are kept apart.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Scope {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Scope {
+    /// The scope a resolver answered `subnet` with.
+    pub fn of(subnet: &ClientSubnet) -> Self {
+        Self {
+            address: mask(subnet.address, subnet.scope_prefix),
+            prefix: subnet.scope_prefix,
+        }
+    }
+
+    /// Whether every address of `subnet` is within the scope.
+    pub fn contains(&self, subnet: &ClientSubnet) -> bool {
+        subnet.source_prefix >= self.prefix && mask(subnet.address, self.prefix) == self.address
+    }
+}
+
+/// `address` with the bits after the first `prefix` bits cleared.
+fn mask(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix.min(32)));
+            IpAddr::from((u32::from(addr) & mask.unwrap_or(0)).to_be_bytes())
+        }
+        IpAddr::V6(addr) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix.min(128)));
+            IpAddr::from((u128::from(addr) & mask.unwrap_or(0)).to_be_bytes())
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct EVCache {
     table_handle: evmap::ReadHandle<CacheKey, Arc<ResourceData>>,
     domain_name_index: evmap::ReadHandle<DomainName, CacheKey>,
     domain_name_and_type_index: evmap::ReadHandle<(DomainName, Type), CacheKey>,
+    /// The A and AAAA records by their address, for reverse lookups.
+    address_index: evmap::ReadHandle<IpAddr, CacheKey>,
     control_channel: mpsc::Sender<EVControlMessage>,
 }
 
@@ -45,12 +96,29 @@ pub struct EVCache {
 // operation on the structure produces new clones of the evmap::ReadHandle. In cases where data
 // is extracted from the structure they are in a concurrenct safe container such as Arc or
 // themselfs implemented as Arc-like.
+//
+// With the multi-threaded runtime every task has its own clone, so a read handle only moves
+// between threads together with its task and is never read from two threads at once.
 unsafe impl Sync for EVCache {
 }
 
 impl EVCache {
+    /// The records of class IN for `key` that were not answered for a client subnet.
     pub fn get(&self, key: impl Into<GetKey>) -> Option<Box<[Arc<ResourceData>]>> {
+        self.get_scoped(key, Class::IN, None)
+    }
+
+    /// The records of class `class` for `key`, or of every class for [`QClass::Any`]. With a
+    /// `subnet`, the records answered for the narrowest scope containing it are returned in place
+    /// of the records without a scope.
+    pub fn get_scoped(
+        &self,
+        key: impl Into<GetKey>,
+        class: impl Into<QClass>,
+        subnet: Option<&ClientSubnet>,
+    ) -> Option<Box<[Arc<ResourceData>]>> {
         let key = key.into();
+        let class = class.into();
         let keys = if let Some(typ) = key.1 {
             self.domain_name_and_type_index.get(&(key.0, typ))
         } else {
@@ -60,7 +128,99 @@ impl EVCache {
             return None;
         };
 
-        Some(keys.iter().filter_map(|key| self.table_handle.get_one(key).map(|v| Arc::clone(v.as_ref()))).collect::<Vec<_>>().into_boxed_slice())
+        let keys: Vec<_> = keys
+            .iter()
+            .filter(|key| class == QClass::Any || QClass::from(key.0 .2) == class)
+            .collect();
+        let scope = subnet.and_then(|subnet| {
+            keys.iter()
+                .filter_map(|key| key.0 .3)
+                .filter(|scope| scope.contains(subnet))
+                .max_by_key(|scope| scope.prefix)
+        });
+
+        let records: Box<[_]> = keys
+            .into_iter()
+            .filter(|key| key.0 .3 == scope)
+            .filter_map(|key| {
+                self.table_handle
+                    .get_one(key)
+                    .map(|v| Arc::clone(v.as_ref()))
+            })
+            .collect();
+        (!records.is_empty()).then_some(records)
+    }
+
+    /// The records of class IN for `name` that were not answered for a client subnet, grouped by
+    /// their type.
+    pub fn get_grouped(&self, name: &DomainName) -> HashMap<Type, Vec<Arc<ResourceData>>> {
+        let mut grouped: HashMap<Type, Vec<_>> = HashMap::new();
+        for data in self.get(name).unwrap_or_default().into_vec() {
+            grouped.entry(*data.typ()).or_default().push(data);
+        }
+        grouped
+    }
+
+    /// A snapshot of every published record in the cache. The records are read from a single
+    /// view of the table, so records published while dumping are either all included or not at
+    /// all.
+    pub fn dump(&self) -> Vec<(DomainName, Type, ResourceData)> {
+        let Some(table) = self.table_handle.read() else {
+            return Vec::new();
+        };
+        table
+            .iter()
+            .flat_map(|(key, values)| {
+                let (name, typ, ..) = key.0.as_ref();
+                values
+                    .iter()
+                    .map(move |data| (name.clone(), *typ, ResourceData::clone(data)))
+            })
+            .collect()
+    }
+
+    /// Every cached A or AAAA record of `addr` along with its name.
+    pub fn find_by_address(&self, addr: IpAddr) -> Vec<(DomainName, Arc<ResourceData>)> {
+        let Some(keys) = self.address_index.get(&addr) else {
+            return Vec::new();
+        };
+        keys.iter()
+            .filter_map(|key| {
+                let record = self.table_handle.get_one(key)?;
+                Some((key.0 .0.clone(), Arc::clone(record.as_ref())))
+            })
+            .collect()
+    }
+
+    /// The records of `zone` in zone order: the names at or below the zone sorted with parents
+    /// before their children, starting with the SOA of the zone. Only the names are collected up
+    /// front, the records of a name are read when the iterator reaches it.
+    pub fn zone(&self, zone: &DomainName) -> ZoneRecords {
+        let mut names: Vec<DomainName> = self
+            .domain_name_index
+            .read()
+            .map(|index| {
+                index
+                    .iter()
+                    .map(|(name, _)| name)
+                    .filter(|name| name.is_subdomain_of(zone))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort_by_cached_key(|name| {
+            let mut labels: Vec<_> = name
+                .labels()
+                .map(|label| label.as_bytes().to_ascii_lowercase())
+                .collect();
+            labels.reverse();
+            labels
+        });
+        ZoneRecords {
+            cache: self.clone(),
+            names: names.into_iter(),
+            records: Vec::new().into_iter(),
+        }
     }
 
     pub fn bulk(&self) -> EVCacheBulk {
@@ -70,6 +230,35 @@ impl EVCache {
     }
 }
 
+/// Iterator returned by [EVCache::zone].
+pub struct ZoneRecords {
+    cache: EVCache,
+    names: std::vec::IntoIter<DomainName>,
+    records: std::vec::IntoIter<Resource>,
+}
+
+impl Iterator for ZoneRecords {
+    type Item = Resource;
+
+    fn next(&mut self) -> Option<Resource> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Some(record);
+            }
+            let name = self.names.next()?;
+            let Some(records) = self.cache.get(&name) else {
+                continue;
+            };
+            let mut records: Vec<_> = records
+                .iter()
+                .map(|data| Resource(name.clone(), Arc::clone(data)))
+                .collect();
+            records.sort_by_key(|Resource(_, data)| *data.typ() != Type::SOA);
+            self.records = records.into_iter();
+        }
+    }
+}
+
 pub struct EVCacheBulk {
     control_channel: mpsc::Sender<EVControlMessage>,
 }
@@ -82,33 +271,47 @@ impl EVCacheBulk {
         self,
         domain_name: &DomainName,
         data: ResourceData,
+    ) -> Result<Self, CacheOperatorGone> {
+        self.insert_scoped(domain_name, data, None).await
+    }
+
+    /// Insert a record answered for the clients within `scope` only.
+    pub async fn insert_scoped(
+        self,
+        domain_name: &DomainName,
+        data: ResourceData,
+        scope: Option<Scope>,
     ) -> Result<Self, CacheOperatorGone> {
         self.control_channel
-            .send(EVControlMessage::Insert(domain_name.clone(), data))
+            .send(EVControlMessage::Insert(domain_name.clone(), data, scope))
             .await
             .map_err(|_| CacheOperatorGone)?;
         Ok(self)
     }
 
+    /// Make the inserted records visible to readers. Returns once the cache operator has
+    /// published them.
     pub async fn publish(self) -> Result<(), CacheOperatorGone> {
+        let (tx, rx) = oneshot::channel();
         self.control_channel
-            .send(EVControlMessage::Publish)
+            .send(EVControlMessage::Publish(tx))
             .await
             .map_err(|_| CacheOperatorGone)?;
-        Ok(())
+        rx.await.map_err(|_| CacheOperatorGone)
     }
 }
 
 #[derive(Debug)]
 enum EVControlMessage {
-    Insert(DomainName, ResourceData),
-    Publish,
+    Insert(DomainName, ResourceData, Option<Scope>),
+    Publish(oneshot::Sender<()>),
 }
 
 pub struct EVCacheOperator {
     table_handle: evmap::WriteHandle<CacheKey, Arc<ResourceData>>,
     domain_name_index: evmap::WriteHandle<DomainName, CacheKey>,
     domain_name_and_type_index: evmap::WriteHandle<(DomainName, Type), CacheKey>,
+    address_index: evmap::WriteHandle<IpAddr, CacheKey>,
     control_channel: mpsc::Receiver<EVControlMessage>,
 }
 
@@ -117,16 +320,38 @@ impl EVCacheOperator {
         while let Some(msg) = self.control_channel.recv().await {
             tracing::debug!("Received cache control message: {msg:?}");
             match msg {
-                EVControlMessage::Insert(name, data) => {
-                    let key = CacheKey(Arc::new((name.clone(), *data.typ(), Arc::from(data.data().as_ref()))));
+                EVControlMessage::Insert(name, data, scope) => {
+                    if let Err(e) = name.validate() {
+                        tracing::warn!(%name, message = %e, "Skipping record with an invalid name");
+                        continue;
+                    }
+                    let name = name.canonical();
+                    let key = CacheKey(Arc::new((
+                        name.clone(),
+                        *data.typ(),
+                        data.class(),
+                        scope,
+                        Arc::from(data.data().as_ref()),
+                    )));
                     self.domain_name_and_type_index.insert((name.clone(), *data.typ()), key.clone());
                     self.domain_name_index.insert(name.clone(), key.clone());
+                    match data {
+                        ResourceData::A { addr, .. } => {
+                            self.address_index.insert(addr.into(), key.clone());
+                        }
+                        ResourceData::AAAA { addr, .. } => {
+                            self.address_index.insert(addr.into(), key.clone());
+                        }
+                        _ => {}
+                    }
                     self.table_handle.update(key, Arc::new(data));
                 }
-                EVControlMessage::Publish => {
+                EVControlMessage::Publish(published) => {
                     self.table_handle.refresh();
                     self.domain_name_index.refresh();
                     self.domain_name_and_type_index.refresh();
+                    self.address_index.refresh();
+                    let _ = published.send(());
                 }
             }
         }
@@ -170,3 +395,234 @@ convert_into_get_key!((DomainName, Option<QType>): (dn, typ) => GetKey(dn, typ.a
 convert_into_get_key!((&DomainName, Option<&QType>): (dn, typ) => GetKey(dn.clone(), typ.and_then(into_type)));
 convert_into_get_key!((&DomainName, Option<QType>): (dn, typ) => GetKey(dn.clone(), typ.as_ref().and_then(into_type)));
 convert_into_get_key!((DomainName, Option<&QType>): (dn, typ) => GetKey(dn, typ.and_then(into_type)));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn dump_contains_every_record() {
+        let (cache, operator) = new();
+        tokio::spawn(operator.listen());
+        let records = [
+            (
+                DomainName::from_static("codecrafters.io"),
+                ResourceData::A {
+                    ttl: 500,
+                    addr: [8, 8, 8, 8].into(),
+                },
+            ),
+            (
+                DomainName::from_static("codecrafters.io"),
+                ResourceData::A {
+                    ttl: 500,
+                    addr: [8, 8, 4, 4].into(),
+                },
+            ),
+            (
+                DomainName::from_static("example.com"),
+                ResourceData::AAAA {
+                    ttl: 60,
+                    addr: "2001:db8::1".parse().unwrap(),
+                },
+            ),
+        ];
+        let mut bulk = cache.bulk();
+        for (name, data) in records.iter().cloned() {
+            bulk = bulk.insert(&name, data).await.unwrap();
+        }
+        bulk.publish().await.unwrap();
+
+        let dump = cache.dump();
+        assert_eq!(dump.len(), records.len());
+        for (name, data) in &records {
+            assert!(dump
+                .iter()
+                .any(|(n, typ, d)| n == name && typ == data.typ() && d == data));
+        }
+    }
+
+    #[tokio::test]
+    async fn records_of_a_name_are_grouped_by_type() {
+        let (cache, operator) = new();
+        tokio::spawn(operator.listen());
+        let name = DomainName::from_static("example.com");
+        let mut mx = vec![0, 10];
+        mx.extend(DomainName::from_static("mail.example.com").to_bytes());
+        let mut bulk = cache.bulk();
+        for data in [
+            ResourceData::A {
+                ttl: 60,
+                addr: [10, 0, 0, 1].into(),
+            },
+            ResourceData::A {
+                ttl: 60,
+                addr: [10, 0, 0, 2].into(),
+            },
+            ResourceData::AAAA {
+                ttl: 60,
+                addr: "2001:db8::1".parse().unwrap(),
+            },
+            ResourceData::Generic {
+                typ: Type::MX,
+                class: Class::IN,
+                ttl: 60,
+                data: Arc::from(mx),
+            },
+        ] {
+            bulk = bulk.insert(&name, data).await.unwrap();
+        }
+        bulk.publish().await.unwrap();
+
+        let grouped = cache.get_grouped(&name);
+        assert_eq!(grouped.len(), 3);
+        assert_eq!(grouped[&Type::A].len(), 2);
+        assert_eq!(grouped[&Type::AAAA].len(), 1);
+        assert_eq!(grouped[&Type::MX].len(), 1);
+        assert!(cache
+            .get_grouped(&DomainName::from_static("example.org"))
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn records_with_invalid_names_are_skipped() {
+        let (cache, operator) = new();
+        tokio::spawn(operator.listen());
+        let a = ResourceData::A {
+            ttl: 60,
+            addr: [10, 0, 0, 1].into(),
+        };
+        let valid = DomainName::from_static("example.com");
+        // 255 bytes of text, but 257 in the wire format.
+        let too_long: DomainName = format!("{}a", "a.".repeat(127)).parse().unwrap();
+        let empty_label: DomainName = "www..example.com".parse().unwrap();
+        cache
+            .bulk()
+            .insert(&too_long, a.clone())
+            .await
+            .unwrap()
+            .insert(&empty_label, a.clone())
+            .await
+            .unwrap()
+            .insert(&valid, a)
+            .await
+            .unwrap()
+            .publish()
+            .await
+            .unwrap();
+
+        assert!(cache.get((&valid, Type::A)).is_some());
+        assert!(cache.get((&too_long, Type::A)).is_none());
+        assert!(cache.get((&empty_label, Type::A)).is_none());
+        assert_eq!(cache.dump().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn zone_records_are_in_zone_order() {
+        let (cache, operator) = new();
+        tokio::spawn(operator.listen());
+        let a = |addr: [u8; 4]| ResourceData::A {
+            ttl: 60,
+            addr: addr.into(),
+        };
+        let soa = ResourceData::Generic {
+            typ: Type::SOA,
+            class: crate::proto::Class::IN,
+            ttl: 3600,
+            data: Arc::from(&[0; 22][..]),
+        };
+        let mut bulk = cache.bulk();
+        for (name, data) in [
+            ("www.b.example.com", a([10, 0, 0, 3])),
+            ("example.com", a([10, 0, 0, 1])),
+            ("a.example.com", a([10, 0, 0, 2])),
+            ("b.example.com", a([10, 0, 0, 4])),
+            ("example.com", soa),
+            ("example.org", a([10, 0, 0, 5])),
+        ] {
+            bulk = bulk
+                .insert(&DomainName::from_static(name), data)
+                .await
+                .unwrap();
+        }
+        bulk.publish().await.unwrap();
+
+        let zone: Vec<_> = cache
+            .zone(&DomainName::from_static("example.com"))
+            .map(|Resource(name, data)| (name.to_string(), *data.typ()))
+            .collect();
+        assert_eq!(
+            zone,
+            [
+                ("example.com.".to_string(), Type::SOA),
+                ("example.com.".to_string(), Type::A),
+                ("a.example.com.".to_string(), Type::A),
+                ("b.example.com.".to_string(), Type::A),
+                ("www.b.example.com.".to_string(), Type::A),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scoped_records_are_kept_apart() {
+        let (cache, operator) = new();
+        tokio::spawn(operator.listen());
+        let name = DomainName::from_static("cdn.example.com");
+        let a = |addr: [u8; 4]| ResourceData::A {
+            ttl: 60,
+            addr: addr.into(),
+        };
+        let subnet = |address: [u8; 4], source_prefix, scope_prefix| ClientSubnet {
+            source_prefix,
+            scope_prefix,
+            address: IpAddr::from(address),
+        };
+        cache
+            .bulk()
+            .insert(&name, a([10, 0, 0, 1]))
+            .await
+            .unwrap()
+            .insert_scoped(
+                &name,
+                a([10, 0, 0, 2]),
+                Some(Scope::of(&subnet([192, 0, 2, 0], 24, 24))),
+            )
+            .await
+            .unwrap()
+            .insert_scoped(
+                &name,
+                a([10, 0, 0, 3]),
+                Some(Scope::of(&subnet([198, 51, 100, 0], 24, 16))),
+            )
+            .await
+            .unwrap()
+            .publish()
+            .await
+            .unwrap();
+
+        let get = |client: Option<ClientSubnet>| {
+            cache
+                .get_scoped((&name, Type::A), Class::IN, client.as_ref())
+                .unwrap()
+                .iter()
+                .map(|record| record.data().as_ref().to_vec())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(get(None), [[10, 0, 0, 1]]);
+        assert_eq!(get(Some(subnet([192, 0, 2, 0], 24, 0))), [[10, 0, 0, 2]]);
+        assert_eq!(get(Some(subnet([198, 51, 7, 0], 24, 0))), [[10, 0, 0, 3]]);
+        assert_eq!(get(Some(subnet([203, 0, 113, 0], 24, 0))), [[10, 0, 0, 1]]);
+        // A client subnet wider than the scope may include addresses outside of it.
+        assert_eq!(get(Some(subnet([192, 0, 0, 0], 16, 0))), [[10, 0, 0, 1]]);
+        assert!(cache
+            .get_scoped((&name, Type::A), Class::CH, None)
+            .is_none());
+        assert_eq!(
+            cache
+                .get_scoped((&name, Type::A), QClass::Any, None)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! A compact binary snapshot of the cache, so a restarted server answers from the records it had
+//! before.
+//!
+//! A snapshot starts with [MAGIC], a version byte and the time it was taken in seconds since the
+//! Unix epoch. Every record follows in a frame prefixed with its length as a `u16`:
+//!
+//! ```text
+//! name (uncompressed wire format) | type u16 | class u16 | ttl u32 | scope | data length u16 | data
+//! ```
+//!
+//! The scope is the address family (0 without a scope, 4 or 6), followed by the address and the
+//! prefix length with one. All integers are big-endian. The TTLs count down from the time the
+//! snapshot was taken, so records that expired since are left out when it is loaded. A record
+//! with a TTL of zero is answered from the cache like any other, but as clients may not keep it,
+//! it isn't saved either.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use thiserror::Error;
+
+use super::{EVCache, Scope};
+use crate::{
+    domain_name::DomainName,
+    proto::{Class, Cursor, CursorError, Type},
+    resource::ResourceData,
+};
+
+pub const MAGIC: &[u8; 4] = b"DNSC";
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Not a cache snapshot")]
+    Magic,
+    #[error("Unsupported cache snapshot version {0}")]
+    Version(u8),
+    #[error("Unknown address family {0} of a record scope")]
+    Scope(u8),
+    #[error(transparent)]
+    Record(#[from] CursorError),
+    #[error("The cache is no longer accepting records")]
+    CacheOperatorGone,
+}
+
+/// Encode every published record of `cache` as it is at `now`.
+pub fn encode(cache: &EVCache, now: SystemTime) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&unix_secs(now).to_be_bytes());
+
+    let Some(table) = cache.table_handle.read() else {
+        return bytes;
+    };
+    let mut frame = Vec::new();
+    for (key, values) in table.iter() {
+        let (name, typ, class, scope, _) = key.0.as_ref();
+        for data in values.iter().filter(|data| data.ttl() > 0) {
+            frame.clear();
+            frame.extend(name.to_bytes());
+            frame.extend_from_slice(&typ.as_u16().to_be_bytes());
+            frame.extend_from_slice(&class.as_u16().to_be_bytes());
+            frame.extend_from_slice(&data.ttl().to_be_bytes());
+            match scope.map(|scope| (scope.address, scope.prefix)) {
+                None => frame.push(0),
+                Some((IpAddr::V4(addr), prefix)) => {
+                    frame.push(4);
+                    frame.extend_from_slice(&addr.octets());
+                    frame.push(prefix);
+                }
+                Some((IpAddr::V6(addr), prefix)) => {
+                    frame.push(6);
+                    frame.extend_from_slice(&addr.octets());
+                    frame.push(prefix);
+                }
+            }
+            let data = data.data();
+            // Lengths are saved as a u16, so larger records can't be.
+            let Ok(data_len) = u16::try_from(data.as_ref().len()) else {
+                tracing::warn!(%name, ?typ, "Skipping record with data too long to save");
+                continue;
+            };
+            frame.extend_from_slice(&data_len.to_be_bytes());
+            frame.extend_from_slice(data.as_ref());
+
+            let Ok(frame_len) = u16::try_from(frame.len()) else {
+                tracing::warn!(%name, ?typ, "Skipping record too long to save");
+                continue;
+            };
+            bytes.extend_from_slice(&frame_len.to_be_bytes());
+            bytes.extend_from_slice(&frame);
+        }
+    }
+    bytes
+}
+
+/// Insert the records of the snapshot `bytes` into `cache` with the TTLs they have left at
+/// `now`, and publish them. Returns the number of records inserted.
+pub async fn load(cache: &EVCache, bytes: &[u8], now: SystemTime) -> Result<usize, SnapshotError> {
+    let mut cursor = Cursor::new(bytes, 0);
+    if cursor.read_slice(MAGIC.len())? != MAGIC {
+        return Err(SnapshotError::Magic);
+    }
+    match cursor.read_u8()? {
+        VERSION => {}
+        version => return Err(SnapshotError::Version(version)),
+    }
+    let taken = u64::from(cursor.read_u32()?) << 32 | u64::from(cursor.read_u32()?);
+    let elapsed = unix_secs(now).saturating_sub(taken);
+
+    let mut bulk = cache.bulk();
+    let mut inserted = 0;
+    while cursor.offset() < bytes.len() {
+        let len = cursor.read_u16()? as usize;
+        let mut frame = Cursor::new(cursor.read_slice(len)?, 0);
+        let name = DomainName::from(&frame.read_name()?);
+        let typ = Type::from(frame.read_u16()?);
+        let class = Class::from(frame.read_u16()?);
+        let ttl = frame.read_u32()?;
+        let scope = match frame.read_u8()? {
+            0 => None,
+            4 => {
+                let octets: [u8; 4] = frame.read_slice(4)?.try_into().unwrap();
+                Some(IpAddr::from(Ipv4Addr::from(octets)))
+            }
+            6 => {
+                let octets: [u8; 16] = frame.read_slice(16)?.try_into().unwrap();
+                Some(IpAddr::from(Ipv6Addr::from(octets)))
+            }
+            family => return Err(SnapshotError::Scope(family)),
+        }
+        .map(|address| frame.read_u8().map(|prefix| Scope { address, prefix }))
+        .transpose()?;
+        let data_len = frame.read_u16()? as usize;
+        let data = frame.read_slice(data_len)?;
+
+        let Some(ttl) = u64::from(ttl).checked_sub(elapsed).filter(|&ttl| ttl > 0) else {
+            continue;
+        };
+        let data = ResourceData::from_parts(typ, class, ttl as u32, data);
+        bulk = bulk
+            .insert_scoped(&name, data, scope)
+            .await
+            .map_err(|_| SnapshotError::CacheOperatorGone)?;
+        inserted += 1;
+    }
+    bulk.publish()
+        .await
+        .map_err(|_| SnapshotError::CacheOperatorGone)?;
+    Ok(inserted)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::proto::ClientSubnet;
+
+    #[tokio::test]
+    async fn expired_records_are_left_out() {
+        let (cache, operator) = super::super::new();
+        tokio::spawn(operator.listen());
+        let name = DomainName::from_static("example.com");
+        let subnet = ClientSubnet {
+            address: IpAddr::from([192, 0, 2, 1]),
+            source_prefix: 24,
+            scope_prefix: 24,
+        };
+        cache
+            .bulk()
+            .insert(
+                &name,
+                ResourceData::A {
+                    ttl: 60,
+                    addr: Ipv4Addr::new(10, 0, 0, 1),
+                },
+            )
+            .await
+            .unwrap()
+            .insert(
+                &name,
+                ResourceData::A {
+                    ttl: 10,
+                    addr: Ipv4Addr::new(10, 0, 0, 2),
+                },
+            )
+            .await
+            .unwrap()
+            .insert_scoped(
+                &name,
+                ResourceData::A {
+                    ttl: 60,
+                    addr: Ipv4Addr::new(10, 0, 0, 3),
+                },
+                Some(Scope::of(&subnet)),
+            )
+            .await
+            .unwrap()
+            .publish()
+            .await
+            .unwrap();
+
+        let taken = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let snapshot = encode(&cache, taken);
+
+        let (restored, operator) = super::super::new();
+        tokio::spawn(operator.listen());
+        let loaded = load(&restored, &snapshot, taken + Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert_eq!(loaded, 2);
+        let records = restored.get((&name, Type::A)).unwrap();
+        assert_eq!(
+            records.as_ref(),
+            [std::sync::Arc::new(ResourceData::A {
+                ttl: 30,
+                addr: Ipv4Addr::new(10, 0, 0, 1),
+            })]
+        );
+        let scoped = restored
+            .get_scoped((&name, Type::A), Class::IN, Some(&subnet))
+            .unwrap();
+        assert_eq!(scoped[0].ttl(), 30);
+        assert_eq!(scoped[0].data().as_ref(), [10, 0, 0, 3]);
+
+        assert!(matches!(
+            load(&restored, b"DNSX", taken).await,
+            Err(SnapshotError::Magic)
+        ));
+    }
+
+    #[tokio::test]
+    async fn zero_ttl_records_are_served_but_not_saved() {
+        let (cache, operator) = super::super::new();
+        tokio::spawn(operator.listen());
+        let name = DomainName::from_static("example.com");
+        cache
+            .bulk()
+            .insert(
+                &name,
+                ResourceData::A {
+                    ttl: 0,
+                    addr: Ipv4Addr::new(10, 0, 0, 1),
+                },
+            )
+            .await
+            .unwrap()
+            .publish()
+            .await
+            .unwrap();
+        let records = cache.get((&name, Type::A)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].ttl(), 0);
+
+        let taken = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let snapshot = encode(&cache, taken);
+        assert_eq!(snapshot.len(), MAGIC.len() + 1 + 8);
+        let (restored, operator) = super::super::new();
+        tokio::spawn(operator.listen());
+        assert_eq!(load(&restored, &snapshot, taken).await.unwrap(), 0);
+        assert!(restored.get((&name, Type::A)).is_none());
+    }
+
+    #[tokio::test]
+    async fn records_too_long_to_save_are_left_out() {
+        let (cache, operator) = super::super::new();
+        tokio::spawn(operator.listen());
+        let name = DomainName::from_static("example.com");
+        let txt = |len: usize| ResourceData::Generic {
+            typ: Type::TXT,
+            class: Class::IN,
+            ttl: 60,
+            data: vec![0; len].into(),
+        };
+        let mut bulk = cache.bulk();
+        // The data of the first one doesn't fit in a u16, the frame of the second one doesn't.
+        for data in [txt(70_000), txt(65_530), txt(10)] {
+            bulk = bulk.insert(&name, data).await.unwrap();
+        }
+        bulk.publish().await.unwrap();
+
+        let taken = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let snapshot = encode(&cache, taken);
+        let (restored, operator) = super::super::new();
+        tokio::spawn(operator.listen());
+        assert_eq!(load(&restored, &snapshot, taken).await.unwrap(), 1);
+        let records = restored.get((&name, Type::TXT)).unwrap();
+        assert_eq!(records[0].data().as_ref().len(), 10);
+    }
+}
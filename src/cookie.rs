@@ -0,0 +1,170 @@
+//! Server cookies (RFC 7873) in the interoperable format of RFC 9018. A client that sends a
+//! client cookie gets a server cookie back, which it returns in later requests to show that it
+//! receives responses at its source address.
+//! ```text
+//!      0                   1                   2                   3
+//!      0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//!     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//!     |    Version    |                   Reserved                    |
+//!     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//!     |                           Timestamp                           |
+//!     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//!     |                             Hash                              |
+//!     |                                                               |
+//!     +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+//! The hash is a truncated HMAC-SHA256 of the client cookie, the first 8 bytes of the server
+//! cookie and the client address, keyed with a secret only known to this server.
+
+use std::{
+    net::IpAddr,
+    sync::RwLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+/// The only server cookie version defined.
+const VERSION: u8 = 1;
+/// Seconds a server cookie stays valid after it was generated.
+const MAX_AGE: u32 = 3600;
+/// Seconds a server cookie may be generated in the future, for servers sharing a secret.
+const MAX_SKEW: u32 = 300;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The secrets server cookies are generated with. After a rotation cookies generated with the
+/// previous secret are still accepted, so clients don't see their cookies fail all at once.
+#[derive(Debug)]
+pub struct ServerCookies {
+    secrets: RwLock<Secrets>,
+}
+
+#[derive(Debug)]
+struct Secrets {
+    current: [u8; 32],
+    previous: Option<[u8; 32]>,
+}
+
+impl Default for ServerCookies {
+    fn default() -> Self {
+        Self {
+            secrets: RwLock::new(Secrets {
+                current: random_secret(),
+                previous: None,
+            }),
+        }
+    }
+}
+
+impl ServerCookies {
+    /// Replace the secret with a new random one. Cookies of the replaced secret stay valid until
+    /// the next rotation.
+    pub fn rotate(&self) {
+        let mut secrets = self.secrets.write().expect("Secrets to not be poisoned");
+        secrets.previous = Some(secrets.current);
+        secrets.current = random_secret();
+    }
+
+    /// A server cookie for `client` at the address `ip`, generated at `now` seconds since the
+    /// Unix epoch.
+    pub fn generate(&self, client: [u8; 8], ip: IpAddr, now: u32) -> [u8; 16] {
+        let mut cookie = [0; 16];
+        cookie[0] = VERSION;
+        cookie[4..8].copy_from_slice(&now.to_be_bytes());
+        let secrets = self.secrets.read().expect("Secrets to not be poisoned");
+        let hash = hash(&secrets.current, client, &cookie[..8], ip);
+        cookie[8..].copy_from_slice(&hash);
+        cookie
+    }
+
+    /// Whether `server` is a cookie this server generated for `client` at `ip` that hasn't
+    /// expired at `now`.
+    pub fn is_valid(&self, client: [u8; 8], server: &[u8], ip: IpAddr, now: u32) -> bool {
+        if server.len() != 16 || server[0] != VERSION {
+            return false;
+        }
+        let age = now.wrapping_sub(timestamp(server));
+        if age > MAX_AGE && age.wrapping_neg() > MAX_SKEW {
+            return false;
+        }
+        let secrets = self.secrets.read().expect("Secrets to not be poisoned");
+        [Some(secrets.current), secrets.previous]
+            .into_iter()
+            .flatten()
+            .any(|secret| hash(&secret, client, &server[..8], ip) == server[8..])
+    }
+
+    /// The server cookie to return to a request with the cookies `client` and `server` from
+    /// `ip`. A valid server cookie is returned as is until it is half an hour old, after which
+    /// or when it is invalid a new one is generated (RFC 9018 section 4.3).
+    pub fn reply(&self, client: [u8; 8], server: &[u8], ip: IpAddr, now: u32) -> [u8; 16] {
+        match <[u8; 16]>::try_from(server) {
+            Ok(server)
+                if self.is_valid(client, &server, ip, now)
+                    && now.wrapping_sub(timestamp(&server)) < MAX_AGE / 2 =>
+            {
+                server
+            }
+            _ => self.generate(client, ip, now),
+        }
+    }
+}
+
+/// The current time as a cookie timestamp.
+pub fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The system time to be after the Unix epoch")
+        .as_secs() as u32
+}
+
+/// The timestamp of a server cookie of at least 8 bytes.
+fn timestamp(server: &[u8]) -> u32 {
+    u32::from_be_bytes(server[4..8].try_into().unwrap())
+}
+
+fn random_secret() -> [u8; 32] {
+    let mut secret = [0; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+fn hash(secret: &[u8], client: [u8; 8], header: &[u8], ip: IpAddr) -> [u8; 8] {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC to accept keys of any length");
+    mac.update(&client);
+    mac.update(header);
+    match ip {
+        IpAddr::V4(ip) => mac.update(&ip.octets()),
+        IpAddr::V6(ip) => mac.update(&ip.octets()),
+    }
+    let mut hash = [0; 8];
+    hash.copy_from_slice(&mac.finalize().into_bytes()[..8]);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookies_expire_and_survive_one_rotation() {
+        let cookies = ServerCookies::default();
+        let client = *b"client01";
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let server = cookies.generate(client, ip, 10_000);
+
+        assert!(cookies.is_valid(client, &server, ip, 10_000 + MAX_AGE));
+        assert!(!cookies.is_valid(client, &server, ip, 10_001 + MAX_AGE));
+        assert!(cookies.is_valid(client, &server, ip, 10_000 - MAX_SKEW));
+        assert!(!cookies.is_valid(client, &server, IpAddr::from([192, 0, 2, 2]), 10_000));
+        assert!(!cookies.is_valid(*b"client02", &server, ip, 10_000));
+
+        cookies.rotate();
+        assert!(cookies.is_valid(client, &server, ip, 10_000));
+        cookies.rotate();
+        assert!(!cookies.is_valid(client, &server, ip, 10_000));
+    }
+}
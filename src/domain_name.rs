@@ -1,9 +1,14 @@
-use std::{fmt::Display, hash::Hash, sync::Arc};
+use std::{
+    fmt::Display,
+    hash::Hash,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
 
 use thiserror::Error;
 
 use crate::{
-    label::{Label, LabelParseError},
+    label::{Label, LabelParseError, MAX_LABEL_SIZE},
     proto,
 };
 
@@ -27,6 +32,8 @@ pub enum DomainNameParseError {
     NameTooLong(usize),
     #[error(transparent)]
     Label(#[from] LabelParseError),
+    #[error("Domain name has an empty label.")]
+    EmptyLabel,
 }
 
 impl DomainName {
@@ -80,6 +87,188 @@ impl DomainName {
                 .zip(other.iter())
                 .all(|(a, b)| a.eq_ignore_ascii_case(&b))
     }
+
+    /// Like [DomainName::equals], but the case of the labels has to match as well.
+    pub fn equals_exact(&self, other: &proto::DomainName<'_>) -> bool {
+        self.len() == other.len()
+            && self
+                .labels()
+                .zip(other.iter())
+                .all(|(a, b)| a.as_ref() == b)
+    }
+
+    /// The name with the case of every letter chosen at random (DNS 0x20). Resolvers echo the
+    /// question exactly as sent, which makes the case one more thing a spoofed response has to
+    /// guess.
+    pub fn with_random_case(&self, rng: &mut impl rand::Rng) -> DomainName {
+        let labels: Vec<_> = self
+            .labels()
+            .map(|label| {
+                let label: String = label
+                    .chars()
+                    .map(|c| {
+                        if rng.gen() {
+                            c.to_ascii_uppercase()
+                        } else {
+                            c.to_ascii_lowercase()
+                        }
+                    })
+                    .collect();
+                Label::new(&label)
+            })
+            .collect();
+        Self::Boxed(Arc::from(labels))
+    }
+
+    /// The name with every label in lowercase. Two names are equal exactly when their canonical
+    /// forms have the same bytes, with or without a trailing dot when parsed, and print the same
+    /// fully qualified name.
+    pub fn canonical(&self) -> DomainName {
+        let labels: Vec<_> = self
+            .labels()
+            .map(|label| Label::new(&label.to_ascii_lowercase()))
+            .collect();
+        Self::Boxed(Arc::from(labels))
+    }
+
+    /// Parse the name at `offset` of a packet, following compression pointers.
+    pub fn from_wire(bytes: &[u8], offset: usize) -> Result<DomainName, proto::LabelError> {
+        use proto::FromPacketBytes;
+
+        Ok(match proto::DomainName::parse(bytes, offset)? {
+            Some(name) => Self::from(&name),
+            None => Self::Static(0, ""),
+        })
+    }
+
+    /// The name in its uncompressed wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for label in self.labels() {
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    /// The name a reverse lookup of `ip` is for, like `4.3.2.1.in-addr.arpa` for 1.2.3.4. IPv6
+    /// addresses are written one nibble per label under ip6.arpa.
+    pub fn reverse_for(ip: IpAddr) -> DomainName {
+        let mut labels: Vec<Label> = match ip {
+            IpAddr::V4(addr) => addr
+                .octets()
+                .iter()
+                .rev()
+                .map(|octet| Label::new(&octet.to_string()))
+                .collect(),
+            IpAddr::V6(addr) => addr
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|octet| [octet & 0xf, octet >> 4])
+                .map(|nibble| Label::new(&format!("{nibble:x}")))
+                .collect(),
+        };
+        let suffix = if ip.is_ipv4() { "in-addr" } else { "ip6" };
+        labels.extend([Label::Static(suffix), Label::Static("arpa")]);
+        Self::Boxed(Arc::from(labels))
+    }
+
+    /// The address a reverse lookup name under in-addr.arpa or ip6.arpa refers to.
+    pub fn reverse_address(&self) -> Option<IpAddr> {
+        let labels: Vec<_> = self.labels().collect();
+        let (suffix, len) = match labels.len() {
+            6 => ("in-addr", 4),
+            34 => ("ip6", 32),
+            _ => return None,
+        };
+        if !labels[len].as_ref().eq_ignore_ascii_case(suffix)
+            || !labels[len + 1].as_ref().eq_ignore_ascii_case("arpa")
+        {
+            return None;
+        }
+
+        // The labels are in reverse order of the address.
+        let address = &labels[..len];
+        if len == 4 {
+            let mut octets = [0; 4];
+            for (octet, label) in octets.iter_mut().zip(address.iter().rev()) {
+                *octet = label.as_ref().parse().ok()?;
+            }
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        } else {
+            let mut octets = [0; 16];
+            for (i, label) in address.iter().rev().enumerate() {
+                if label.len() != 1 {
+                    return None;
+                }
+                let nibble = u8::from_str_radix(label.as_ref(), 16).ok()?;
+                octets[i / 2] |= nibble << (if i % 2 == 0 { 4 } else { 0 });
+            }
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+    }
+
+    /// The name without its leftmost label, or `None` for the root.
+    pub fn parent(&self) -> Option<DomainName> {
+        match self {
+            Self::Static(0, _) => None,
+            Self::Static(len, s) => {
+                let rest = s.split_once('.').map_or("", |(_, rest)| rest);
+                Some(Self::Static(len - 1, rest))
+            }
+            Self::Boxed(labels) if labels.is_empty() => None,
+            Self::Boxed(labels) => Some(Self::Boxed(Arc::from(&labels[1..]))),
+        }
+    }
+
+    /// The name qualified with `suffix`, like `www` with `example.com` becoming
+    /// `www.example.com`. Returns an error when the result is longer than 255 bytes in its wire
+    /// format.
+    pub fn append(&self, suffix: &DomainName) -> Result<DomainName, DomainNameParseError> {
+        if self.len() == 0 {
+            return Ok(suffix.clone());
+        }
+        let size = self.size_in_packet() + suffix.size_in_packet() - 1;
+        if size > MAX_NAME_SIZE {
+            return Err(DomainNameParseError::NameTooLong(size));
+        }
+        let labels: Vec<_> = self.labels().chain(suffix.labels()).collect();
+        Ok(Self::Boxed(Arc::from(labels)))
+    }
+
+    /// Check the limits of RFC 1035 a name can't be written to a packet without: labels of 1 to
+    /// 63 bytes, and at most 255 bytes in the wire format.
+    pub fn validate(&self) -> Result<(), DomainNameParseError> {
+        for label in self.labels() {
+            match label.len() {
+                0 => return Err(DomainNameParseError::EmptyLabel),
+                len if len > MAX_LABEL_SIZE => {
+                    return Err(LabelParseError::LabelTooLong(len).into())
+                }
+                _ => {}
+            }
+        }
+        match self.size_in_packet() {
+            size if size > MAX_NAME_SIZE => Err(DomainNameParseError::NameTooLong(size)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The size of the name in its uncompressed wire format.
+    pub fn size_in_packet(&self) -> usize {
+        1 + self.labels().map(|label| 1 + label.len()).sum::<usize>()
+    }
+
+    /// Whether the name is equal to or below `other` in the domain tree.
+    pub fn is_subdomain_of(&self, other: &DomainName) -> bool {
+        self.len() >= other.len()
+            && self
+                .labels()
+                .skip(self.len() - other.len())
+                .eq(other.labels())
+    }
 }
 
 impl core::str::FromStr for DomainName {
@@ -194,3 +383,129 @@ impl<'a> Iterator for DomainNameIter<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_address() {
+        assert_eq!(
+            DomainName::from_static("1.0.0.10.in-addr.arpa").reverse_address(),
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+        );
+        let ip6: DomainName =
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            ip6.reverse_address(),
+            Some(IpAddr::V6("2001:db8::1".parse().unwrap()))
+        );
+        assert_eq!(
+            DomainName::from_static("www.example.com").reverse_address(),
+            None
+        );
+    }
+
+    #[test]
+    fn reverse_names_of_addresses() {
+        let v4 = DomainName::reverse_for(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(v4.to_string(), "4.3.2.1.in-addr.arpa.");
+        let v6 = DomainName::reverse_for(IpAddr::V6("2001:db8::1".parse().unwrap()));
+        assert_eq!(
+            v6.to_string(),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        );
+        for name in [v4, v6] {
+            assert_eq!(
+                DomainName::reverse_for(name.reverse_address().unwrap()),
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn parents_up_to_the_root() {
+        for name in [
+            DomainName::from_static("www.example.com"),
+            "www.example.com".parse().unwrap(),
+        ] {
+            let parent = name.parent().unwrap();
+            assert_eq!(parent, DomainName::from_static("example.com"));
+            let parent = parent.parent().unwrap();
+            assert_eq!(parent.to_string(), "com.");
+            let root = parent.parent().unwrap();
+            assert_eq!(root.len(), 0);
+            assert!(root.parent().is_none());
+        }
+    }
+
+    #[test]
+    fn append_qualifies_relative_names() {
+        let origin: DomainName = "example.com.".parse().unwrap();
+        let name = DomainName::from_static("www").append(&origin).unwrap();
+        assert_eq!(name, DomainName::from_static("www.example.com"));
+        assert_eq!(name.len(), 3);
+
+        let root = DomainName::from_static("");
+        assert_eq!(root.append(&origin).unwrap(), origin);
+        assert_eq!(origin.append(&root).unwrap(), origin);
+
+        // Four labels of 63 bytes take 257 bytes with their length octets and the root.
+        let label = "a".repeat(63);
+        let long: DomainName = format!("{label}.{label}").parse().unwrap();
+        assert!(matches!(
+            long.append(&long),
+            Err(DomainNameParseError::NameTooLong(257))
+        ));
+        assert!(long.append(&long.parent().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn service_names() {
+        let name: DomainName = "_sip._tcp.example.com".parse().unwrap();
+        assert_eq!(name.to_bytes(), b"\x04_sip\x04_tcp\x07example\x03com\x00");
+        assert_eq!(DomainName::from_static("_sip._tcp.example.com"), name);
+        assert!("sip_.example.com".parse::<DomainName>().is_err());
+    }
+
+    #[test]
+    fn from_wire_follows_pointers() {
+        #[rustfmt::skip]
+        let packet = [
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+            3, b'w', b'w', b'w', 0xc0, 0,
+        ];
+        let name = DomainName::from_wire(&packet, 13).unwrap();
+        assert_eq!(name, DomainName::from_static("www.example.com"));
+        assert_eq!(DomainName::from_wire(&packet, 12).unwrap().len(), 0);
+        assert!(DomainName::from_wire(&packet, 14).is_err());
+    }
+
+    #[test]
+    fn canonical_names() {
+        let upper: DomainName = "A.COM".parse().unwrap();
+        let qualified: DomainName = "a.com.".parse().unwrap();
+        assert_eq!(
+            upper.canonical().to_bytes(),
+            qualified.canonical().to_bytes()
+        );
+        assert_eq!(upper.canonical().to_string(), "a.com.");
+        assert_eq!(
+            DomainName::from_static("WWW.Example.com")
+                .canonical()
+                .to_string(),
+            "www.example.com."
+        );
+    }
+
+    #[test]
+    fn subdomains() {
+        let zone = DomainName::from_static("example.com");
+        assert!(DomainName::from_static("www.example.com").is_subdomain_of(&zone));
+        assert!(zone.is_subdomain_of(&zone));
+        assert!(!DomainName::from_static("www.example.org").is_subdomain_of(&zone));
+        assert!(!zone.is_subdomain_of(&DomainName::from_static("www.example.com")));
+    }
+}
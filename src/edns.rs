@@ -0,0 +1,250 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use bytes::BufMut;
+use thiserror::Error;
+
+use crate::{
+    array_buffer::ArrayBuffer,
+    proto::{self, ClientSubnet, Type},
+};
+
+/// The UDP payload size advertised in responses to EDNS requests.
+pub const UDP_PAYLOAD_SIZE: u16 = 1024;
+
+/// The largest UDP payload a client without EDNS accepts, and the smallest any client can
+/// advertise.
+pub const MIN_UDP_PAYLOAD_SIZE: u16 = 512;
+
+/// The only EDNS version implemented.
+pub const VERSION: u8 = 0;
+
+/// Extended DNS Error info code for errors not covered by another code.
+pub const EDE_OTHER: u16 = 0;
+/// Extended DNS Error info code for a name blocked by the operator of the server.
+pub const EDE_BLOCKED: u16 = 15;
+/// Extended DNS Error info code for a question the server is not authoritative for and does not
+/// recurse for.
+pub const EDE_NOT_AUTHORITATIVE: u16 = 20;
+/// Extended DNS Error info code for a failure to reach another server.
+pub const EDE_NETWORK_ERROR: u16 = 23;
+
+/// The OPT record written in the additional section of a built packet.
+#[derive(Debug, Clone)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+    /// Pad the packet with the Padding option (RFC 7830) to a multiple of this many bytes, so
+    /// the size of an encrypted message tells less about its content.
+    pub padding: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EdnsOption {
+    ClientSubnet(ClientSubnet),
+    ExtendedError {
+        info_code: u16,
+        extra_text: Arc<str>,
+    },
+    /// This many zero bytes.
+    Padding(u16),
+    /// A client cookie and the server cookie, which is empty in a request without one.
+    Cookie {
+        client: [u8; 8],
+        server: Arc<[u8]>,
+    },
+    Unknown {
+        code: u16,
+        data: Arc<[u8]>,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ClientSubnetParseError {
+    #[error("Expected a subnet in the format address/prefix")]
+    MissingPrefix,
+    #[error(transparent)]
+    Address(#[from] std::net::AddrParseError),
+    #[error(transparent)]
+    Prefix(#[from] std::num::ParseIntError),
+    #[error("The prefix {0} is too long for the address")]
+    PrefixTooLong(u8),
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Self {
+            udp_payload_size: UDP_PAYLOAD_SIZE,
+            version: VERSION,
+            dnssec_ok: false,
+            options: Vec::new(),
+            padding: None,
+        }
+    }
+}
+
+impl Edns {
+    /// The size of the record when written into a packet.
+    pub fn size_in_packet(&self) -> usize {
+        11 + self
+            .options
+            .iter()
+            .map(|option| 4 + option.data_len())
+            .sum::<usize>()
+    }
+
+    /// Write the OPT record. The upper bits of an extended response code are passed in
+    /// `extended_response_code`.
+    pub fn write_into(&self, buffer: &mut ArrayBuffer, extended_response_code: u8) {
+        buffer.put_u8(0);
+        buffer.put_u16(Type::OPT.as_u16());
+        buffer.put_u16(self.udp_payload_size);
+        buffer.put_u8(extended_response_code);
+        buffer.put_u8(self.version);
+        buffer.put_u16(if self.dnssec_ok { 0x8000 } else { 0 });
+        buffer.put_u16((self.size_in_packet() - 11) as u16);
+        for option in &self.options {
+            option.write_into(buffer);
+        }
+    }
+}
+
+impl EdnsOption {
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::ClientSubnet(_) => proto::CLIENT_SUBNET,
+            Self::ExtendedError { .. } => proto::EXTENDED_ERROR,
+            Self::Padding(_) => proto::PADDING,
+            Self::Cookie { .. } => proto::COOKIE,
+            Self::Unknown { code, .. } => *code,
+        }
+    }
+
+    fn data_len(&self) -> usize {
+        match self {
+            Self::ClientSubnet(subnet) => 4 + (subnet.source_prefix as usize).div_ceil(8),
+            Self::ExtendedError { extra_text, .. } => 2 + extra_text.len(),
+            Self::Padding(len) => *len as usize,
+            Self::Cookie { server, .. } => 8 + server.len(),
+            Self::Unknown { data, .. } => data.len(),
+        }
+    }
+
+    fn write_into(&self, buffer: &mut ArrayBuffer) {
+        buffer.put_u16(self.code());
+        buffer.put_u16(self.data_len() as u16);
+        match self {
+            Self::ClientSubnet(subnet) => {
+                buffer.put_u16(subnet.family());
+                buffer.put_u8(subnet.source_prefix);
+                buffer.put_u8(subnet.scope_prefix);
+                let octets = match subnet.address {
+                    IpAddr::V4(addr) => addr.octets().to_vec(),
+                    IpAddr::V6(addr) => addr.octets().to_vec(),
+                };
+                buffer.put_slice(&octets[..self.data_len() - 4]);
+            }
+            Self::ExtendedError {
+                info_code,
+                extra_text,
+            } => {
+                buffer.put_u16(*info_code);
+                buffer.put_slice(extra_text.as_bytes());
+            }
+            Self::Padding(len) => buffer.put_bytes(0, *len as usize),
+            Self::Cookie { client, server } => {
+                buffer.put_slice(client);
+                buffer.put_slice(server);
+            }
+            Self::Unknown { data, .. } => buffer.put_slice(data),
+        }
+    }
+}
+
+impl<'data> From<proto::EdnsOption<'data>> for EdnsOption {
+    fn from(value: proto::EdnsOption<'data>) -> Self {
+        match value {
+            proto::EdnsOption::ClientSubnet(subnet) => Self::ClientSubnet(subnet),
+            proto::EdnsOption::ExtendedError {
+                info_code,
+                extra_text,
+            } => Self::ExtendedError {
+                info_code,
+                extra_text: Arc::from(extra_text),
+            },
+            proto::EdnsOption::Cookie(cookie) => Self::Cookie {
+                client: cookie.client,
+                server: Arc::from(cookie.server),
+            },
+            proto::EdnsOption::Unknown { code, data } => Self::Unknown {
+                code,
+                data: Arc::from(data),
+            },
+        }
+    }
+}
+
+/// Parse a client subnet in the format address/prefix, like 192.0.2.0/24. Bits of the address
+/// outside of the prefix are cleared.
+pub fn parse_client_subnet(s: &str) -> Result<ClientSubnet, ClientSubnetParseError> {
+    let (address, prefix) = s
+        .split_once('/')
+        .ok_or(ClientSubnetParseError::MissingPrefix)?;
+    let address: IpAddr = address.parse()?;
+    let prefix: u8 = prefix.parse()?;
+    let address = match address {
+        IpAddr::V4(addr) if prefix <= 32 => IpAddr::V4(Ipv4Addr::from(
+            u32::from(addr) & u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0),
+        )),
+        IpAddr::V6(addr) if prefix <= 128 => IpAddr::V6(Ipv6Addr::from(
+            u128::from(addr) & u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0),
+        )),
+        _ => return Err(ClientSubnetParseError::PrefixTooLong(prefix)),
+    };
+    Ok(ClientSubnet {
+        source_prefix: prefix,
+        scope_prefix: 0,
+        address,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{EdnsView, FromPacketBytes, Resource};
+
+    #[test]
+    fn options_round_trip() {
+        let edns = Edns {
+            options: vec![
+                EdnsOption::ClientSubnet(parse_client_subnet("192.0.2.77/20").unwrap()),
+                EdnsOption::Unknown {
+                    code: 65001,
+                    data: Arc::from(&[1, 2, 3][..]),
+                },
+            ],
+            ..Edns::default()
+        };
+        let mut buffer = ArrayBuffer::new();
+        edns.write_into(&mut buffer, 0);
+        assert_eq!(buffer.len(), edns.size_in_packet());
+
+        let resource = Resource::parse(&buffer, 0).unwrap().unwrap();
+        let view = EdnsView::from_resource(resource).unwrap();
+        let options: Vec<EdnsOption> = view
+            .options()
+            .unwrap()
+            .into_iter()
+            .map(EdnsOption::from)
+            .collect();
+        assert_eq!(options, edns.options);
+        assert_eq!(
+            view.client_subnet().unwrap().unwrap().address,
+            IpAddr::from([192, 0, 0, 0])
+        );
+    }
+}
@@ -62,6 +62,20 @@ pub struct Header {
     /// Field: RA
     pub recursion_available: bool,
 
+    /// Authentic Data - set in a response when the name server
+    /// has verified all the data in the answer and authority
+    /// sections with DNSSEC (RFC 4035).
+    ///
+    /// Field: AD
+    pub authentic_data: bool,
+
+    /// Checking Disabled - set in a query when the resolver
+    /// accepts data the name server has not verified with
+    /// DNSSEC (RFC 4035).
+    ///
+    /// Field: CD
+    pub checking_disabled: bool,
+
     /// Response code - this 4 bit field is set as part of responses.
     ///
     /// Field: RCODE
@@ -105,6 +119,8 @@ impl Header {
             truncated: false,
             recursion_desired: false,
             recursion_available: false,
+            authentic_data: false,
+            checking_disabled: false,
             response_code: ResponseCode::None,
             question_entries: 0,
             answer_entries: 0,
@@ -122,7 +138,12 @@ impl Header {
                 + (self.opcode.as_u8() << 3)
                 + (self.packet_type.as_u8() << 7),
         );
-        buffer.put_u8(self.response_code.as_u8() + ((self.recursion_available as u8) << 7));
+        buffer.put_u8(
+            self.response_code.as_u8()
+                + ((self.checking_disabled as u8) << 4)
+                + ((self.authentic_data as u8) << 5)
+                + ((self.recursion_available as u8) << 7),
+        );
         buffer.put_u16(self.question_entries);
         buffer.put_u16(self.answer_entries);
         buffer.put_u16(self.authority_entries);
@@ -144,7 +165,7 @@ impl TryFrom<&[u8]> for Header {
         if value.len() < Self::SIZE {
             return Err(HeaderParseError::EOF);
         }
-        if (value[3] & 0b1110) != 0 {
+        if (value[3] & 0x40) != 0 {
             return Err(HeaderParseError::UseOfReservedBits);
         }
         Ok(Self {
@@ -158,12 +179,15 @@ impl TryFrom<&[u8]> for Header {
                 0 => Opcode::Query,
                 1 => Opcode::InverseQuery,
                 2 => Opcode::Status,
+                4 => Opcode::Notify,
                 code => return Err(HeaderParseError::UnknownOpcode(code)),
             },
             authoritive_answer: (value[2] & 4) == 4,
             truncated: (value[2] & 2) == 2,
             recursion_desired: (value[2] & 1) == 1,
-            recursion_available: (value[3] & 0xf0) == 0xf0,
+            recursion_available: (value[3] & 0x80) == 0x80,
+            authentic_data: (value[3] & 0x20) == 0x20,
+            checking_disabled: (value[3] & 0x10) == 0x10,
             response_code: match value[3] & 0xf {
                 0 => ResponseCode::None,
                 1 => ResponseCode::FormatError,
@@ -171,6 +195,7 @@ impl TryFrom<&[u8]> for Header {
                 3 => ResponseCode::NameError,
                 4 => ResponseCode::NotImplemented,
                 5 => ResponseCode::Refused,
+                9 => ResponseCode::NotAuth,
                 code => return Err(HeaderParseError::UnknownResponseCode(code)),
             },
             question_entries: u16::from_be_bytes([value[4], value[5]]),
@@ -184,6 +209,7 @@ impl TryFrom<&[u8]> for Header {
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_serde() {
@@ -193,4 +219,112 @@ mod test {
         input_header.write_into(&mut output_bytes);
         assert_eq!(&input_bytes[..Header::SIZE], &output_bytes[..Header::SIZE]);
     }
+
+    #[test]
+    fn checking_disabled_round_trips() {
+        // A query with RD and CD set
+        let input_bytes = [4u8, 210, 1, 0x10, 0, 1, 0, 0, 0, 0, 0, 0];
+        let input_header = Header::try_from(&input_bytes[..]).unwrap();
+        assert!(input_header.checking_disabled);
+        assert!(!input_header.authentic_data);
+        assert!(!input_header.recursion_available);
+
+        let view = crate::proto::HeaderView::new(&input_bytes);
+        assert_eq!(view.checking_disabled(), Some(true));
+        assert_eq!(view.authentic_data(), Some(false));
+
+        let mut output_bytes = ArrayBuffer::with_capacity(Header::SIZE);
+        input_header.write_into(&mut output_bytes);
+        assert_eq!(&input_bytes[..], &output_bytes[..Header::SIZE]);
+    }
+
+    #[test]
+    fn z_bit_is_reserved() {
+        let input_bytes = [4u8, 210, 1, 0x40, 0, 1, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(
+            Header::try_from(&input_bytes[..]),
+            Err(HeaderParseError::UseOfReservedBits)
+        ));
+    }
+
+    fn headers() -> impl Strategy<Value = Header> {
+        let opcodes = (0u8..16).prop_map(|code| match code {
+            0 => Opcode::Query,
+            1 => Opcode::InverseQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            code => Opcode::Unknown(code),
+        });
+        // Only the response codes carried in the header, not the extended ones.
+        let response_codes = prop::sample::select(vec![
+            ResponseCode::None,
+            ResponseCode::FormatError,
+            ResponseCode::ServerFailure,
+            ResponseCode::NameError,
+            ResponseCode::NotImplemented,
+            ResponseCode::Refused,
+            ResponseCode::NotAuth,
+        ]);
+        (
+            any::<u16>(),
+            any::<bool>(),
+            opcodes,
+            any::<[bool; 6]>(),
+            response_codes,
+            any::<[u16; 4]>(),
+        )
+            .prop_map(|(id, response, opcode, flags, response_code, counts)| {
+                let [aa, tc, rd, ra, ad, cd] = flags;
+                Header {
+                    id,
+                    packet_type: if response {
+                        PacketType::Response
+                    } else {
+                        PacketType::Query
+                    },
+                    opcode,
+                    authoritive_answer: aa,
+                    truncated: tc,
+                    recursion_desired: rd,
+                    recursion_available: ra,
+                    authentic_data: ad,
+                    checking_disabled: cd,
+                    response_code,
+                    question_entries: counts[0],
+                    answer_entries: counts[1],
+                    authority_entries: counts[2],
+                    additional_entries: counts[3],
+                }
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn every_field_survives_a_round_trip(header in headers()) {
+            let mut bytes = ArrayBuffer::with_capacity(Header::SIZE);
+            header.write_into(&mut bytes);
+            let view = crate::proto::HeaderViewValidated::new(&bytes).unwrap().unwrap();
+            prop_assert_eq!(view.id(), header.id);
+            prop_assert_eq!(view.packet_type().as_u8(), header.packet_type.as_u8());
+            prop_assert_eq!(view.opcode().as_u8(), header.opcode.as_u8());
+            prop_assert_eq!(view.authoritive_answer(), header.authoritive_answer);
+            prop_assert_eq!(view.truncated(), header.truncated);
+            prop_assert_eq!(view.recursion_desired(), header.recursion_desired);
+            prop_assert_eq!(view.recursion_available(), header.recursion_available);
+            prop_assert_eq!(view.authentic_data(), header.authentic_data);
+            prop_assert_eq!(view.checking_disabled(), header.checking_disabled);
+            prop_assert_eq!(view.response_code().as_u8(), header.response_code.as_u8());
+            prop_assert_eq!(view.question_entries(), header.question_entries);
+            prop_assert_eq!(view.answer_entries(), header.answer_entries);
+            prop_assert_eq!(view.authority_entries(), header.authority_entries);
+            prop_assert_eq!(view.additional_entries(), header.additional_entries);
+
+            // Header itself only parses the opcodes it knows.
+            if let Ok(parsed) = Header::try_from(&bytes[..]) {
+                let mut again = ArrayBuffer::with_capacity(Header::SIZE);
+                parsed.write_into(&mut again);
+                prop_assert_eq!(&again[..], &bytes[..]);
+            }
+        }
+    }
 }
@@ -0,0 +1,62 @@
+//! IDs of queries sent by the server. The ID is one of the few values an off-path attacker has to
+//! guess to spoof a response, so IDs are random instead of sequential.
+
+use std::sync::Mutex;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[derive(Debug)]
+pub struct IdGenerator {
+    rng: Mutex<StdRng>,
+}
+
+impl IdGenerator {
+    /// A generator with a fixed seed, giving the same IDs every time.
+    pub fn from_seed(seed: u64) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    pub fn next(&self) -> u16 {
+        self.rng
+            .lock()
+            .expect("ID generator RNG to not be poisoned")
+            .gen()
+    }
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self {
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_spread_out() {
+        let ids = IdGenerator::from_seed(385);
+        let generated: Vec<u16> = (0..4096).map(|_| ids.next()).collect();
+
+        let sequential = generated
+            .windows(2)
+            .filter(|pair| pair[1] == pair[0].wrapping_add(1))
+            .count();
+        assert!(sequential < 4, "{sequential} sequential IDs");
+
+        // Each of the 16 ranges of the ID space is expected to get 256 of the IDs.
+        let mut buckets = [0; 16];
+        for id in &generated {
+            buckets[(id >> 12) as usize] += 1;
+        }
+        assert!(
+            buckets.iter().all(|&count| (192..320).contains(&count)),
+            "{buckets:?}"
+        );
+    }
+}
@@ -2,7 +2,7 @@ use std::{fmt::Display, hash::Hash, sync::Arc};
 
 use thiserror::Error;
 
-const MAX_LABEL_SIZE: usize = 63;
+pub(crate) const MAX_LABEL_SIZE: usize = 63;
 
 #[derive(Debug)]
 pub enum Label {
@@ -53,9 +53,11 @@ impl Label {
         let mut i = 0;
         while i < label_bytes.len() {
             match &label_bytes[i] {
-                b'A'..=b'Z' | b'a'..=b'z' => {}
-                b'0'..=b'9' if i != 0 => {}
+                // Labels may start with a digit since RFC 1123.
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => {}
                 b'-' if i != 0 && i + 1 != label_bytes.len() => {}
+                // Service labels like _dmarc and _tcp are outside LDH but common in practice.
+                b'_' if i == 0 => {}
                 c => {
                     return Err(IllegalLabelChar {
                         char: *c,
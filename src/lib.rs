@@ -0,0 +1,86 @@
+//! Parsing and building DNS messages, and the cache and resolver clients of the server binary.
+//!
+//! [proto] reads messages in place from a buffer. Without the default `std` feature only
+//! [proto] is available, which parses packets with nothing but `core` and `alloc`.
+// The builder needs `std`, so it is only documented and tested with it.
+#![cfg_attr(
+    feature = "std",
+    doc = r#"
+[DNSPacketBuilder] writes messages from owned values such as [DomainName], [Question] and
+[ResourceData] into an [ArrayBuffer]:
+
+```
+use dns_starter_rust::{
+    proto::{FromPacketBytes, Packet, QClass, QType},
+    ArrayBuffer, DNSPacketBuilder, Question,
+};
+
+let name = "codecrafters.io".parse().unwrap();
+let mut buffer = ArrayBuffer::new();
+DNSPacketBuilder::query(1234)
+    .add_question(Question::new(QType::A, QClass::IN, name))
+    .build_into(&mut buffer);
+
+let packet = Packet::parse(&buffer, 0).unwrap().unwrap();
+assert_eq!(packet.header().id(), 1234);
+let question = packet.questions().next().unwrap();
+assert_eq!(question.name().to_string(), "codecrafters.io.");
+assert!(question.q_type() == QType::A);
+```
+"#
+)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod array_buffer;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod cookie;
+#[cfg(feature = "std")]
+pub mod domain_name;
+#[cfg(feature = "std")]
+pub mod edns;
+#[cfg(feature = "std")]
+pub mod header;
+#[cfg(feature = "std")]
+pub mod id;
+#[cfg(feature = "std")]
+pub mod label;
+#[cfg(feature = "std")]
+pub mod packet;
+#[cfg(feature = "std")]
+pub mod presentation;
+pub mod proto;
+#[cfg(feature = "std")]
+pub mod question;
+#[cfg(feature = "std")]
+pub mod resource;
+#[cfg(feature = "std")]
+pub mod retransmit;
+#[cfg(feature = "std")]
+pub mod round_robin;
+#[cfg(feature = "std")]
+pub mod secondary;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod suffix_matcher;
+#[cfg(feature = "std")]
+pub mod tsig;
+#[cfg(feature = "std")]
+pub mod types;
+#[cfg(feature = "std")]
+pub mod upstream;
+
+#[cfg(feature = "std")]
+pub use crate::{
+    array_buffer::ArrayBuffer,
+    cache::EVCache,
+    domain_name::DomainName,
+    packet::DNSPacketBuilder,
+    question::Question,
+    resource::{Resource, ResourceData},
+};
@@ -1,6 +1,7 @@
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 use tokio::{
     net::{TcpListener, TcpStream, UdpSocket},
@@ -16,18 +17,26 @@ use packet::DNSPacketBuilder;
 use proto::{FromPacketBytes, Opcode};
 
 use crate::cache::EVCache;
-use crate::{domain_name::DomainName, proto::ResponseCode, question::Question, resource::Resource};
-
-mod array_buffer;
-mod cache;
-mod domain_name;
-mod header;
-mod label;
-mod packet;
-mod proto;
-mod question;
-mod resource;
-mod types;
+use crate::cookie::ServerCookies;
+use crate::retransmit::Retransmits;
+use crate::round_robin::{RoundRobin, Shuffle};
+use crate::secondary::Secondary;
+use crate::stats::QueryStats;
+use crate::suffix_matcher::{MatchKind, SuffixMatcher};
+use crate::tsig::TsigKey;
+use crate::upstream::{ForwardError, Upstream, UpstreamSpec, Upstreams};
+use crate::{
+    domain_name::DomainName,
+    edns::{Edns, EdnsOption},
+    proto::ResponseCode,
+    question::Question,
+    resource::{Resource, ResourceData},
+};
+
+use dns_starter_rust::{
+    array_buffer, cache, cookie, domain_name, edns, packet, presentation, proto, question,
+    resource, retransmit, round_robin, secondary, stats, suffix_matcher, tsig, upstream,
+};
 
 #[cfg(feature = "code_crafters")]
 const DEFAULT_PORT: u16 = 2053;
@@ -36,12 +45,33 @@ const DEFAULT_PORT: u16 = 53;
 
 const DEFAULT_UPSTREAM: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53);
 
+/// The most CNAME records followed from the name of a question in the cache.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// The TTL of the records of answers rewritten with an address.
+const REWRITE_TTL: u32 = 60;
+
+/// How long a server cookie secret is used before it is replaced by a new one.
+const COOKIE_SECRET_LIFETIME: Duration = Duration::from_secs(3600);
+
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// The resolver to use
-    #[arg(short, long, default_value_t = DEFAULT_UPSTREAM)]
-    resolver: SocketAddr,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The resolver to use, as an address or a URL with the scheme udp, tcp, tls or https. For
+    /// example tls://9.9.9.9#dns.quad9.net or https://dns.google/dns-query
+    #[arg(short, long, default_value_t = UpstreamSpec::Udp(DEFAULT_UPSTREAM))]
+    resolver: UpstreamSpec,
+
+    /// A resolver to use in order while the ones before it are down
+    #[arg(long = "backup-resolver")]
+    backup_resolvers: Vec<UpstreamSpec>,
+
+    /// Seconds between health checks of the resolver and backup resolvers
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    health_check_interval: u64,
 
     /// More output
     #[arg(short, long, default_value_t = false)]
@@ -54,12 +84,536 @@ struct Args {
     /// The port to listen on
     #[arg(short, long, default_value_t = DEFAULT_PORT)]
     port: u16,
+
+    /// A key used to verify signed requests and sign their responses, in the format
+    /// name:base64-secret. The key must use HMAC-SHA256.
+    #[arg(long = "tsig-key")]
+    tsig_keys: Vec<TsigKey>,
+
+    /// How questions are answered from the cache and the resolver
+    #[arg(long, value_enum, default_value_t = ForwardPolicy::ForwardFirst)]
+    policy: ForwardPolicy,
+
+    /// Answer only from the cache and local zones. Questions that would be forwarded to a
+    /// resolver are refused
+    #[arg(long, default_value_t = false)]
+    no_recursion: bool,
+
+    /// A zone this server answers for locally. Used by the forward-only policy
+    #[arg(long = "local-zone")]
+    local_zones: Vec<DomainName>,
+
+    /// Forward questions for names under a suffix to another resolver, in the format
+    /// suffix=resolver. For example corp.example.com=10.0.0.53:53
+    #[arg(long = "forward")]
+    forward_rules: Vec<ForwardRule>,
+
+    /// The client subnet sent to the resolver when a request does not include one, for example
+    /// 192.0.2.0/24
+    #[arg(long, value_parser = edns::parse_client_subnet)]
+    client_subnet: Option<proto::ClientSubnet>,
+
+    /// Answer AAAA questions for names with only A records with addresses in a NAT64 prefix
+    /// (DNS64). The prefix must be a /96 and defaults to the well-known prefix 64:ff9b::/96
+    #[arg(long, num_args = 0..=1, default_missing_value = "64:ff9b::/96")]
+    dns64: Option<Nat64Prefix>,
+
+    /// Pad queries to tls:// and https:// resolvers to a multiple of this many bytes with the
+    /// EDNS Padding option (RFC 7830), so their size tells less about the name queried. RFC 8467
+    /// recommends 128
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    edns_padding: Option<u16>,
+
+    /// Forward questions with the exact bytes the client sent, instead of encoding them again
+    /// with a randomized case (DNS 0x20)
+    #[arg(long, default_value_t = false)]
+    verbatim_questions: bool,
+
+    /// Forward the questions of a request to a resolver in one query. Falls back to a query per
+    /// question when the resolver does not answer every question. Combined questions are always
+    /// encoded again, so this can't be used with --verbatim-questions
+    #[arg(long, default_value_t = false, conflicts_with = "verbatim_questions")]
+    combine_questions: bool,
+
+    /// Pass on the authority and additional records of resolver responses, like the name
+    /// servers of the zone and their addresses. They are left out of responses they don't fit in
+    #[arg(long, default_value_t = false)]
+    forward_authority: bool,
+
+    /// Milliseconds to wait for a response from the resolver
+    #[arg(long, default_value_t = 2000)]
+    upstream_timeout: u64,
+
+    /// The most queries sent to resolvers at once. Requests beyond it wait for one to finish, and
+    /// are answered with SERVFAIL if none does within the upstream timeout
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u16).range(1..))]
+    max_inflight: u16,
+
+    /// A zone to transfer from its primary at startup and answer for, in the format
+    /// zone@primary. For example example.com@192.0.2.1:53
+    #[arg(long = "secondary")]
+    secondaries: Vec<Secondary>,
+
+    /// Refuse questions for names under this name
+    #[arg(long = "block")]
+    blocked: Vec<DomainName>,
+
+    /// Rewrite the answers to questions for a name, in the format name=action. The action is
+    /// nxdomain, an IPv4 address to answer A questions with, or passthru to keep the answers of a
+    /// name matched by a wider rule. A name starting with *. matches the names below it. For
+    /// example *.tracker.example.com=nxdomain
+    #[arg(long = "rewrite")]
+    rewrite_rules: Vec<RewriteRule>,
+
+    /// Randomly permute answers with multiple records instead of rotating them
+    #[arg(long, default_value_t = false)]
+    shuffle_answers: bool,
+
+    /// The seed of the random permutations of --shuffle-answers, for reproducible responses
+    #[arg(long)]
+    shuffle_seed: Option<u64>,
+
+    /// The number of tasks handling UDP requests
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    workers: u16,
+
+    /// Set the TC bit and leave out every record of UDP responses longer than this many bytes,
+    /// regardless of EDNS. For testing how clients fall back to TCP
+    #[arg(long)]
+    force_truncate_over: Option<usize>,
+
+    /// Send at most this many answer records in a UDP response, setting the TC bit when any are
+    /// left out so the client retries over TCP, where every record is sent
+    #[arg(long)]
+    max_answer_records: Option<usize>,
+
+    /// Milliseconds to wait before sending every response. For testing how clients time out and
+    /// retry
+    #[arg(long, hide = true)]
+    response_delay: Option<u64>,
+
+    /// Serve every answer record with this TTL, whatever the TTL of the cached or forwarded
+    /// record. For testing how clients cache and expire answers
+    #[arg(long, hide = true)]
+    answer_ttl_override: Option<u32>,
+
+    /// A file the cache is saved to when the server closes, and loaded from when it starts
+    #[arg(long)]
+    cache_file: Option<std::path::PathBuf>,
+
+    /// Parse and log every request without responding to it or forwarding it
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Log a hex dump of every request that fails to parse, at debug level
+    #[arg(long, default_value_t = false)]
+    log_bad_packets: bool,
+
+    /// The number of UDP requests that can wait for a handler before the listener stops reading
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u16).range(1..))]
+    queue_depth: u16,
+
+    /// The number of OS threads running the server. More than one uses a multi-threaded runtime
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    threads: u16,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Print the messages in a file in presentation format instead of running the server. The
+    /// file holds one message in hex per line, or messages each prefixed by their length as a
+    /// big endian u16 like over TCP
+    Parse { file: std::path::PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ForwardPolicy {
+    /// Answer from the cache and forward questions it has no records for.
+    ForwardFirst,
+    /// Answer questions within the local zones only from the cache, and forward everything
+    /// else without looking in the cache.
+    ForwardOnly,
+}
+
+/// The /96 prefix IPv4 addresses are embedded in for DNS64 (RFC 6052 section 2.2).
+#[derive(Debug, Clone, Copy)]
+struct Nat64Prefix(Ipv6Addr);
+
+#[derive(Debug, Error)]
+enum Nat64PrefixParseError {
+    #[error("Expected a prefix in the format address/96")]
+    MissingLength,
+    #[error("Only a prefix length of 96 is supported")]
+    Length,
+    #[error("The last 32 bits of the prefix must be zero")]
+    HostBits,
+    #[error(transparent)]
+    Address(#[from] std::net::AddrParseError),
+}
+
+impl std::str::FromStr for Nat64Prefix {
+    type Err = Nat64PrefixParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, length) = s
+            .split_once('/')
+            .ok_or(Nat64PrefixParseError::MissingLength)?;
+        if length != "96" {
+            return Err(Nat64PrefixParseError::Length);
+        }
+        let address: Ipv6Addr = address.parse()?;
+        if u128::from(address) as u32 != 0 {
+            return Err(Nat64PrefixParseError::HostBits);
+        }
+        Ok(Self(address))
+    }
+}
+
+impl Nat64Prefix {
+    fn embed(&self, addr: Ipv4Addr) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.0) | u32::from(addr) as u128)
+    }
+}
+
+/// Forward questions for names under `suffix` to `resolver` instead of the default resolver.
+#[derive(Debug, Clone)]
+struct ForwardRule {
+    suffix: DomainName,
+    resolver: UpstreamSpec,
+}
+
+#[derive(Debug, Error)]
+enum ForwardRuleParseError {
+    #[error("Expected a rule in the format suffix=resolver")]
+    MissingResolver,
+    #[error(transparent)]
+    Suffix(#[from] domain_name::DomainNameParseError),
+    #[error(transparent)]
+    Resolver(#[from] upstream::UpstreamSpecParseError),
+}
+
+impl std::str::FromStr for ForwardRule {
+    type Err = ForwardRuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (suffix, resolver) = s
+            .split_once('=')
+            .ok_or(ForwardRuleParseError::MissingResolver)?;
+        let suffix = suffix.strip_prefix("*.").unwrap_or(suffix);
+        Ok(Self {
+            suffix: suffix.parse()?,
+            resolver: resolver.parse()?,
+        })
+    }
+}
+
+/// Rewrite the answers to questions for `name`, or for the names below it with `wildcard`, like
+/// the QNAME triggers of a response policy zone.
+#[derive(Debug, Clone)]
+struct RewriteRule {
+    name: DomainName,
+    wildcard: bool,
+    action: RewriteAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RewriteAction {
+    /// Answer that the name does not exist.
+    NameError,
+    /// Answer A questions with the address, and other questions with no records.
+    A(Ipv4Addr),
+    /// Keep the answers, overriding rules for a wider name.
+    Passthru,
+}
+
+#[derive(Debug, Error)]
+enum RewriteRuleParseError {
+    #[error("Expected a rule in the format name=action")]
+    MissingAction,
+    #[error("Expected nxdomain, passthru or an IPv4 address as the action")]
+    Action,
+    #[error(transparent)]
+    Name(#[from] domain_name::DomainNameParseError),
+}
+
+impl RewriteRule {
+    fn matches(&self, name: &DomainName) -> bool {
+        match self.wildcard {
+            true => name.len() > self.name.len() && name.is_subdomain_of(&self.name),
+            false => *name == self.name,
+        }
+    }
+}
+
+impl std::str::FromStr for RewriteRule {
+    type Err = RewriteRuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, action) = s
+            .split_once('=')
+            .ok_or(RewriteRuleParseError::MissingAction)?;
+        let (name, wildcard) = match name.strip_prefix("*.") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let action = match action.to_ascii_lowercase().as_str() {
+            "nxdomain" => RewriteAction::NameError,
+            "passthru" => RewriteAction::Passthru,
+            addr => RewriteAction::A(addr.parse().map_err(|_| RewriteRuleParseError::Action)?),
+        };
+        Ok(Self {
+            name: name.parse::<DomainName>()?.canonical(),
+            wildcard,
+            action,
+        })
+    }
+}
+
+/// Settings shared by every request handler.
+struct Settings {
+    resolver: UpstreamSpec,
+    /// Used in order in place of the resolver while it is down.
+    backup_resolvers: Vec<UpstreamSpec>,
+    tsig_keys: Vec<TsigKey>,
+    policy: ForwardPolicy,
+    /// Questions are forwarded to resolvers. Without recursion they are refused instead.
+    recursion: bool,
+    local_zones: Vec<DomainName>,
+    forward_rules: Vec<ForwardRule>,
+    client_subnet: Option<proto::ClientSubnet>,
+    dns64: Option<Nat64Prefix>,
+    /// The block size to pad queries to encrypted resolvers to.
+    edns_padding: Option<u16>,
+    /// Questions are forwarded as the client encoded them.
+    verbatim_questions: bool,
+    /// The questions of a request are forwarded in one query.
+    combine_questions: bool,
+    /// The authority and additional records of resolvers are passed on to clients.
+    forward_authority: bool,
+    upstream_timeout: Duration,
+    /// A permit is held for every forward to a resolver, bounding the sockets open at once.
+    inflight: tokio::sync::Semaphore,
+    blocked: SuffixMatcher,
+    rewrite_rules: Vec<RewriteRule>,
+    round_robin: RoundRobin,
+    /// Replaces the round-robin rotation when set.
+    shuffle: Option<Shuffle>,
+    upstreams: Upstreams,
+    /// UDP requests being answered, so retransmissions of them are not forwarded again.
+    retransmits: Arc<Retransmits>,
+    secondaries: Vec<Secondary>,
+    force_truncate_over: Option<usize>,
+    /// The most answer records sent in a UDP response.
+    max_answer_records: Option<usize>,
+    /// Every response is held back this long, without holding up other requests.
+    response_delay: Option<Duration>,
+    /// Replaces the TTL of every answer record.
+    answer_ttl_override: Option<u32>,
+    stats: QueryStats,
+    /// Requests are logged and left unanswered.
+    dry_run: bool,
+    /// The bytes of requests that fail to parse are logged.
+    log_bad_packets: bool,
+    /// Server cookies for clients that send a client cookie.
+    cookies: ServerCookies,
+}
+
+impl Settings {
+    /// The local zones, with the secondary zones that have been transferred.
+    fn zones(&self) -> impl Iterator<Item = &DomainName> {
+        let secondaries = self.secondaries.iter().filter(|s| s.is_loaded());
+        self.local_zones
+            .iter()
+            .chain(secondaries.map(|secondary| &secondary.zone))
+    }
+
+    fn is_local(&self, name: &DomainName) -> bool {
+        self.zones().any(|zone| name.is_subdomain_of(zone))
+    }
+
+    /// The innermost local zone `name` is in.
+    fn zone_for(&self, name: &DomainName) -> Option<&DomainName> {
+        self.zones()
+            .filter(|zone| name.is_subdomain_of(zone))
+            .max_by_key(|zone| zone.len())
+    }
+
+    fn is_blocked(&self, name: &DomainName) -> bool {
+        self.blocked.matches(name) != MatchKind::None
+    }
+
+    /// The action of the most specific rewrite rule matching `name`. A rule for the name itself
+    /// is more specific than a wildcard rule for its parent.
+    fn rewrite_for(&self, name: &DomainName) -> Option<RewriteAction> {
+        let name = name.canonical();
+        self.rewrite_rules
+            .iter()
+            .filter(|rule| rule.matches(&name))
+            .max_by_key(|rule| (rule.name.len(), !rule.wildcard))
+            .map(|rule| rule.action)
+    }
+
+    /// The resolver to forward questions for `name` to. The rule with the longest matching
+    /// suffix is used, falling back to the first default or backup resolver that is up.
+    fn resolver_for(&self, name: &DomainName) -> &UpstreamSpec {
+        self.forward_rules
+            .iter()
+            .filter(|rule| name.is_subdomain_of(&rule.suffix))
+            .max_by_key(|rule| rule.suffix.len())
+            .map(|rule| &rule.resolver)
+            .unwrap_or_else(|| {
+                self.default_resolvers()
+                    .find(|resolver| self.upstreams.is_healthy(resolver))
+                    .unwrap_or(&self.resolver)
+            })
+    }
+
+    fn default_resolvers(&self) -> impl Iterator<Item = &UpstreamSpec> + '_ {
+        std::iter::once(&self.resolver).chain(&self.backup_resolvers)
+    }
+}
+
+/// Probe the default and backup resolvers every `interval`, so forwards skip the ones that are
+/// down.
+async fn check_health(settings: Arc<Settings>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        for resolver in settings.default_resolvers() {
+            settings
+                .upstreams
+                .probe(resolver, settings.upstream_timeout)
+                .await;
+        }
+    }
+}
+
+/// Replace the server cookie secret every `interval`. Cookies of the replaced secret stay valid
+/// for another `interval`.
+async fn rotate_cookie_secret(settings: Arc<Settings>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    // The first tick completes immediately.
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        settings.cookies.rotate();
+    }
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
+fn main() {
     let args = Args::parse();
+    if let Some(Command::Parse { file }) = &args.command {
+        match std::fs::read(file) {
+            Ok(contents) => print!("{}", parse_messages(&contents)),
+            Err(e) => eprintln!("Failed to read {}: {e}", file.display()),
+        }
+        return;
+    }
+
+    let runtime = match build_runtime(args.threads as usize) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start the runtime: {e}");
+            return;
+        }
+    };
+    runtime.block_on(run(args));
+}
+
+#[derive(Debug, Error, PartialEq)]
+enum MessageFileError {
+    #[error("Invalid hex on line {0}")]
+    Hex(usize),
+    #[error("Message {0} is cut off by the end of the file")]
+    CutOff(usize),
+}
+
+/// Split the contents of a message file into messages. Text made of only hex digits and
+/// whitespace has one message per line; anything else is read as length prefixed messages.
+fn read_messages(contents: &[u8]) -> Vec<Result<Vec<u8>, MessageFileError>> {
+    if contents
+        .iter()
+        .all(|b| b.is_ascii_hexdigit() || b.is_ascii_whitespace())
+    {
+        let text = String::from_utf8_lossy(contents);
+        return text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                let digits: Vec<u8> = line.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+                if !digits.len().is_multiple_of(2) {
+                    return Err(MessageFileError::Hex(index + 1));
+                }
+                digits
+                    .chunks(2)
+                    .map(|pair| {
+                        std::str::from_utf8(pair)
+                            .ok()
+                            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                            .ok_or(MessageFileError::Hex(index + 1))
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
+    let mut messages = Vec::new();
+    let mut rest = contents;
+    while !rest.is_empty() {
+        let number = messages.len() + 1;
+        let Some(len) = rest
+            .get(..2)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+        else {
+            messages.push(Err(MessageFileError::CutOff(number)));
+            break;
+        };
+        let Some(message) = rest.get(2..2 + len) else {
+            messages.push(Err(MessageFileError::CutOff(number)));
+            break;
+        };
+        messages.push(Ok(message.to_vec()));
+        rest = &rest[2 + len..];
+    }
+    messages
+}
+
+/// Every message in a message file in presentation format, or why it could not be parsed.
+fn parse_messages(contents: &[u8]) -> String {
+    let mut out = String::new();
+    for (index, message) in read_messages(contents).into_iter().enumerate() {
+        out.push_str(&format!(";; Message {}\n", index + 1));
+        let parsed = message.map_err(|e| e.to_string()).and_then(|message| {
+            match proto::Packet::parse(&message, 0) {
+                Ok(Some(packet)) => Ok(presentation::render(&packet)),
+                Ok(None) => Err("Message is shorter than a header".to_owned()),
+                Err(e) => Err(e.to_string()),
+            }
+        });
+        match parsed {
+            Ok(rendered) => out.push_str(&rendered),
+            Err(e) => out.push_str(&format!(";; Failed to parse message: {e}\n")),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn build_runtime(threads: usize) -> std::io::Result<tokio::runtime::Runtime> {
+    if threads > 1 {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(threads)
+            .enable_all()
+            .build()
+    } else {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+    }
+}
 
+async fn run(args: Args) {
     // Setup logging
     let max_level = if args.vvv {
         Level::DEBUG
@@ -79,8 +633,86 @@ async fn main() {
     #[cfg(feature = "code_crafters")]
     setup_for_code_crafters(&cache).await;
 
+    if let Some(path) = &args.cache_file {
+        match std::fs::read(path) {
+            Ok(snapshot) => {
+                match cache::snapshot::load(&cache, &snapshot, SystemTime::now()).await {
+                    Ok(records) => tracing::info!(records, "Loaded the cache"),
+                    Err(e) => tracing::error!(message = %e, "Failed to load the cache"),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::error!(message = %e, "Failed to read the cache file"),
+        }
+    }
+
+    // Secondary zones are answered like local zones once transferred. A zone that fails to
+    // transfer now is retried by its refresh task.
+    let upstream_timeout = Duration::from_millis(args.upstream_timeout);
+    for secondary in &args.secondaries {
+        let soa = match secondary.load(&cache, upstream_timeout).await {
+            Ok(soa) => Some(soa),
+            Err(e) => {
+                tracing::error!(zone = %secondary.zone, message = %e, "Failed to transfer zone");
+                None
+            }
+        };
+        tokio::spawn(
+            secondary
+                .clone()
+                .refresh(cache.clone(), upstream_timeout, soa),
+        );
+    }
+
+    let settings = Arc::new(Settings {
+        resolver: args.resolver,
+        backup_resolvers: args.backup_resolvers,
+        tsig_keys: args.tsig_keys,
+        policy: args.policy,
+        recursion: !args.no_recursion,
+        local_zones: args.local_zones,
+        forward_rules: args.forward_rules,
+        client_subnet: args.client_subnet,
+        dns64: args.dns64,
+        edns_padding: args.edns_padding,
+        verbatim_questions: args.verbatim_questions,
+        combine_questions: args.combine_questions,
+        forward_authority: args.forward_authority,
+        upstream_timeout,
+        inflight: tokio::sync::Semaphore::new(args.max_inflight as usize),
+        blocked: args.blocked.into_iter().collect(),
+        rewrite_rules: args.rewrite_rules,
+        round_robin: RoundRobin::default(),
+        retransmits: Arc::default(),
+        shuffle: args.shuffle_answers.then(|| {
+            args.shuffle_seed
+                .map(Shuffle::from_seed)
+                .unwrap_or_default()
+        }),
+        upstreams: Upstreams::default(),
+        secondaries: args.secondaries,
+        force_truncate_over: args.force_truncate_over,
+        max_answer_records: args.max_answer_records,
+        response_delay: args.response_delay.map(Duration::from_millis),
+        answer_ttl_override: args.answer_ttl_override,
+        stats: QueryStats::default(),
+        dry_run: args.dry_run,
+        log_bad_packets: args.log_bad_packets,
+        cookies: ServerCookies::default(),
+    });
+    tokio::spawn(rotate_cookie_secret(
+        Arc::clone(&settings),
+        COOKIE_SECRET_LIFETIME,
+    ));
+
     // UDP Listener
-    let (mut udp, rx) = match UDPStateSender::new(args.port, args.resolver).await {
+    let (udp, rx) = match UDPStateSender::new(
+        args.port,
+        Arc::clone(&settings),
+        args.queue_depth as usize,
+    )
+    .await
+    {
         Ok(res) => res,
         Err(_) => {
             tracing::error!(
@@ -92,7 +724,14 @@ async fn main() {
         }
     };
     tracing::info!(transport = "UDP", port = args.port, "Listening");
-    spawn_udp_handler(cache.clone(), rx);
+    // Without backups there is nothing to switch to, so the resolver is not probed.
+    if !settings.backup_resolvers.is_empty() {
+        tokio::spawn(check_health(
+            Arc::clone(&settings),
+            Duration::from_secs(args.health_check_interval),
+        ));
+    }
+    spawn_udp_handlers(&cache, rx, args.workers as usize);
 
     // TCP Listener
     let tcp = match TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], args.port))).await {
@@ -109,41 +748,104 @@ async fn main() {
     tracing::info!(transport = "TCP", port = args.port, "Listening");
 
     // Handle exit signal
-    let (sigint_sender, mut sigint_reciever) = tokio::sync::broadcast::channel(1);
-    tokio::spawn(async move {
+    let shutdown = async {
         tokio::signal::ctrl_c()
             .await
             .expect("Failed to listen on Ctrl+c");
-        sigint_sender.send(())
-    });
+    };
+
+    serve(
+        udp,
+        tcp,
+        cache.clone(),
+        Arc::clone(&settings),
+        args.workers as usize,
+        shutdown,
+    )
+    .await;
+
+    for (q_type, count) in settings.stats.counts() {
+        tracing::info!(r#type = ?q_type, count, "Questions answered");
+    }
+
+    if let Some(path) = &args.cache_file {
+        let snapshot = cache::snapshot::encode(&cache, SystemTime::now());
+        // Written next to the file first, so a crash while writing leaves the old snapshot.
+        let partial = path.with_extension("partial");
+        if let Err(e) =
+            std::fs::write(&partial, snapshot).and_then(|_| std::fs::rename(&partial, path))
+        {
+            tracing::error!(message = %e, "Failed to save the cache");
+        }
+    }
 
-    let resolver_shim = Arc::new(args.resolver);
+    tracing::info!("Closing server");
+}
 
-    let mut udp_buffer = [0; 1024];
+/// Accept requests on the listeners until `shutdown` completes.
+async fn serve(
+    mut udp: UDPStateSender,
+    tcp: TcpListener,
+    cache: EVCache,
+    settings: Arc<Settings>,
+    workers: usize,
+    shutdown: impl std::future::Future<Output = ()>,
+) {
+    tokio::pin!(shutdown);
+    let mut udp_buffer = [0; edns::UDP_PAYLOAD_SIZE as usize];
     loop {
         tokio::select! {
             (size, source) = udp.recv(&mut udp_buffer) => {
-                udp.enqueue(&udp_buffer[..size], source, |rx| spawn_udp_handler(cache.clone(), rx)).await;
+                udp.enqueue(&udp_buffer[..size], source, |rx| spawn_udp_handlers(&cache, rx, workers)).await;
             }
             Ok((socket, source)) = tcp.accept() => {
-                tokio::spawn(handle_tcp(cache.clone(), Arc::clone(&resolver_shim), socket, source));
+                tokio::spawn(handle_tcp(cache.clone(), Arc::clone(&settings), socket, source));
             }
-            _ = sigint_reciever.recv() => break,
+            _ = &mut shutdown => break,
         }
     }
+}
 
-    tracing::info!("Closing server");
+/// Spawn `workers` tasks taking requests from the same queue.
+fn spawn_udp_handlers(cache: &EVCache, rx: mpsc::Receiver<UDPState>, workers: usize) {
+    let rx = Arc::new(tokio::sync::Mutex::new(rx));
+    for _ in 0..workers {
+        spawn_udp_handler(cache.clone(), Arc::clone(&rx));
+    }
 }
 
 // NOTE: An owned EVCache is needed to have its own read handle on the cache data.
-fn spawn_udp_handler(cache: EVCache, mut rx: mpsc::Receiver<UDPState>) {
+fn spawn_udp_handler(cache: EVCache, rx: Arc<tokio::sync::Mutex<mpsc::Receiver<UDPState>>>) {
     tokio::spawn(async move {
-        let mut response = ArrayBuffer::new().with_max_len(512);
-        while let Some(mut state) = rx.recv().await {
+        let mut response = ArrayBuffer::new();
+        loop {
+            // The lock is only held while waiting for the next request.
+            let Some(mut state) = rx.lock().await.recv().await else {
+                break;
+            };
             response.clear();
             async {
-                handle_dns_packet(&mut state.buffer, &mut response, &state.forwarding, &cache).await;
-                if response.len() > 0 {
+                let Some(pending) = state
+                    .settings
+                    .retransmits
+                    .begin(state.source, &state.buffer)
+                else {
+                    tracing::debug!("Dropping retransmission of a request being answered");
+                    return;
+                };
+                handle_dns_packet(
+                    &mut state.buffer,
+                    &mut response,
+                    Transport::Udp,
+                    state.source,
+                    &state.settings,
+                    &cache,
+                )
+                .await;
+                if response.len() == 0 {
+                    return;
+                }
+                let Some(delay) = state.settings.response_delay else {
                     if let Err(_) = state
                         .socket
                         .send_to(response.as_slice(), state.source)
@@ -151,7 +853,17 @@ fn spawn_udp_handler(cache: EVCache, mut rx: mpsc::Receiver<UDPState>) {
                     {
                         tracing::error!("Failed to send back to source");
                     }
-                }
+                    return;
+                };
+                // The handler moves on to the next request while the response waits.
+                let response = response.as_slice().to_vec();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if state.socket.send_to(&response, state.source).await.is_err() {
+                        tracing::error!("Failed to send back to source");
+                    }
+                    drop(pending);
+                });
             }
             .instrument(tracing::info_span!("dns_request", source = %state.source))
             .await
@@ -160,7 +872,7 @@ fn spawn_udp_handler(cache: EVCache, mut rx: mpsc::Receiver<UDPState>) {
 }
 
 // NOTE: An owned EVCache is needed to have its own read handle on the cache data.
-async fn handle_tcp(cache: EVCache, forwarding_addr: Arc<SocketAddr>, mut stream: TcpStream, source: SocketAddr) {
+async fn handle_tcp(cache: EVCache, settings: Arc<Settings>, mut stream: TcpStream, source: SocketAddr) {
     use tokio::io::AsyncReadExt;
 
     let mut request = ArrayBuffer::new().with_max_len(u16::MAX as usize);
@@ -203,7 +915,33 @@ async fn handle_tcp(cache: EVCache, forwarding_addr: Arc<SocketAddr>, mut stream
         };
 
         response.clear();
-        handle_dns_packet(&mut request, &mut response, &forwarding_addr, &cache).await;
+        let transfer = handle_dns_packet(
+            &mut request,
+            &mut response,
+            Transport::Tcp,
+            source,
+            &settings,
+            &cache,
+        )
+        .await;
+        if response.len() == 0 && transfer.is_none() {
+            continue;
+        }
+        if let Some(delay) = settings.response_delay {
+            tokio::time::sleep(delay).await;
+        }
+        // Zone transfers are streamed as several messages instead of a single response.
+        if let Some(transfer) = transfer {
+            let packet = proto::Packet::parse(&request, 0)
+                .ok()
+                .flatten()
+                .expect("Zone transfer request to have been parsed");
+            if let Err(e) = transfer_zone(&mut stream, &packet, transfer, &cache).await {
+                tracing::error!(transport = "TCP", message = %e, "Error sending zone transfer");
+                break;
+            }
+            continue;
+        }
         if let Err(_) = stream.write_all(&(response.len() as u16).to_be_bytes()).await {
             tracing::error!(transport="TCP","Error sending response to client");
             break;
@@ -244,39 +982,55 @@ async fn setup_for_code_crafters(cache: &EVCache) {
         .unwrap();
 }
 
+/// The transport a request was received over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
 struct UDPState {
     socket: Arc<UdpSocket>,
-    forwarding: Arc<SocketAddr>,
+    settings: Arc<Settings>,
     buffer: ArrayBuffer,
     source: SocketAddr,
 }
 
 struct UDPStateSender {
     socket: Arc<UdpSocket>,
-    forwarding: Arc<SocketAddr>,
+    settings: Arc<Settings>,
     sender: mpsc::Sender<UDPState>,
     port: u16,
+    queue_depth: usize,
 }
 
 impl UDPStateSender {
     pub async fn new(
         port: u16,
-        forwarding_addr: SocketAddr,
+        settings: Arc<Settings>,
+        queue_depth: usize,
     ) -> Result<(Self, mpsc::Receiver<UDPState>), std::io::Error> {
-        let (tx, rx) = mpsc::channel(1000);
+        let (tx, rx) = mpsc::channel(queue_depth);
         let udp_socket = Arc::new(UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], port))).await?);
 
         Ok((
             Self {
                 socket: udp_socket,
-                forwarding: Arc::new(forwarding_addr),
+                settings,
                 sender: tx,
                 port,
+                queue_depth,
             },
             rx,
         ))
     }
 
+    /// The address the socket is bound to, for tests binding to an ephemeral port.
+    #[cfg(test)]
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
     pub async fn recv(&mut self, buffer: &mut [u8]) -> (usize, SocketAddr) {
         let mut retried = false;
         loop {
@@ -305,13 +1059,13 @@ impl UDPStateSender {
             .sender
             .send(UDPState {
                 socket: Arc::clone(&self.socket),
-                forwarding: Arc::clone(&self.forwarding),
+                settings: Arc::clone(&self.settings),
                 buffer: buf.into(),
                 source,
             })
             .await;
         if let Err(mpsc::error::SendError(state)) = res {
-            let (tx, rx) = mpsc::channel(1000);
+            let (tx, rx) = mpsc::channel(self.queue_depth);
             self.sender = tx;
             respawn_udp_handler(rx);
             self.sender
@@ -337,19 +1091,55 @@ fn configure_tracing(max_level: Level) {
         .init();
 }
 
+/// A zone transfer (AXFR) accepted by [handle_dns_packet], to be streamed to the TCP client by
+/// [transfer_zone].
+struct ZoneTransfer {
+    soa: Resource,
+    signed: Option<tsig::Signed>,
+}
+
+/// Answer the request in `buf` into `response`. An accepted zone transfer is returned instead of
+/// being answered, as it doesn't fit in a single response.
 async fn handle_dns_packet(
     buf: &mut ArrayBuffer,
     response: &mut ArrayBuffer,
-    forwarding_addr: &SocketAddr,
+    transport: Transport,
+    source: SocketAddr,
+    settings: &Settings,
     cache: &EVCache,
-) {
+) -> Option<ZoneTransfer> {
     if cfg!(debug_assertions) {
         //print_buffer("Input", &buf);
     }
 
-    let packet = match proto::Packet::parse(&buf, 0) {
+    let packet = proto::Packet::parse(&buf, 0);
+    // A UDP response must fit in the payload size of the client, or be truncated to make it
+    // retry over TCP.
+    if transport == Transport::Udp {
+        let max_len = match &packet {
+            Ok(Some(packet)) => max_udp_response_size(packet),
+            _ => edns::MIN_UDP_PAYLOAD_SIZE,
+        };
+        response.clear_with_max_len(Some(max_len as usize));
+    }
+
+    // The span of the request holds the source address.
+    if settings.log_bad_packets && packet.is_err() {
+        tracing::debug!("Malformed packet:\n{buf:b}");
+    }
+
+    let packet = match packet {
         Ok(Some(packet)) => packet,
-        Ok(None) => return,
+        Ok(None) => return None,
+        // Without a whole header there is no ID to answer to, so the datagram is dropped.
+        Err(e) if buf.len() < proto::HeaderView::SIZE => {
+            tracing::warn!(error = "Dropping packet shorter than a header", message = ?e);
+            return None;
+        }
+        Err(e) if settings.dry_run => {
+            tracing::warn!(error = "Failed to parse packet", message = ?e);
+            return None;
+        }
         Err(e) => {
             tracing::error!(error = "Failed to parse packet", message = ?e);
             response.clear();
@@ -359,136 +1149,1098 @@ async fn handle_dns_packet(
             )
             .build_into(response);
 
-            return;
+            return None;
         }
     };
 
-    match packet.header().opcode() {
-        Opcode::Query => {
-            let mut builder = DNSPacketBuilder::respond(&packet, ResponseCode::None);
-            let mut unknown_questions = Vec::new();
-            for q in packet.questions() {
-                tracing::info!(section = "question", domain_name = %q.name(), r#type = ?q.q_type(), class = ?q.q_class());
-                let name = (&q.name()).into();
-                match cache.get((&name, q.q_type())) {
-                    Some(records) if !records.is_empty() => {
-                        builder = records.iter().fold(
-                            builder.add_question(Question::new(
-                                q.q_type().clone(),
-                                q.q_class().clone(),
-                                name.clone(),
-                            )),
-                            |b, record| b.add_answer(Resource(name.clone(), record.clone())),
-                        )
-                    }
-                    Some(_) => {
-                        builder = builder.add_question(Question::new(
-                            q.q_type().clone(),
-                            q.q_class().clone(),
-                            name.clone(),
-                        ))
-                    }
-                    None => unknown_questions.push(q),
-                }
-            }
-            if !unknown_questions.is_empty() {
-                builder =
-                    match forward_request(&forwarding_addr, &packet, &unknown_questions, builder)
-                        .await
-                    {
-                        Ok(b) => b,
-                        Err(e) => {
-                            tracing::error!(error = "Failed to parse packet", message = ?e);
-                            DNSPacketBuilder::respond(
-                                &packet,
-                                match e {
-                                    ForwardError::IO(_) => ResponseCode::Refused,
-                                    ForwardError::ParsePacket(_) => ResponseCode::ServerFailure,
-                                },
-                            )
-                            .build_into(response);
+    if settings.dry_run {
+        log_packet(&packet);
+        return None;
+    }
 
-                            return;
-                        }
-                    };
-            }
-            builder.build_into(response);
+    let signed = match tsig::verify(&settings.tsig_keys, &packet, buf, tsig::now()) {
+        Ok(signed) => signed,
+        Err(e) => {
+            tracing::warn!(error = "Failed to verify transaction signature", message = %e);
+            DNSPacketBuilder::respond(&packet, ResponseCode::NotAuth).build_into(response);
+            return None;
+        }
+    };
 
-            if cfg!(debug_assertions) {
-                //print_buffer("Output", &response);
+    if transport == Transport::Tcp {
+        if let Some(soa) = zone_transfer_soa(&packet, settings, cache) {
+            settings.stats.record(proto::QType::AXFR);
+            // With keys configured, only their holders may transfer the zones.
+            if signed.is_none() && !settings.tsig_keys.is_empty() {
+                tracing::info!(zone = %soa.0, "Refusing unsigned zone transfer");
+                DNSPacketBuilder::respond(&packet, ResponseCode::Refused).build_into(response);
+                return None;
             }
-        }
-        _ => {
-            DNSPacketBuilder::respond(&packet, ResponseCode::NotImplemented).build_into(response);
+            return Some(ZoneTransfer { soa, signed });
         }
     }
-}
 
-#[derive(Debug, Error)]
-enum ForwardError {
-    #[error(transparent)]
-    ParsePacket(#[from] proto::PacketError),
-    #[error(transparent)]
-    IO(#[from] std::io::Error),
-}
-
-async fn forward_request<'a, 'b>(
-    resolver: &SocketAddr,
-    packet: &proto::Packet<'a>,
-    questions: &[proto::Question<'a>],
-    mut builder: DNSPacketBuilder,
-) -> Result<DNSPacketBuilder, ForwardError>
-where
-    'a: 'b,
-{
-    let mut request = ArrayBuffer::new().with_max_len((u16::MAX - 2) as usize);
-    let mut response = [0; 1024];
-    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
-    socket.connect(resolver).await?;
+    let cookie = match packet.edns().map_or(Ok(None), |edns| edns.cookie()) {
+        Ok(cookie) => cookie.map(|proto::Cookie { client, server }| {
+            let server = settings
+                .cookies
+                .reply(client, server, source.ip(), cookie::now());
+            (client, server)
+        }),
+        Err(e) => {
+            tracing::warn!(error = "Malformed cookie", message = %e);
+            DNSPacketBuilder::respond(&packet, ResponseCode::FormatError).build_into(response);
+            return None;
+        }
+    };
 
-    for q in questions {
-        let name: DomainName = (&q.name()).into();
-        request.clear();
-        DNSPacketBuilder::query(packet.header().id())
-            .add_question(Question::new(
-                q.q_type().clone(),
-                q.q_class().clone(),
-                name.clone(),
-            ))
-            .build_into(&mut request);
+    answer_packet(&packet, response, transport, cookie, settings, cache).await;
 
-        tracing::info!(%name, "Forwarding question");
+    if transport == Transport::Udp
+        && settings
+            .force_truncate_over
+            .is_some_and(|max_len| response.len() > max_len)
+    {
+        force_truncate(&packet, response);
+    }
 
-        //print_buffer("Forward Request", &request);
+    if let Some(signed) = signed {
+        if tsig::sign(response, &signed.key, Some(&signed.mac), tsig::now()).is_none() {
+            tracing::warn!("No room left in the response for the transaction signature");
+        }
+    }
+    None
+}
 
-        socket.send(&request).await?;
-        let resp_size = socket.recv(&mut response).await?;
+/// Log the header and every entry of the sections of `packet`, one line per entry like dig.
+fn log_packet(packet: &proto::Packet<'_>) {
+    let header = packet.header();
+    tracing::info!(
+        id = header.id(),
+        opcode = ?header.opcode(),
+        r#type = ?header.packet_type(),
+        rcode = ?header.response_code(),
+        rd = header.recursion_desired(),
+        cd = header.checking_disabled(),
+        "Received packet"
+    );
+    for question in packet.questions() {
+        tracing::info!(section = "question", "{question}");
+    }
+    for answer in packet.answers() {
+        tracing::info!(section = "answer", "{answer}");
+    }
+    for authority in packet.authority() {
+        tracing::info!(section = "authority", "{authority}");
+    }
+    for additional in packet.additional() {
+        tracing::info!(section = "additional", "{additional}");
+    }
+}
 
-        //print_buffer("Forward Response", &ArrayBuffer::from(&response[..resp_size]));
+/// Replace the response to `packet` with one without records and with the TC bit set, keeping
+/// the questions, response code, AA bit and cookies of the response.
+fn force_truncate(packet: &proto::Packet<'_>, response: &mut ArrayBuffer) {
+    let builder = match proto::Packet::parse(response.as_slice(), 0) {
+        Ok(Some(full)) => {
+            let builder = full.questions().fold(
+                DNSPacketBuilder::respond(packet, full.header().response_code())
+                    .with_authoritative_answer(full.header().authoritive_answer())
+                    .with_truncated(true),
+                |builder, question| builder.add_question(question.into()),
+            );
+            match full.edns().and_then(|edns| edns.cookie().ok().flatten()) {
+                Some(proto::Cookie { client, server }) => builder.with_cookie(client, server),
+                None => builder,
+            }
+        }
+        _ => return,
+    };
+    response.clear();
+    builder.build_into(response);
+}
 
-        let Some(res_packet) = proto::Packet::parse(&response[..resp_size], 0)? else {
-            tracing::warn!("Returned no packet repr from response");
-            continue;
-        };
+/// The largest UDP response `packet` accepts: the payload size it advertises with EDNS, capped
+/// at the one advertised by this server, or 512 bytes without EDNS (RFC 6891).
+fn max_udp_response_size(packet: &proto::Packet<'_>) -> u16 {
+    packet.edns().map_or(edns::MIN_UDP_PAYLOAD_SIZE, |edns| {
+        edns.udp_payload_size()
+            .clamp(edns::MIN_UDP_PAYLOAD_SIZE, edns::UDP_PAYLOAD_SIZE)
+    })
+}
 
-        assert_eq!(packet.header().id(), res_packet.header().id());
+async fn answer_packet(
+    packet: &proto::Packet<'_>,
+    response: &mut ArrayBuffer,
+    transport: Transport,
+    cookie: Option<([u8; 8], [u8; 16])>,
+    settings: &Settings,
+    cache: &EVCache,
+) {
+    // Every response to a request with a client cookie carries a server cookie.
+    let respond = |code| {
+        let builder = DNSPacketBuilder::respond(packet, code);
+        match &cookie {
+            Some((client, server)) => builder.with_cookie(*client, server),
+            None => builder,
+        }
+    };
+    if let Some(edns) = packet.edns() {
+        if edns.version() > edns::VERSION {
+            tracing::info!(version = edns.version(), "Unsupported EDNS version");
+            respond(ResponseCode::BadVersion).build_into(response);
+            return;
+        }
+    }
+
+    match packet.header().opcode() {
+        Opcode::Query => {
+            for q in packet.questions() {
+                settings.stats.record(q.q_type());
+            }
+            if let Some(q) = packet
+                .questions()
+                .find(|q| settings.is_blocked(&(&q.name()).into()))
+            {
+                tracing::info!(domain_name = %q.name(), "Refusing blocked name");
+                for q in packet.questions() {
+                    tracing::info!(
+                        section = "question",
+                        domain_name = %q.name(),
+                        r#type = ?q.q_type(),
+                        class = ?q.q_class(),
+                        answer_source = AnswerSource::Blocked.as_str(),
+                        answers = 0,
+                    );
+                }
+                respond(ResponseCode::Refused)
+                    .with_extended_error(edns::EDE_BLOCKED, "Blocked")
+                    .build_into(response);
+                return;
+            }
+
+            // Pass on the subnet of the client so the resolver can give geo-aware answers.
+            let client_subnet = packet
+                .edns()
+                .and_then(|edns| edns.client_subnet().ok().flatten())
+                .or(settings.client_subnet);
+
+            // Every question is resolved before the response is built, so the questions are
+            // echoed and their answers grouped in the order of the request.
+            let mut answers = Vec::new();
+            let mut sources = Vec::new();
+            for q in packet.questions() {
+                let name = (&q.name()).into();
+                let local = settings.is_local(&name);
+                if let Some(referral) = referral(&name, settings, cache) {
+                    answers.push((Question::new(q.q_type(), q.q_class(), name), referral));
+                    sources.push(AnswerSource::CacheHit);
+                    continue;
+                }
+                let cached = match settings.policy {
+                    ForwardPolicy::ForwardOnly if !local => None,
+                    _ => {
+                        let class = q.q_class();
+                        let subnet = client_subnet.as_ref();
+                        lookup(&name, q.q_type(), class, subnet, settings, cache)
+                            .or_else(|| {
+                                lookup_dns64(&name, q.q_type(), class, subnet, settings, cache)
+                            })
+                            .map(|records| (records, AnswerSource::CacheHit))
+                            .or_else(|| {
+                                let records = synthesize_ptr(&name, q.q_type(), cache)?;
+                                let records = records
+                                    .iter()
+                                    .map(|record| Resource(name.clone(), Arc::clone(record)))
+                                    .collect();
+                                Some((records, AnswerSource::Synthesized))
+                            })
+                    }
+                };
+                let source = match cached {
+                    Some((_, source)) => source,
+                    None => AnswerSource::CacheHit,
+                };
+                let answer = match cached.map(|(records, _)| records) {
+                    Some(records) => Answer::Records(records),
+                    // The name exists but has no records of the type (NODATA). The SOA of the
+                    // zone tells resolvers how long to cache that (RFC 2308).
+                    None if local && cache.get(&name).is_some_and(|r| !r.is_empty()) => {
+                        Answer::NoData(
+                            settings
+                                .zone_for(&name)
+                                .and_then(|zone| zone_soa(zone, cache)),
+                        )
+                    }
+                    None if local && settings.policy == ForwardPolicy::ForwardOnly => {
+                        Answer::NameError
+                    }
+                    None => Answer::Forward,
+                };
+                let source = match answer {
+                    Answer::Forward => AnswerSource::Forwarded,
+                    _ => source,
+                };
+                answers.push((Question::new(q.q_type(), q.q_class(), name), answer));
+                sources.push(source);
+            }
+            // Only answers from local zones are authoritative, not those from resolvers.
+            let authoritative = !answers.is_empty()
+                && answers.iter().all(|(question, answer)| {
+                    !matches!(answer, Answer::Forward | Answer::Referral { .. })
+                        && settings.is_local(question.name())
+                });
+            // A client that doesn't desire recursion only gets what is known here, without
+            // forwarding (RFC 1034 section 4.3.1).
+            let recursion_desired = packet.header().recursion_desired();
+            if !settings.recursion
+                && recursion_desired
+                && answers
+                    .iter()
+                    .any(|(_, answer)| matches!(answer, Answer::Forward))
+            {
+                tracing::info!("Refusing question that needs recursion");
+                log_questions(&answers, &sources);
+                respond(ResponseCode::Refused)
+                    .with_extended_error(edns::EDE_NOT_AUTHORITATIVE, "Recursion is disabled")
+                    .build_into(response);
+                return;
+            }
+            // The authority and additional records of the responses of resolvers.
+            let mut authority = Vec::new();
+            let mut additional = Vec::new();
+            // Group the questions by the resolver they are forwarded to.
+            let mut forwards: Vec<(&UpstreamSpec, Vec<usize>)> = Vec::new();
+            for (index, (question, answer)) in answers.iter().enumerate() {
+                if !matches!(answer, Answer::Forward) {
+                    continue;
+                }
+                if !recursion_desired {
+                    sources[index] = AnswerSource::CacheMiss;
+                    continue;
+                }
+                let resolver = settings.resolver_for(question.name());
+                match forwards.iter_mut().find(|(addr, _)| *addr == resolver) {
+                    Some((_, indices)) => indices.push(index),
+                    None => forwards.push((resolver, vec![index])),
+                }
+            }
+            for (resolver, indices) in forwards {
+                let padding = settings.edns_padding.filter(|_| resolver.is_encrypted());
+                let upstream_edns = (client_subnet.is_some() || padding.is_some()).then(|| Edns {
+                    options: client_subnet
+                        .into_iter()
+                        .map(EdnsOption::ClientSubnet)
+                        .collect(),
+                    padding,
+                    ..Edns::default()
+                });
+                let questions: Vec<_> = indices
+                    .iter()
+                    .map(|&index| answers[index].0.clone())
+                    .collect();
+                let permit =
+                    tokio::time::timeout(settings.upstream_timeout, settings.inflight.acquire())
+                        .await;
+                let Ok(Ok(_permit)) = permit else {
+                    tracing::warn!(%resolver, "Too many queries in flight to forward");
+                    log_questions(&answers, &sources);
+                    respond(ResponseCode::ServerFailure)
+                        .with_extended_error(edns::EDE_OTHER, "Too many queries in flight")
+                        .build_into(response);
+                    return;
+                };
+                let forwarded = match settings.upstreams.get(resolver) {
+                    Ok(upstream) => {
+                        let forwarded = forward_request(
+                            &*upstream,
+                            packet,
+                            &questions,
+                            upstream_edns.as_ref(),
+                            ForwardMode {
+                                verbatim: settings.verbatim_questions,
+                                combined: settings.combine_questions,
+                            },
+                            settings.upstream_timeout,
+                        )
+                        .await;
+                        match (forwarded, settings.dns64) {
+                            (Ok(mut forwarded), Some(prefix)) => {
+                                forwarded.answers = forward_dns64(
+                                    prefix,
+                                    &*upstream,
+                                    packet,
+                                    &questions,
+                                    forwarded.answers,
+                                    upstream_edns.as_ref(),
+                                    settings.upstream_timeout,
+                                )
+                                .await;
+                                Ok(forwarded)
+                            }
+                            (forwarded, _) => forwarded,
+                        }
+                    }
+                    Err(e) => Err(ForwardError::Connect(e)),
+                };
+                match forwarded {
+                    Ok(forwarded) => {
+                        for (index, answer) in indices.into_iter().zip(forwarded.answers) {
+                            answers[index].1 = answer;
+                        }
+                        if settings.forward_authority {
+                            authority.extend(forwarded.authority);
+                            additional.extend(forwarded.additional);
+                        }
+                    }
+                    Err(e) => {
+                        // Failing to get an answer is a server failure; REFUSED is kept for
+                        // policy (blocked names, disabled recursion).
+                        let (info_code, extra_text) = match &e {
+                            ForwardError::TimedOut => (
+                                edns::EDE_NETWORK_ERROR,
+                                "The resolver did not respond in time",
+                            ),
+                            ForwardError::Connect(_)
+                            | ForwardError::IO(_)
+                            | ForwardError::HttpStatus(_) => {
+                                (edns::EDE_NETWORK_ERROR, "Failed to reach the resolver")
+                            }
+                            ForwardError::ParsePacket(_)
+                            | ForwardError::QuestionMismatch
+                            | ForwardError::IdMismatch => {
+                                (edns::EDE_OTHER, "Invalid response from the resolver")
+                            }
+                        };
+                        tracing::error!(%resolver, error = extra_text, message = %e);
+                        log_questions(&answers, &sources);
+                        respond(ResponseCode::ServerFailure)
+                            .with_extended_error(info_code, extra_text)
+                            .build_into(response);
+
+                        return;
+                    }
+                };
+            }
+            // The records of resolvers don't belong with answers made up here.
+            if rewrite_answers(&mut answers, &mut sources, settings) {
+                authority.clear();
+                additional.clear();
+            }
+            log_questions(&answers, &sources);
+            let truncated = match (transport, settings.max_answer_records) {
+                (Transport::Udp, Some(max)) => cap_answers(&mut answers, max),
+                _ => false,
+            };
+
+            let mut builder = respond(ResponseCode::None)
+                .with_authoritative_answer(authoritative)
+                .with_recursion_available(settings.recursion)
+                .with_truncated(truncated);
+            for (question, answer) in answers {
+                builder = builder.add_question(question);
+                builder = match answer {
+                    Answer::Records(records) => records
+                        .into_iter()
+                        .map(|Resource(owner, data)| match settings.answer_ttl_override {
+                            Some(ttl) => Resource(owner, Arc::new(data.with_ttl(ttl))),
+                            None => Resource(owner, data),
+                        })
+                        .fold(builder, |b, record| b.add_answer(record)),
+                    Answer::NoData(Some(soa)) => builder.add_authority(soa),
+                    Answer::Referral { ns, glue } => {
+                        let builder = ns.into_iter().fold(builder, |b, r| b.add_authority(r));
+                        glue.into_iter().fold(builder, |b, r| b.add_additional(r))
+                    }
+                    Answer::NoData(None) | Answer::Forward => builder,
+                    Answer::NameError => builder.with_response_code(ResponseCode::NameError),
+                };
+            }
+            let builder = authority
+                .into_iter()
+                .fold(builder, |b, r| b.add_optional_authority(r));
+            let builder = additional
+                .into_iter()
+                .fold(builder, |b, r| b.add_additional(r));
+            builder.build_into(response);
+
+            if cfg!(debug_assertions) {
+                //print_buffer("Output", &response);
+            }
+        }
+        // The primary of a secondary zone tells it changed. Anyone could send this, but it only
+        // makes the serial be checked with the primary before the refresh interval.
+        Opcode::Notify => {
+            let Some(q) = packet.questions().next() else {
+                respond(ResponseCode::FormatError).build_into(response);
+                return;
+            };
+            let name = DomainName::from(&q.name());
+            let code = match settings.secondaries.iter().find(|s| s.zone == name) {
+                Some(secondary) => {
+                    secondary.notify();
+                    ResponseCode::None
+                }
+                None => ResponseCode::Refused,
+            };
+            respond(code)
+                .add_question(Question::new(q.q_type(), q.q_class(), name))
+                .build_into(response);
+        }
+        _ => {
+            respond(ResponseCode::NotImplemented).build_into(response);
+        }
+    }
+}
+
+/// The SOA record at the apex of a zone.
+fn zone_soa(zone: &DomainName, cache: &EVCache) -> Option<Resource> {
+    let records = cache.get((zone, proto::Type::SOA))?;
+    let soa = records.first()?;
+    Some(Resource(zone.clone(), Arc::clone(soa)))
+}
+
+/// A referral to the child zone `name` is in, when NS records in the cache delegate a part of
+/// the local zone of `name` (RFC 1034 section 4.3.2). The zone cut closest to the apex is the one
+/// referred to, as that is where resolution leaves the local zone.
+fn referral(name: &DomainName, settings: &Settings, cache: &EVCache) -> Option<Answer> {
+    let zone = settings.zone_for(name)?;
+    let mut cut = None;
+    let mut owner = name.clone();
+    while owner.len() > zone.len() {
+        if let Some(records) = cache.get((&owner, proto::Type::NS)) {
+            cut = Some((owner.clone(), records));
+        }
+        owner = owner.parent()?;
+    }
+    let (cut, records) = cut?;
+
+    let mut glue = Vec::new();
+    for record in records.iter() {
+        let Ok(target) = DomainName::from_wire(record.data().as_ref(), 0) else {
+            continue;
+        };
+        // Name servers outside of the child zone are found without the help of the parent.
+        if !target.is_subdomain_of(&cut) {
+            continue;
+        }
+        for typ in [proto::Type::A, proto::Type::AAAA] {
+            if let Some(addresses) = cache.get((&target, typ)) {
+                glue.extend(
+                    addresses
+                        .iter()
+                        .map(|address| Resource(target.clone(), Arc::clone(address))),
+                );
+            }
+        }
+    }
+    let ns = records
+        .iter()
+        .map(|record| Resource(cut.clone(), Arc::clone(record)))
+        .collect();
+    Some(Answer::Referral { ns, glue })
+}
+
+/// The SOA of the local zone `packet` requests a transfer (AXFR) of.
+fn zone_transfer_soa(
+    packet: &proto::Packet<'_>,
+    settings: &Settings,
+    cache: &EVCache,
+) -> Option<Resource> {
+    let mut questions = packet.questions();
+    let question = questions.next()?;
+    if questions.next().is_some() || question.q_type() != proto::QType::AXFR {
+        return None;
+    }
+    let name = DomainName::from(&question.name());
+    let zone = settings.zones().find(|zone| **zone == name)?;
+    zone_soa(zone, cache)
+}
+
+/// Stream the records of the zone of `soa` to a TCP client, batching as many records into each
+/// length-prefixed message as fit. The transfer starts and ends with the SOA (RFC 5936). A
+/// signed request gets every message signed, each covering the MAC of the one before it.
+async fn transfer_zone(
+    stream: &mut (impl tokio::io::AsyncWrite + Unpin),
+    packet: &proto::Packet<'_>,
+    transfer: ZoneTransfer,
+    cache: &EVCache,
+) -> std::io::Result<()> {
+    let ZoneTransfer { soa, mut signed } = transfer;
+    let question = packet
+        .questions()
+        .next()
+        .expect("AXFR request to have a question");
+    let question = Question::new(question.q_type(), question.q_class(), soa.0.clone());
+    // Names are counted uncompressed, so a batch always fits in a message.
+    let signature_size = signed
+        .as_ref()
+        .map_or(0, |signed| tsig::record_len(&signed.key));
+    let empty_size =
+        proto::HeaderView::SIZE + question.name().size_in_packet() + 4 + signature_size;
+    let records = cache.zone(&soa.0).chain(std::iter::once(soa));
+
+    let mut batch = Vec::new();
+    let mut size = empty_size;
+    let mut first = true;
+    for record in records {
+        let record_size = record.0.size_in_packet() + 10 + record.1.data().as_ref().len();
+        if !batch.is_empty() && size + record_size > u16::MAX as usize {
+            write_transfer_message(stream, packet, &question, &mut batch, &mut signed, first)
+                .await?;
+            size = empty_size;
+            first = false;
+        }
+        size += record_size;
+        batch.push(record);
+    }
+    write_transfer_message(stream, packet, &question, &mut batch, &mut signed, first).await
+}
+
+/// Write the records of `batch` as a single message of a zone transfer, leaving it empty. The
+/// message is signed when the request was, replacing the MAC of `signed` with its own so the
+/// next message covers it.
+async fn write_transfer_message(
+    stream: &mut (impl tokio::io::AsyncWrite + Unpin),
+    packet: &proto::Packet<'_>,
+    question: &Question,
+    batch: &mut Vec<Resource>,
+    signed: &mut Option<tsig::Signed>,
+    first: bool,
+) -> std::io::Result<()> {
+    let builder =
+        DNSPacketBuilder::respond(packet, ResponseCode::None).add_question(question.clone());
+    let builder = batch
+        .drain(..)
+        .fold(builder, |builder, record| builder.add_answer(record));
+    let mut response = ArrayBuffer::new().with_max_len(u16::MAX as usize);
+    builder.build_into(&mut response);
+    if let Some(signed) = signed {
+        let now = tsig::now();
+        let mac = if first {
+            tsig::sign(&mut response, &signed.key, Some(&signed.mac), now)
+        } else {
+            tsig::sign_subsequent(&mut response, &signed.key, &signed.mac, now)
+        };
+        match mac {
+            Some(mac) => signed.mac = mac,
+            None => tracing::warn!("No room left in the response for the transaction signature"),
+        }
+    }
+    stream
+        .write_all(&(response.len() as u16).to_be_bytes())
+        .await?;
+    stream.write_all(response.as_slice()).await
+}
+
+/// The records of type `q_type` owned by `name` in the cache. Without any, a CNAME record of
+/// `name` is followed to the records of its target, which are returned after the CNAME records
+/// of the chain (RFC 1034 section 4.3.2). A chain that ends without records is not answered from
+/// the cache, so the resolver gets to follow it.
+fn lookup(
+    name: &DomainName,
+    q_type: proto::QType,
+    class: proto::QClass,
+    subnet: Option<&proto::ClientSubnet>,
+    settings: &Settings,
+    cache: &EVCache,
+) -> Option<Vec<Resource>> {
+    let mut answers = Vec::new();
+    let mut owner = name.clone();
+    for _ in 0..=MAX_CNAME_CHAIN {
+        if let Some(mut records) = cache.get_scoped((&owner, q_type), class, subnet) {
+            match &settings.shuffle {
+                Some(shuffle) => shuffle.shuffle(&mut records),
+                None => settings.round_robin.rotate(&owner, q_type, &mut records),
+            }
+            answers.extend(
+                records
+                    .iter()
+                    .map(|record| Resource(owner.clone(), Arc::clone(record))),
+            );
+            return Some(answers);
+        }
+        if matches!(q_type, proto::QType::CNAME | proto::QType::ALL) {
+            return None;
+        }
+        let cname = cache
+            .get_scoped((&owner, proto::Type::CNAME), class, subnet)?
+            .first()
+            .cloned()?;
+        let target = DomainName::from_wire(cname.data().as_ref(), 0).ok()?;
+        answers.push(Resource(owner, cname));
+        owner = target;
+    }
+    None
+}
+
+/// Answer an AAAA question for a name with only A records in the cache with AAAA records
+/// synthesized from them (RFC 6147 section 5.1).
+fn lookup_dns64(
+    name: &DomainName,
+    q_type: proto::QType,
+    class: proto::QClass,
+    subnet: Option<&proto::ClientSubnet>,
+    settings: &Settings,
+    cache: &EVCache,
+) -> Option<Vec<Resource>> {
+    let prefix = settings.dns64.filter(|_| q_type == proto::QType::AAAA)?;
+    let a = lookup(name, proto::QType::A, class, subnet, settings, cache)?;
+    let records = synthesize_aaaa(prefix, a);
+    has_aaaa(&records).then_some(records)
+}
+
+/// Replace the answers the resolver gave without AAAA records to AAAA `questions` with AAAA
+/// records synthesized from the A records of the same names at the resolver.
+async fn forward_dns64(
+    prefix: Nat64Prefix,
+    upstream: &dyn Upstream,
+    packet: &proto::Packet<'_>,
+    questions: &[Question],
+    mut answers: Vec<Answer>,
+    edns: Option<&Edns>,
+    timeout: Duration,
+) -> Vec<Answer> {
+    let missing: Vec<_> = questions
+        .iter()
+        .zip(&answers)
+        .enumerate()
+        .filter(|(_, (q, answer))| {
+            *q.q_type() == proto::QType::AAAA
+                && matches!(answer, Answer::Records(records) if !has_aaaa(records))
+        })
+        .map(|(index, _)| index)
+        .collect();
+    if missing.is_empty() {
+        return answers;
+    }
+    let a_questions: Vec<_> = missing
+        .iter()
+        .map(|&index| {
+            let q = &questions[index];
+            Question::new(proto::QType::A, *q.q_class(), q.name().clone())
+        })
+        .collect();
+    let mode = ForwardMode::default();
+    match forward_request(upstream, packet, &a_questions, edns, mode, timeout).await {
+        Ok(forwarded) => {
+            for (index, answer) in missing.into_iter().zip(forwarded.answers) {
+                let Answer::Records(records) = answer else {
+                    continue;
+                };
+                let records = synthesize_aaaa(prefix, records);
+                if has_aaaa(&records) {
+                    answers[index] = Answer::Records(records);
+                }
+            }
+        }
+        // The answers without AAAA records are still right, just not as useful.
+        Err(e) => tracing::warn!(message = ?e, "Failed to get A records for DNS64"),
+    }
+    answers
+}
+
+/// The CNAME records of `records`, and an AAAA record in `prefix` for each A record.
+fn synthesize_aaaa(prefix: Nat64Prefix, records: Vec<Resource>) -> Vec<Resource> {
+    records
+        .into_iter()
+        .filter_map(|Resource(owner, data)| match *data.typ() {
+            proto::Type::CNAME => Some(Resource(owner, data)),
+            proto::Type::A => {
+                let octets = <[u8; 4]>::try_from(data.data().as_ref()).ok()?;
+                let aaaa = ResourceData::AAAA {
+                    ttl: data.ttl(),
+                    addr: prefix.embed(Ipv4Addr::from(octets)),
+                };
+                Some(Resource(owner, Arc::new(aaaa)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn has_aaaa(records: &[Resource]) -> bool {
+    records
+        .iter()
+        .any(|record| *record.1.typ() == proto::Type::AAAA)
+}
+
+/// Answer a PTR question for an address that cached A or AAAA records point to, so reverse
+/// lookups of known names work without reverse zone entries.
+fn synthesize_ptr(
+    name: &DomainName,
+    q_type: proto::QType,
+    cache: &EVCache,
+) -> Option<Box<[Arc<ResourceData>]>> {
+    if q_type != proto::QType::PTR {
+        return None;
+    }
+    let records: Box<[_]> = cache
+        .find_by_address(name.reverse_address()?)
+        .into_iter()
+        .map(|(owner, record)| {
+            Arc::new(ResourceData::Generic {
+                typ: proto::Type::PTR,
+                class: proto::Class::IN,
+                ttl: record.ttl(),
+                data: Arc::from(owner.to_bytes()),
+            })
+        })
+        .collect();
+    (!records.is_empty()).then_some(records)
+}
+
+/// Replace the answers to questions matching a rewrite rule with the answer of its action.
+/// Returns whether any answer was replaced.
+fn rewrite_answers(
+    answers: &mut [(Question, Answer)],
+    sources: &mut [AnswerSource],
+    settings: &Settings,
+) -> bool {
+    let mut rewritten = false;
+    for ((question, answer), source) in answers.iter_mut().zip(sources) {
+        let Some(action) = settings.rewrite_for(question.name()) else {
+            continue;
+        };
+        tracing::info!(domain_name = %question.name(), ?action, "Rewriting answer");
+        *answer = match action {
+            RewriteAction::NameError => Answer::NameError,
+            RewriteAction::A(addr) => match question.q_type() {
+                proto::QType::A | proto::QType::ALL => Answer::Records(vec![Resource(
+                    question.name().clone(),
+                    Arc::new(ResourceData::A {
+                        ttl: REWRITE_TTL,
+                        addr,
+                    }),
+                )]),
+                _ => Answer::NoData(None),
+            },
+            RewriteAction::Passthru => continue,
+        };
+        *source = AnswerSource::Synthesized;
+        rewritten = true;
+    }
+    rewritten
+}
+
+/// Leave out the answer records past the first `max` of all questions. Returns whether any were
+/// left out.
+fn cap_answers(answers: &mut [(Question, Answer)], max: usize) -> bool {
+    let mut left = max;
+    let mut truncated = false;
+    for (_, answer) in answers {
+        if let Answer::Records(records) = answer {
+            truncated |= records.len() > left;
+            records.truncate(left);
+            left -= records.len();
+        }
+    }
+    truncated
+}
+
+/// Log every question of a request with where its answer came from and how many records it has.
+fn log_questions(answers: &[(Question, Answer)], sources: &[AnswerSource]) {
+    for ((question, answer), source) in answers.iter().zip(sources) {
+        let records = match answer {
+            Answer::Records(records) => records.len(),
+            _ => 0,
+        };
+        tracing::info!(
+            section = "question",
+            domain_name = %question.name(),
+            r#type = ?question.q_type(),
+            class = ?question.q_class(),
+            answer_source = source.as_str(),
+            answers = records,
+        );
+    }
+}
+
+/// How a single question of a request is answered.
+enum Answer {
+    /// Records from the cache, or from the resolver once forwarded.
+    Records(Vec<Resource>),
+    /// The name exists but has no records of the type. Holds the SOA of the zone, if known.
+    NoData(Option<Resource>),
+    NameError,
+    /// The name is in a child zone delegated away from a local zone. Holds the NS records of the
+    /// zone cut and the addresses of the name servers inside the child zone (glue).
+    Referral {
+        ns: Vec<Resource>,
+        glue: Vec<Resource>,
+    },
+    /// Not answered yet, the question has to be forwarded to a resolver.
+    Forward,
+}
+
+/// Where the answer to a question came from, for logging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnswerSource {
+    /// Records, or their absence, from the cache and the local zones.
+    CacheHit,
+    /// Sent to a resolver.
+    Forwarded,
+    /// Not in the cache and not forwarded, as the client did not desire recursion.
+    CacheMiss,
+    Blocked,
+    /// Made up here instead of stored, like PTR records of addresses and rewritten answers.
+    Synthesized,
+}
+
+impl AnswerSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::CacheHit => "cache-hit",
+            Self::Forwarded => "forwarded",
+            Self::CacheMiss => "cache-miss",
+            Self::Blocked => "blocked",
+            Self::Synthesized => "synthesized",
+        }
+    }
+}
+
+/// The answers of a resolver to forwarded questions, with the records of the authority and
+/// additional sections of its responses, like the SOA of a negative answer or the addresses of
+/// the names in the answers.
+#[derive(Default)]
+struct Forwarded {
+    answers: Vec<Answer>,
+    authority: Vec<Resource>,
+    additional: Vec<Resource>,
+}
+
+impl Forwarded {
+    /// Keep the authority and additional records of `res_packet` that weren't kept already. The
+    /// OPT and TSIG records are left out, as they only apply to the response they are in.
+    fn add_sections(&mut self, res_packet: &proto::Packet<'_>) -> Result<(), ForwardError> {
+        for (section, record) in res_packet.all_records() {
+            let (kept, error): (_, fn(_) -> proto::PacketError) = match section {
+                proto::Section::Answers => continue,
+                proto::Section::Authority => {
+                    (&mut self.authority, proto::PacketError::AuthoritiveItem)
+                }
+                proto::Section::Additional => {
+                    (&mut self.additional, proto::PacketError::AdditionalItem)
+                }
+            };
+            if matches!(record.typ(), proto::Type::OPT | proto::Type::TSIG) {
+                continue;
+            }
+            let data = ResourceData::try_from(record).map_err(error)?;
+            let record = Resource(DomainName::from(&record.name()), Arc::new(data));
+            if !kept.iter().any(|r| r.0 == record.0 && r.1 == record.1) {
+                kept.push(record);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the questions of a request are sent to a resolver.
+#[derive(Debug, Clone, Copy, Default)]
+struct ForwardMode {
+    /// Questions are sent with the bytes the client sent them in.
+    verbatim: bool,
+    /// Every question is sent in one query, instead of one query per question.
+    combined: bool,
+}
+
+/// Whether the name at the start of `question` ends in a compression pointer.
+fn has_pointer(question: &[u8]) -> bool {
+    let mut offset = 0;
+    while let Some(&len) = question.get(offset) {
+        match len {
+            0 => return false,
+            len if len & 0xC0 != 0 => return true,
+            len => offset += 1 + len as usize,
+        }
+    }
+    false
+}
+
+async fn forward_request(
+    upstream: &dyn Upstream,
+    packet: &proto::Packet<'_>,
+    questions: &[Question],
+    edns: Option<&Edns>,
+    mode: ForwardMode,
+    timeout: Duration,
+) -> Result<Forwarded, ForwardError> {
+    // Combined questions are encoded again, so verbatim questions are sent in a query each.
+    if mode.combined && !mode.verbatim && questions.len() > 1 {
+        if let Some(forwarded) =
+            forward_combined(upstream, packet, questions, edns, timeout).await?
+        {
+            return Ok(forwarded);
+        }
+        tracing::debug!("Resolver did not answer every question, forwarding them one by one");
+    }
+
+    let mut request = ArrayBuffer::new().with_max_len((u16::MAX - 2) as usize);
+    let mut forwarded = Forwarded::default();
+
+    for q in questions {
+        let name = q.name();
+        request.clear();
+        // A compressed name points into the packet of the client, so it can't be copied.
+        let original = packet
+            .questions()
+            .filter(|_| mode.verbatim)
+            .find(|raw| {
+                name.equals(&raw.name())
+                    && raw.q_type().as_u16() == q.q_type().as_u16()
+                    && raw.q_class().as_u16() == q.q_class().as_u16()
+            })
+            .filter(|raw| !has_pointer(raw.as_bytes()));
+        let query_name = match &original {
+            Some(raw) => DomainName::from(&raw.name()),
+            None => name.with_random_case(&mut rand::thread_rng()),
+        };
+        let mut query = DNSPacketBuilder::query(packet.header().id()).add_question(Question::new(
+            *q.q_type(),
+            *q.q_class(),
+            query_name.clone(),
+        ));
+        if let Some(edns) = edns {
+            query = query.with_edns(edns.clone());
+        }
+        query.build_into(&mut request);
+        if let Some(raw) = original {
+            // An uncompressed name is written with the same length right after the header.
+            let raw = raw.as_bytes();
+            request[proto::HeaderView::SIZE..][..raw.len()].copy_from_slice(raw);
+        }
+
+        tracing::info!(%name, "Forwarding question");
+
+        //print_buffer("Forward Request", &request);
+
+        let response = tokio::time::timeout(timeout, upstream.query(&request))
+            .await
+            .map_err(|_| ForwardError::TimedOut)??;
+
+        //print_buffer("Forward Response", &ArrayBuffer::from(&response[..]));
+
+        let Some(res_packet) = proto::Packet::parse(&response, 0)? else {
+            tracing::warn!("Returned no packet repr from response");
+            forwarded.answers.push(Answer::Records(Vec::new()));
+            continue;
+        };
+
+        // Only the UDP transport matches responses to queries by their ID.
+        if res_packet.header().id() != packet.header().id() {
+            return Err(ForwardError::IdMismatch);
+        }
+        if !res_packet
+            .questions()
+            .next()
+            .is_some_and(|echoed| query_name.equals_exact(&echoed.name()))
+        {
+            return Err(ForwardError::QuestionMismatch);
+        }
         //println!("name={name}");
         //println!("{res_packet:#?}");
+        forwarded.add_sections(&res_packet)?;
+        if matches!(res_packet.header().response_code(), ResponseCode::NameError) {
+            forwarded.answers.push(Answer::NameError);
+            continue;
+        }
 
-        builder = res_packet
-            .answers()
-            .filter(|answer| name.equals(&answer.name()))
-            .fold(
-                builder.add_question(Question::new(
-                    q.q_type().clone(),
-                    q.q_class().clone(),
-                    name.clone(),
-                )),
-                |b, a| b.add_answer(Resource(name.clone(), Arc::new(a.into()))),
-            );
+        let records = answer_records(name, &res_packet)?;
+        forwarded.answers.push(Answer::Records(records));
+    }
+
+    Ok(forwarded)
+}
+
+/// Forward every question of `questions` in a single query. Returns `None` when the resolver
+/// did not answer every question, or answered with a name error, which can't be told apart
+/// between the questions.
+async fn forward_combined(
+    upstream: &dyn Upstream,
+    packet: &proto::Packet<'_>,
+    questions: &[Question],
+    edns: Option<&Edns>,
+    timeout: Duration,
+) -> Result<Option<Forwarded>, ForwardError> {
+    let query_names: Vec<_> = questions
+        .iter()
+        .map(|q| q.name().with_random_case(&mut rand::thread_rng()))
+        .collect();
+    let mut query = DNSPacketBuilder::query(packet.header().id());
+    for (q, query_name) in questions.iter().zip(&query_names) {
+        query = query.add_question(Question::new(*q.q_type(), *q.q_class(), query_name.clone()));
     }
+    if let Some(edns) = edns {
+        query = query.with_edns(edns.clone());
+    }
+    let mut request = ArrayBuffer::new().with_max_len((u16::MAX - 2) as usize);
+    query.build_into(&mut request);
+
+    tracing::info!(
+        questions = questions.len(),
+        "Forwarding questions in one query"
+    );
+
+    let response = tokio::time::timeout(timeout, upstream.query(&request))
+        .await
+        .map_err(|_| ForwardError::TimedOut)??;
+    let Some(res_packet) = proto::Packet::parse(&response, 0)? else {
+        return Ok(None);
+    };
+    if res_packet.header().id() != packet.header().id() {
+        return Err(ForwardError::IdMismatch);
+    }
+    if res_packet.questions().count() != questions.len()
+        || matches!(res_packet.header().response_code(), ResponseCode::NameError)
+    {
+        return Ok(None);
+    }
+    if !res_packet
+        .questions()
+        .zip(&query_names)
+        .all(|(echoed, query_name)| query_name.equals_exact(&echoed.name()))
+    {
+        return Err(ForwardError::QuestionMismatch);
+    }
+
+    let mut forwarded = Forwarded::default();
+    forwarded.add_sections(&res_packet)?;
+    for q in questions {
+        // The answer section is shared, so each question only takes the records of its type.
+        let mut records = answer_records(q.name(), &res_packet)?;
+        records.retain(|Resource(_, data)| {
+            *q.q_type() == proto::QType::ALL
+                || *data.typ() == proto::Type::CNAME
+                || data.typ().as_u16() == q.q_type().as_u16()
+        });
+        forwarded.answers.push(Answer::Records(records));
+    }
+    Ok(Some(forwarded))
+}
 
-    Ok(builder)
+/// The records of the answer section of `res_packet` owned by `name`. Records of the targets of
+/// CNAME records owned by the name are part of the answer too.
+fn answer_records(
+    name: &DomainName,
+    res_packet: &proto::Packet<'_>,
+) -> Result<Vec<Resource>, ForwardError> {
+    let mut owners = vec![name.clone()];
+    let mut records = Vec::new();
+    for answer in res_packet.answers() {
+        let Some(owner) = owners.iter().find(|owner| owner.equals(&answer.name())) else {
+            continue;
+        };
+        let owner = owner.clone();
+        let data = ResourceData::try_from(answer).map_err(proto::PacketError::Answer)?;
+        if *data.typ() == proto::Type::CNAME {
+            if let Ok(target) = DomainName::from_wire(data.data().as_ref(), 0) {
+                owners.push(target);
+            }
+        }
+        records.push(Resource(owner, Arc::new(data)));
+    }
+    Ok(records)
 }
 /*
 fn print_buffer(label: &str, buffer: &ArrayBuffer) {
@@ -496,3 +2248,2572 @@ fn print_buffer(label: &str, buffer: &ArrayBuffer) {
     eprint!("{buffer:b}");
     eprintln!("--- End {label} ---");
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::QType;
+
+    /// The address requests in tests are sent from.
+    const CLIENT: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 5353);
+
+    fn settings() -> Settings {
+        Settings {
+            resolver: UpstreamSpec::Udp(DEFAULT_UPSTREAM),
+            backup_resolvers: Vec::new(),
+            tsig_keys: Vec::new(),
+            policy: ForwardPolicy::ForwardFirst,
+            recursion: true,
+            local_zones: Vec::new(),
+            forward_rules: Vec::new(),
+            client_subnet: None,
+            dns64: None,
+            edns_padding: None,
+            verbatim_questions: false,
+            combine_questions: false,
+            forward_authority: false,
+            upstream_timeout: Duration::from_secs(2),
+            inflight: tokio::sync::Semaphore::new(256),
+            blocked: SuffixMatcher::default(),
+            rewrite_rules: Vec::new(),
+            round_robin: RoundRobin::default(),
+            retransmits: Arc::default(),
+            shuffle: None,
+            upstreams: Upstreams::default(),
+            secondaries: Vec::new(),
+            force_truncate_over: None,
+            max_answer_records: None,
+            response_delay: None,
+            answer_ttl_override: None,
+            stats: QueryStats::default(),
+            dry_run: false,
+            log_bad_packets: false,
+            cookies: ServerCookies::default(),
+        }
+    }
+
+    async fn seeded_cache(records: Vec<(DomainName, ResourceData)>) -> EVCache {
+        let (cache, operator) = cache::new();
+        tokio::spawn(operator.listen());
+        let mut bulk = cache.bulk();
+        for (name, data) in records {
+            bulk = bulk.insert(&name, data).await.unwrap();
+        }
+        bulk.publish().await.unwrap();
+        cache
+    }
+
+    /// Start a resolver answering every question with an A record of `addr`.
+    async fn stub_upstream(addr: Ipv4Addr) -> UpstreamSpec {
+        recording_stub_upstream(addr).await.0
+    }
+
+    /// Like [stub_upstream], but also sends the bytes of every query it receives to the returned
+    /// channel.
+    async fn recording_stub_upstream(
+        addr: Ipv4Addr,
+    ) -> (UpstreamSpec, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = socket.local_addr().unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((size, source)) = socket.recv_from(&mut buf).await {
+                let _ = tx.send(buf[..size].to_vec());
+                let packet = proto::Packet::parse(&buf[..size], 0).unwrap().unwrap();
+                let mut builder = DNSPacketBuilder::respond(&packet, ResponseCode::None);
+                for q in packet.questions() {
+                    let name: DomainName = (&q.name()).into();
+                    builder = builder
+                        .add_question(q.into())
+                        .add_answer(Resource(name, Arc::new(ResourceData::A { ttl: 60, addr })));
+                }
+                let mut response = ArrayBuffer::new();
+                builder.build_into(&mut response);
+                let _ = socket.send_to(&response, source).await;
+            }
+        });
+        (local_addr.into(), rx)
+    }
+
+    /// A canned answer of [canned_upstream].
+    enum Canned {
+        A(Ipv4Addr),
+        NameError,
+    }
+
+    /// Start a resolver answering questions from a fixed table, keyed by name and type. Queries
+    /// with a question missing from the table are never answered.
+    async fn canned_upstream(table: Vec<(&'static str, QType, Canned)>) -> UpstreamSpec {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = socket.local_addr().unwrap();
+        let table: Vec<_> = table
+            .into_iter()
+            .map(|(name, q_type, canned)| (DomainName::from_static(name), q_type, canned))
+            .collect();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            'queries: while let Ok((size, source)) = socket.recv_from(&mut buf).await {
+                let packet = proto::Packet::parse(&buf[..size], 0).unwrap().unwrap();
+                let mut code = ResponseCode::None;
+                let mut records = Vec::new();
+                for q in packet.questions() {
+                    let name: DomainName = (&q.name()).into();
+                    let Some((_, _, canned)) = table
+                        .iter()
+                        .find(|(n, t, _)| *n == name && t.as_u16() == q.q_type().as_u16())
+                    else {
+                        continue 'queries;
+                    };
+                    match canned {
+                        Canned::A(addr) => records.push(Resource(
+                            name,
+                            Arc::new(ResourceData::A {
+                                ttl: 60,
+                                addr: *addr,
+                            }),
+                        )),
+                        Canned::NameError => code = ResponseCode::NameError,
+                    }
+                }
+                let mut builder = DNSPacketBuilder::respond(&packet, code);
+                for q in packet.questions() {
+                    builder = builder.add_question(q.into());
+                }
+                for record in records {
+                    builder = builder.add_answer(record);
+                }
+                let mut response = ArrayBuffer::new();
+                builder.build_into(&mut response);
+                let _ = socket.send_to(&response, source).await;
+            }
+        });
+        local_addr.into()
+    }
+
+    /// Start the server on ephemeral ports and return the address of its UDP listener.
+    async fn start_server(settings: Settings, cache: EVCache, workers: usize) -> SocketAddr {
+        let settings = Arc::new(settings);
+        let (udp, rx) = UDPStateSender::new(0, Arc::clone(&settings), 100)
+            .await
+            .unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        spawn_udp_handlers(&cache, rx, workers);
+        tokio::spawn(serve(
+            udp,
+            tcp,
+            cache,
+            settings,
+            workers,
+            std::future::pending(),
+        ));
+        addr
+    }
+
+    /// Start a resolver that holds back its answers until it has received `queries` queries.
+    async fn gated_stub_upstream(addr: Ipv4Addr, queries: usize) -> UpstreamSpec {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut received = Vec::new();
+            let mut buf = [0; 512];
+            while received.len() < queries {
+                let (size, source) = socket.recv_from(&mut buf).await.unwrap();
+                received.push((buf[..size].to_vec(), source));
+            }
+            for (query, source) in received {
+                let packet = proto::Packet::parse(&query, 0).unwrap().unwrap();
+                let mut builder = DNSPacketBuilder::respond(&packet, ResponseCode::None);
+                for q in packet.questions() {
+                    let name: DomainName = (&q.name()).into();
+                    builder = builder
+                        .add_question(q.into())
+                        .add_answer(Resource(name, Arc::new(ResourceData::A { ttl: 60, addr })));
+                }
+                let mut response = ArrayBuffer::new();
+                builder.build_into(&mut response);
+                socket.send_to(&response, source).await.unwrap();
+            }
+        });
+        local_addr.into()
+    }
+
+    fn query(name: &'static str, q_type: QType) -> DNSPacketBuilder {
+        DNSPacketBuilder::query(1).add_question(Question::new(
+            q_type,
+            proto::QClass::IN,
+            DomainName::from_static(name),
+        ))
+    }
+
+    async fn respond(
+        request: DNSPacketBuilder,
+        settings: &Settings,
+        cache: &EVCache,
+    ) -> ArrayBuffer {
+        let mut buf = ArrayBuffer::new();
+        request.build_into(&mut buf);
+        let mut response = ArrayBuffer::new();
+        handle_dns_packet(
+            &mut buf,
+            &mut response,
+            Transport::Udp,
+            CLIENT,
+            settings,
+            cache,
+        )
+        .await;
+        response
+    }
+
+    fn answer_data(packet: &proto::Packet<'_>) -> Vec<Vec<u8>> {
+        packet
+            .answers()
+            .map(|answer| answer.data().unwrap().to_vec())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn packets_shorter_than_a_header_are_dropped() {
+        let cache = seeded_cache(Vec::new()).await;
+        for garbage in [&[4, 210, 1, 0][..], &[4, 210, 1, 0, 0, 1, 0, 0, 0, 0, 0]] {
+            let mut buf = ArrayBuffer::from(garbage);
+            let mut response = ArrayBuffer::new();
+            handle_dns_packet(
+                &mut buf,
+                &mut response,
+                Transport::Udp,
+                CLIENT,
+                &settings(),
+                &cache,
+            )
+            .await;
+            assert_eq!(response.len(), 0, "Responded to {} bytes", garbage.len());
+        }
+
+        // A whole header with a truncated question is still answered.
+        let mut buf = ArrayBuffer::from(&[4, 210, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 3, b'c'][..]);
+        let mut response = ArrayBuffer::new();
+        handle_dns_packet(
+            &mut buf,
+            &mut response,
+            Transport::Udp,
+            CLIENT,
+            &settings(),
+            &cache,
+        )
+        .await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().id(), 1234);
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::FormatError.as_u8()
+        );
+    }
+
+    #[tokio::test]
+    async fn query_without_questions_is_answered() {
+        let cache = seeded_cache(Vec::new()).await;
+        for (request, edns) in [
+            (DNSPacketBuilder::query(1234), false),
+            (
+                DNSPacketBuilder::query(1234).with_edns(Edns::default()),
+                true,
+            ),
+        ] {
+            let response = respond(request, &settings(), &cache).await;
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            assert_eq!(packet.header().id(), 1234);
+            assert!(matches!(
+                packet.header().packet_type(),
+                proto::PacketType::Response
+            ));
+            assert_eq!(
+                packet.header().response_code().as_u8(),
+                ResponseCode::None.as_u8()
+            );
+            assert_eq!(packet.questions().count(), 0);
+            assert_eq!(packet.edns().is_some(), edns);
+        }
+    }
+
+    #[tokio::test]
+    async fn unsupported_edns_version_is_bad_version() {
+        let (cache, _) = cache::new();
+        let request = query("codecrafters.io", QType::A).with_edns(Edns {
+            version: 1,
+            ..Edns::default()
+        });
+        let response = respond(request, &settings(), &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().response_code().as_u8(), 0);
+        let edns = packet.edns().expect("Response to have an OPT record");
+        assert_eq!(edns.extended_response_code(), 1);
+        assert_eq!(edns.version(), 0);
+    }
+
+    async fn many_records_cache() -> EVCache {
+        seeded_cache(
+            (0..40)
+                .map(|i| {
+                    (
+                        DomainName::from_static("many.example.com"),
+                        ResourceData::A {
+                            ttl: 60,
+                            addr: Ipv4Addr::new(10, 0, 0, i),
+                        },
+                    )
+                })
+                .collect(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn udp_response_without_edns_is_capped_at_512() {
+        let cache = many_records_cache().await;
+        let response = respond(query("many.example.com", QType::A), &settings(), &cache).await;
+        assert!(response.len() <= 512, "{} bytes", response.len());
+        assert_eq!(proto::HeaderView::new(&response).truncated(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn udp_response_uses_edns_payload_size() {
+        let cache = many_records_cache().await;
+        let request = query("many.example.com", QType::A).with_edns(Edns {
+            udp_payload_size: 4096,
+            ..Edns::default()
+        });
+        let response = respond(request, &settings(), &cache).await;
+        assert!(response.len() > 512, "{} bytes", response.len());
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert!(!packet.header().truncated());
+        assert_eq!(packet.answers().count(), 40);
+    }
+
+    #[tokio::test]
+    async fn forced_truncation_drops_records_that_would_fit() {
+        let settings = Settings {
+            force_truncate_over: Some(40),
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("example.com"),
+            ResourceData::A {
+                ttl: 500,
+                addr: Ipv4Addr::new(10, 0, 0, 1),
+            },
+        )])
+        .await;
+        let response = respond(query("example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert!(packet.header().truncated());
+        assert_eq!(packet.questions().count(), 1);
+        assert_eq!(packet.answers().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn forward_first_forwards_local_zone_miss() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            local_zones: vec!["example.com".parse().unwrap()],
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let response = respond(query("www.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().response_code().as_u8(), 0);
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+    }
+
+    #[tokio::test]
+    async fn forward_only_local_zone_miss_is_name_error() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            policy: ForwardPolicy::ForwardOnly,
+            local_zones: vec!["example.com".parse().unwrap()],
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let response = respond(query("www.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::NameError.as_u8()
+        );
+        assert_eq!(packet.header().question_entries(), 1);
+        assert_eq!(packet.header().answer_entries(), 0);
+    }
+
+    #[tokio::test]
+    async fn forward_only_skips_cache_outside_local_zones() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            policy: ForwardPolicy::ForwardOnly,
+            local_zones: vec!["example.com".parse().unwrap()],
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("codecrafters.io"),
+            ResourceData::A {
+                ttl: 500,
+                addr: Ipv4Addr::new(8, 8, 8, 8),
+            },
+        )])
+        .await;
+        let response = respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+    }
+
+    #[tokio::test]
+    async fn forward_rule_selects_resolver_by_suffix() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            forward_rules: vec![format!(
+                "*.corp.example.com={}",
+                stub_upstream(Ipv4Addr::new(10, 0, 0, 2)).await
+            )
+            .parse()
+            .unwrap()],
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+
+        let response = respond(
+            query("intranet.corp.example.com", QType::A),
+            &settings,
+            &cache,
+        )
+        .await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 2]]);
+
+        let response = respond(query("www.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+    }
+
+    #[tokio::test]
+    async fn question_case_is_preserved() {
+        // Clients randomizing the case of their questions (DNS 0x20) expect it echoed exactly,
+        // while the cache still matches the name in any case.
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("example.com"),
+            ResourceData::A {
+                ttl: 60,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+        .await;
+
+        for (name, data) in [
+            ("ExAmPlE.CoM", [192, 0, 2, 1]),
+            ("wWw.ExAmPlE.oRg", [10, 0, 0, 1]),
+        ] {
+            let request = DNSPacketBuilder::query(1).add_question(Question::new(
+                QType::A,
+                proto::QClass::IN,
+                name.parse().unwrap(),
+            ));
+            let response = respond(request, &settings, &cache).await;
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            let question = packet.questions().next().unwrap();
+            assert_eq!(question.name().to_string(), format!("{name}."));
+            assert_eq!(answer_data(&packet), [data]);
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_type_questions_keep_their_order() {
+        // The MX question is forwarded while the A question is answered from the cache, but
+        // both are echoed and answered in the order of the request.
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("example.com"),
+            ResourceData::A {
+                ttl: 60,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+        .await;
+
+        let request = query("example.com", QType::MX).add_question(Question::new(
+            QType::A,
+            proto::QClass::IN,
+            DomainName::from_static("example.com"),
+        ));
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.questions().map(|q| q.q_type()).collect::<Vec<_>>(),
+            [QType::MX, QType::A]
+        );
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1], [192, 0, 2, 1]]);
+    }
+
+    #[tokio::test]
+    async fn forwards_reuse_a_socket_per_resolver() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            forward_rules: vec![format!(
+                "corp.example.com={}",
+                stub_upstream(Ipv4Addr::new(10, 0, 0, 2)).await
+            )
+            .parse()
+            .unwrap()],
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+
+        for id in 0..50 {
+            let suffix = if id % 2 == 0 {
+                "example.com"
+            } else {
+                "corp.example.com"
+            };
+            let name: DomainName = format!("host{id}.{suffix}").parse().unwrap();
+            let request = DNSPacketBuilder::query(id).add_question(Question::new(
+                QType::A,
+                proto::QClass::IN,
+                name,
+            ));
+            let response = respond(request, &settings, &cache).await;
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            assert_eq!(packet.header().id(), id);
+            assert_eq!(answer_data(&packet), [[10, 0, 0, 1 + (id % 2) as u8]]);
+        }
+        assert_eq!(settings.upstreams.socket_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn forwarded_query_carries_client_subnet() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let settings = Settings {
+            resolver,
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let subnet = edns::parse_client_subnet("192.0.2.0/24").unwrap();
+        let request = query("codecrafters.io", QType::A).with_edns(Edns {
+            options: vec![EdnsOption::ClientSubnet(subnet)],
+            ..Edns::default()
+        });
+        respond(request, &settings, &cache).await;
+
+        let forwarded = queries.recv().await.unwrap();
+        let packet = proto::Packet::parse(&forwarded, 0).unwrap().unwrap();
+        let edns = packet
+            .edns()
+            .expect("Forwarded query to have an OPT record");
+        assert_eq!(edns.client_subnet().unwrap(), Some(subnet));
+    }
+
+    #[tokio::test]
+    async fn forwarded_name_has_random_case() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let settings = Settings {
+            resolver,
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let name = "abcdefghijklmnopqrstuvwxyz.example.com";
+        let response = respond(query(name, QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+        assert_eq!(
+            packet.questions().next().unwrap().name().to_string(),
+            format!("{name}.")
+        );
+
+        let forwarded = queries.recv().await.unwrap();
+        let packet = proto::Packet::parse(&forwarded, 0).unwrap().unwrap();
+        let forwarded_name = packet.questions().next().unwrap().name().to_string();
+        assert!(forwarded_name.eq_ignore_ascii_case(&format!("{name}.")));
+        assert_ne!(forwarded_name, format!("{name}."));
+    }
+
+    #[test]
+    fn verbatim_questions_cannot_be_combined() {
+        let args = [
+            "dns-starter-rust",
+            "--verbatim-questions",
+            "--combine-questions",
+        ];
+        assert!(Args::try_parse_from(args).is_err());
+        let args = ["dns-starter-rust", "--combine-questions"];
+        assert!(Args::try_parse_from(args).is_ok());
+    }
+
+    #[test]
+    fn health_check_interval_cannot_be_zero() {
+        let args = ["dns-starter-rust", "--health-check-interval", "0"];
+        assert!(Args::try_parse_from(args).is_err());
+        let args = ["dns-starter-rust", "--health-check-interval", "1"];
+        assert!(Args::try_parse_from(args).is_ok());
+    }
+
+    #[tokio::test]
+    async fn verbatim_questions_are_forwarded_byte_for_byte() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let settings = Settings {
+            resolver,
+            verbatim_questions: true,
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let mut request = ArrayBuffer::new();
+        query("CodeCrafters.IO", QType::A).build_into(&mut request);
+        let sent = proto::Packet::parse(&request, 0).unwrap().unwrap();
+        let question = sent.questions().next().unwrap().as_bytes().to_vec();
+
+        let response = respond(query("CodeCrafters.IO", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+
+        let forwarded = queries.recv().await.unwrap();
+        let packet = proto::Packet::parse(&forwarded, 0).unwrap().unwrap();
+        assert_eq!(packet.questions().next().unwrap().as_bytes(), question);
+    }
+
+    #[tokio::test]
+    async fn questions_are_forwarded_in_one_query() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let settings = Settings {
+            resolver,
+            combine_questions: true,
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let request = query("www.example.com", QType::A).add_question(Question::new(
+            QType::A,
+            proto::QClass::IN,
+            DomainName::from_static("mail.example.com"),
+        ));
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        let owners: Vec<_> = packet.answers().map(|a| a.name().to_string()).collect();
+        assert_eq!(owners, ["www.example.com.", "mail.example.com."]);
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1], [10, 0, 0, 1]]);
+
+        let forwarded = queries.recv().await.unwrap();
+        let forwarded = proto::Packet::parse(&forwarded, 0).unwrap().unwrap();
+        assert_eq!(forwarded.questions().count(), 2);
+        assert!(queries.try_recv().is_err(), "Sent a query per question");
+    }
+
+    #[tokio::test]
+    async fn resolver_answering_the_first_question_gets_a_query_per_question() {
+        // A resolver that only keeps the first question of a query.
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver: UpstreamSpec = socket.local_addr().unwrap().into();
+        let (tx, mut queries) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((size, source)) = socket.recv_from(&mut buf).await {
+                let packet = proto::Packet::parse(&buf[..size], 0).unwrap().unwrap();
+                let _ = tx.send(packet.questions().count());
+                let q = packet.questions().next().unwrap();
+                let name: DomainName = (&q.name()).into();
+                let mut response = ArrayBuffer::new();
+                DNSPacketBuilder::respond(&packet, ResponseCode::None)
+                    .add_question(q.into())
+                    .add_answer(Resource(
+                        name,
+                        Arc::new(ResourceData::A {
+                            ttl: 60,
+                            addr: Ipv4Addr::new(10, 0, 0, 1),
+                        }),
+                    ))
+                    .build_into(&mut response);
+                let _ = socket.send_to(&response, source).await;
+            }
+        });
+        let settings = Settings {
+            resolver,
+            combine_questions: true,
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let request = query("www.example.com", QType::A).add_question(Question::new(
+            QType::A,
+            proto::QClass::IN,
+            DomainName::from_static("mail.example.com"),
+        ));
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1], [10, 0, 0, 1]]);
+
+        let mut sent = Vec::new();
+        while let Ok(questions) = queries.try_recv() {
+            sent.push(questions);
+        }
+        assert_eq!(sent, [2, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn response_with_different_case_is_rejected() {
+        // A resolver that lowercases the question it echoes.
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((size, source)) = socket.recv_from(&mut buf).await {
+                let packet = proto::Packet::parse(&buf[..size], 0).unwrap().unwrap();
+                let name = packet.questions().next().unwrap().name().to_string();
+                let mut response = ArrayBuffer::new();
+                DNSPacketBuilder::respond(&packet, ResponseCode::None)
+                    .add_question(Question::new(
+                        QType::A,
+                        proto::QClass::IN,
+                        name.to_ascii_lowercase().parse().unwrap(),
+                    ))
+                    .build_into(&mut response);
+                let _ = socket.send_to(&response, source).await;
+            }
+        });
+        let settings = Settings {
+            resolver: resolver.into(),
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+
+        let response = respond(
+            query("abcdefghijklmnopqrstuvwxyz.example.com", QType::A),
+            &settings,
+            &cache,
+        )
+        .await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::ServerFailure.as_u8()
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded_query_uses_default_client_subnet() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let subnet = edns::parse_client_subnet("2001:db8::/56").unwrap();
+        let settings = Settings {
+            resolver,
+            client_subnet: Some(subnet),
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+
+        let forwarded = queries.recv().await.unwrap();
+        let packet = proto::Packet::parse(&forwarded, 0).unwrap().unwrap();
+        let edns = packet
+            .edns()
+            .expect("Forwarded query to have an OPT record");
+        assert_eq!(edns.client_subnet().unwrap(), Some(subnet));
+    }
+
+    /// Send a query to a running server over UDP and wait for the response.
+    async fn exchange(server: SocketAddr, request: DNSPacketBuilder) -> ArrayBuffer {
+        let mut buf = ArrayBuffer::new();
+        request.build_into(&mut buf);
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server).await.unwrap();
+        client.send(&buf).await.unwrap();
+        let mut response = [0; 512];
+        let size = client.recv(&mut response).await.unwrap();
+        ArrayBuffer::from(&response[..size])
+    }
+
+    #[tokio::test]
+    async fn server_answers_from_cache() {
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("codecrafters.io"),
+            ResourceData::A {
+                ttl: 500,
+                addr: Ipv4Addr::new(8, 8, 8, 8),
+            },
+        )])
+        .await;
+        let server = start_server(settings(), cache, 1).await;
+
+        let response = exchange(server, query("codecrafters.io", QType::A)).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().id(), 1);
+        assert_eq!(packet.header().response_code().as_u8(), 0);
+        assert_eq!(answer_data(&packet), [[8, 8, 8, 8]]);
+    }
+
+    #[tokio::test]
+    async fn server_forwards_cache_miss() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            ..settings()
+        };
+        let server = start_server(settings, seeded_cache(Vec::new()).await, 1).await;
+
+        let response = exchange(server, query("www.example.com", QType::A)).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().question_entries(), 1);
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+    }
+
+    #[tokio::test]
+    async fn workers_handle_requests_concurrently() {
+        // The resolver only answers once both forwarded queries have arrived, which requires a
+        // second worker to pick up the second request while the first is waiting.
+        let settings = Settings {
+            resolver: gated_stub_upstream(Ipv4Addr::new(10, 0, 0, 1), 2).await,
+            ..settings()
+        };
+        let server = start_server(settings, seeded_cache(Vec::new()).await, 2).await;
+
+        let exchanges = async {
+            tokio::join!(
+                exchange(server, query("a.example.com", QType::A)),
+                exchange(server, query("b.example.com", QType::A)),
+            )
+        };
+        let (a, b) = tokio::time::timeout(std::time::Duration::from_secs(5), exchanges)
+            .await
+            .expect("Both requests to be answered");
+        for response in [a, b] {
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+        }
+    }
+
+    #[test]
+    fn multi_threaded_runtime_answers_concurrent_requests() {
+        let runtime = build_runtime(4).unwrap();
+        runtime.block_on(async {
+            let settings = Settings {
+                resolver: gated_stub_upstream(Ipv4Addr::new(10, 0, 0, 1), 2).await,
+                ..settings()
+            };
+            let cache = seeded_cache(vec![(
+                DomainName::from_static("codecrafters.io"),
+                ResourceData::A {
+                    ttl: 500,
+                    addr: Ipv4Addr::new(8, 8, 8, 8),
+                },
+            )])
+            .await;
+            let server = start_server(settings, cache, 4).await;
+
+            let mut requests = tokio::task::JoinSet::new();
+            for name in ["a.example.com", "b.example.com"] {
+                requests.spawn(async move {
+                    (exchange(server, query(name, QType::A)).await, [10, 0, 0, 1])
+                });
+            }
+            for _ in 0..8 {
+                requests.spawn(async move {
+                    (
+                        exchange(server, query("codecrafters.io", QType::A)).await,
+                        [8, 8, 8, 8],
+                    )
+                });
+            }
+            let all = async {
+                while let Some(result) = requests.join_next().await {
+                    let (response, expected) = result.unwrap();
+                    let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+                    assert_eq!(answer_data(&packet), [expected]);
+                }
+            };
+            tokio::time::timeout(std::time::Duration::from_secs(5), all)
+                .await
+                .expect("Every request to be answered");
+        });
+    }
+
+    #[tokio::test]
+    async fn blocked_name_is_refused_with_extended_error() {
+        let settings = Settings {
+            blocked: ["ads.example.com".parse().unwrap()].into_iter().collect(),
+            ..settings()
+        };
+        let (cache, _) = cache::new();
+        let request = query("tracker.ads.example.com", QType::A).with_edns(Edns::default());
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::Refused.as_u8()
+        );
+        let edns = packet.edns().expect("Response to have an OPT record");
+        assert_eq!(
+            edns.extended_error().unwrap().map(|(code, _)| code),
+            Some(edns::EDE_BLOCKED)
+        );
+    }
+
+    #[tokio::test]
+    async fn rewrite_rules_override_answers() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            rewrite_rules: vec![
+                "*.tracker.example.com=nxdomain".parse().unwrap(),
+                "ok.tracker.example.com=passthru".parse().unwrap(),
+                "portal.example.com=192.0.2.10".parse().unwrap(),
+            ],
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let rcode_and_answers = |name, q_type| {
+            let settings = &settings;
+            let cache = &cache;
+            async move {
+                let response = respond(query(name, q_type), settings, cache).await;
+                let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+                (
+                    packet.header().response_code().as_u8(),
+                    answer_data(&packet),
+                )
+            }
+        };
+
+        let name_error = ResponseCode::NameError.as_u8();
+        let no_error = ResponseCode::None.as_u8();
+        assert_eq!(
+            rcode_and_answers("pixel.TRACKER.example.com", QType::A).await,
+            (name_error, Vec::new())
+        );
+        assert_eq!(
+            rcode_and_answers("ok.tracker.example.com", QType::A).await,
+            (no_error, vec![vec![10, 0, 0, 1]])
+        );
+        // The wildcard only matches names below it.
+        assert_eq!(
+            rcode_and_answers("tracker.example.com", QType::A).await,
+            (no_error, vec![vec![10, 0, 0, 1]])
+        );
+        assert_eq!(
+            rcode_and_answers("portal.example.com", QType::A).await,
+            (no_error, vec![vec![192, 0, 2, 10]])
+        );
+        assert_eq!(
+            rcode_and_answers("portal.example.com", QType::AAAA).await,
+            (no_error, Vec::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn out_of_zone_questions_are_refused_without_recursion() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let settings = Settings {
+            resolver,
+            recursion: false,
+            local_zones: vec![DomainName::from_static("example.com")],
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("www.example.com"),
+            ResourceData::A {
+                ttl: 60,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+        .await;
+
+        let response = respond(query("www.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[192, 0, 2, 1]]);
+
+        let request = query("codecrafters.io", QType::A).with_edns(Edns::default());
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::Refused.as_u8()
+        );
+        let edns = packet.edns().expect("Response to have an OPT record");
+        assert_eq!(
+            edns.extended_error().unwrap().map(|(code, _)| code),
+            Some(edns::EDE_NOT_AUTHORITATIVE)
+        );
+        assert!(queries.try_recv().is_err(), "Forwarded without recursion");
+    }
+
+    #[tokio::test]
+    async fn questions_without_recursion_desired_are_not_forwarded() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let settings = Settings {
+            resolver,
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("cached.example.com"),
+            ResourceData::A {
+                ttl: 60,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+        .await;
+        let request = query("cached.example.com", QType::A).with_recursion_desired(false);
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[192, 0, 2, 1]]);
+
+        let request = query("codecrafters.io", QType::A).with_recursion_desired(false);
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::None.as_u8()
+        );
+        assert!(!packet.header().recursion_desired());
+        assert!(packet.header().recursion_available());
+        assert_eq!(packet.answers().count(), 0);
+        assert_eq!(packet.authority().count(), 0);
+        assert!(queries.try_recv().is_err(), "Forwarded without RD");
+    }
+
+    #[tokio::test]
+    async fn unreachable_resolver_is_network_error() {
+        // A resolver that never answers.
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let settings = Settings {
+            resolver: silent.local_addr().unwrap().into(),
+            upstream_timeout: Duration::from_millis(50),
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let request = query("codecrafters.io", QType::A).with_edns(Edns::default());
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::ServerFailure.as_u8()
+        );
+        let edns = packet.edns().expect("Response to have an OPT record");
+        assert_eq!(
+            edns.extended_error().unwrap().map(|(code, _)| code),
+            Some(edns::EDE_NETWORK_ERROR)
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_connection_to_resolver_is_server_failure() {
+        // Nothing listens on the port once the listener is dropped.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let settings = Settings {
+            resolver: UpstreamSpec::Tcp(addr),
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let request = query("codecrafters.io", QType::A).with_edns(Edns::default());
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::ServerFailure.as_u8()
+        );
+        let edns = packet.edns().expect("Response to have an OPT record");
+        assert_eq!(
+            edns.extended_error().unwrap().map(|(code, _)| code),
+            Some(edns::EDE_NETWORK_ERROR)
+        );
+    }
+
+    #[tokio::test]
+    async fn forwards_canned_answers() {
+        let resolver = canned_upstream(vec![
+            (
+                "codecrafters.io",
+                QType::A,
+                Canned::A(Ipv4Addr::new(10, 0, 0, 1)),
+            ),
+            (
+                "example.com",
+                QType::A,
+                Canned::A(Ipv4Addr::new(10, 0, 0, 2)),
+            ),
+        ])
+        .await;
+        let settings = Settings {
+            resolver,
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        for (name, expected) in [
+            ("codecrafters.io", [10, 0, 0, 1]),
+            ("example.com", [10, 0, 0, 2]),
+        ] {
+            let response = respond(query(name, QType::A), &settings, &cache).await;
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            assert_eq!(
+                packet.header().response_code().as_u8(),
+                ResponseCode::None.as_u8()
+            );
+            assert_eq!(answer_data(&packet), [expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn name_error_of_the_resolver_is_passed_on() {
+        let resolver = canned_upstream(vec![("nx.example.com", QType::A, Canned::NameError)]).await;
+        let settings = Settings {
+            resolver,
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let response = respond(query("nx.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::NameError.as_u8()
+        );
+        assert!(answer_data(&packet).is_empty());
+    }
+
+    #[tokio::test]
+    async fn unanswered_forward_times_out() {
+        let resolver = canned_upstream(Vec::new()).await;
+        let settings = Settings {
+            resolver,
+            upstream_timeout: Duration::from_millis(50),
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let request = query("codecrafters.io", QType::A).with_edns(Edns::default());
+        let response =
+            tokio::time::timeout(Duration::from_secs(2), respond(request, &settings, &cache))
+                .await
+                .expect("The forward to give up after the upstream timeout");
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::ServerFailure.as_u8()
+        );
+        let edns = packet.edns().expect("Response to have an OPT record");
+        assert_eq!(
+            edns.extended_error().unwrap().map(|(code, _)| code),
+            Some(edns::EDE_NETWORK_ERROR)
+        );
+    }
+
+    #[tokio::test]
+    async fn ptr_is_synthesized_from_cached_address() {
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("host.local"),
+            ResourceData::A {
+                ttl: 300,
+                addr: Ipv4Addr::new(10, 0, 0, 1),
+            },
+        )])
+        .await;
+        let response = respond(
+            query("1.0.0.10.in-addr.arpa", QType::PTR),
+            &settings(),
+            &cache,
+        )
+        .await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().response_code().as_u8(), 0);
+        let answer = packet.answers().next().expect("A synthesized PTR record");
+        assert_eq!(answer.typ(), proto::Type::PTR);
+        assert_eq!(answer.ttl(), 300);
+        assert_eq!(
+            answer.data().unwrap(),
+            DomainName::from_static("host.local").to_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn ptr_is_not_synthesized_when_forwarding_only() {
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("host.local"),
+            ResourceData::A {
+                ttl: 300,
+                addr: Ipv4Addr::new(10, 0, 0, 1),
+            },
+        )])
+        .await;
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 2)).await;
+        let settings = Settings {
+            resolver,
+            policy: ForwardPolicy::ForwardOnly,
+            ..settings()
+        };
+        respond(
+            query("1.0.0.10.in-addr.arpa", QType::PTR),
+            &settings,
+            &cache,
+        )
+        .await;
+
+        let forwarded = queries.recv().await.unwrap();
+        let packet = proto::Packet::parse(&forwarded, 0).unwrap().unwrap();
+        let question = packet.questions().next().unwrap();
+        assert!(question.q_type() == QType::PTR);
+    }
+
+    /// A cache with an A record for every address in `addrs` on codecrafters.io.
+    async fn multi_record_cache(addrs: &[[u8; 4]]) -> EVCache {
+        let name = DomainName::from_static("codecrafters.io");
+        seeded_cache(
+            addrs
+                .iter()
+                .map(|addr| {
+                    (
+                        name.clone(),
+                        ResourceData::A {
+                            ttl: 60,
+                            addr: (*addr).into(),
+                        },
+                    )
+                })
+                .collect(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn multiple_records_are_rotated_between_responses() {
+        let cache = multi_record_cache(&[[8, 8, 8, 8], [8, 8, 4, 4], [1, 1, 1, 1]]).await;
+        let settings = settings();
+
+        let first = respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        let second = respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        let first = answer_data(&proto::Packet::parse(&first, 0).unwrap().unwrap());
+        let second = answer_data(&proto::Packet::parse(&second, 0).unwrap().unwrap());
+        assert_eq!(first.len(), 3);
+        assert_ne!(first[0], second[0]);
+    }
+
+    #[tokio::test]
+    async fn seeded_shuffle_permutes_answers() {
+        let addrs = [[10, 0, 0, 1], [10, 0, 0, 2], [10, 0, 0, 3], [10, 0, 0, 4]];
+        let cache = multi_record_cache(&addrs).await;
+        let settings = Settings {
+            shuffle: Some(Shuffle::from_seed(7)),
+            ..settings()
+        };
+        let in_cache: Vec<Vec<u8>> = cache
+            .get((&DomainName::from_static("codecrafters.io"), QType::A))
+            .unwrap()
+            .iter()
+            .map(|record| record.data().as_ref().to_vec())
+            .collect();
+
+        let response = respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        let expected: Vec<_> = [1, 2, 3, 0].map(|i| in_cache[i].clone()).into();
+        assert_eq!(answer_data(&packet), expected);
+    }
+
+    #[tokio::test]
+    async fn nodata_in_local_zone_has_soa_authority() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 9)).await,
+            local_zones: vec!["example.com".parse().unwrap()],
+            ..settings()
+        };
+        // MNAME and RNAME at the root, then SERIAL, REFRESH, RETRY, EXPIRE and MINIMUM.
+        #[rustfmt::skip]
+        let soa_data = [0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5];
+        let soa = ResourceData::Generic {
+            typ: proto::Type::SOA,
+            class: proto::Class::IN,
+            ttl: 3600,
+            data: Arc::from(&soa_data[..]),
+        };
+        let cache = seeded_cache(vec![
+            (DomainName::from_static("example.com"), soa),
+            (
+                DomainName::from_static("www.example.com"),
+                ResourceData::A {
+                    ttl: 60,
+                    addr: Ipv4Addr::new(10, 0, 0, 1),
+                },
+            ),
+        ])
+        .await;
+
+        let response = respond(query("www.example.com", QType::AAAA), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().response_code().as_u8(), 0);
+        assert_eq!(packet.header().answer_entries(), 0);
+        let authority: Vec<_> = packet.authority().collect();
+        assert_eq!(authority.len(), 1);
+        assert_eq!(authority[0].typ(), proto::Type::SOA);
+        assert_eq!(authority[0].name().to_string(), "example.com.");
+    }
+
+    /// A cache with an SOA of example.com and A records of `hosts` names below it.
+    async fn zone_cache(hosts: u32) -> EVCache {
+        #[rustfmt::skip]
+        let soa_data = [0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5];
+        let mut records = vec![(
+            DomainName::from_static("example.com"),
+            ResourceData::Generic {
+                typ: proto::Type::SOA,
+                class: proto::Class::IN,
+                ttl: 3600,
+                data: Arc::from(&soa_data[..]),
+            },
+        )];
+        for i in 0..hosts {
+            records.push((
+                format!("host{i}.example.com").parse().unwrap(),
+                ResourceData::A {
+                    ttl: 60,
+                    addr: Ipv4Addr::from(0x0a00_0000 + i),
+                },
+            ));
+        }
+        seeded_cache(records).await
+    }
+
+    /// Handle `request` over TCP and stream the zone transfer it is accepted as. Returns the
+    /// response when it isn't, or the length-prefixed messages of the transfer.
+    async fn transfer(request: &mut ArrayBuffer, settings: &Settings, cache: &EVCache) -> Vec<u8> {
+        let mut response = ArrayBuffer::new();
+        let transfer = handle_dns_packet(
+            request,
+            &mut response,
+            Transport::Tcp,
+            CLIENT,
+            settings,
+            cache,
+        )
+        .await;
+        let Some(transfer) = transfer else {
+            return response.as_slice().to_vec();
+        };
+        let packet = proto::Packet::parse(request, 0).unwrap().unwrap();
+        let mut stream = Vec::new();
+        transfer_zone(&mut stream, &packet, transfer, cache)
+            .await
+            .unwrap();
+        stream
+    }
+
+    /// Split a stream of length-prefixed messages.
+    fn split_messages(mut stream: &[u8]) -> Vec<&[u8]> {
+        let mut messages = Vec::new();
+        while !stream.is_empty() {
+            let len = u16::from_be_bytes([stream[0], stream[1]]) as usize;
+            messages.push(&stream[2..2 + len]);
+            stream = &stream[2 + len..];
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn zone_transfer_is_streamed_in_batches() {
+        let cache = zone_cache(3000).await;
+        let settings = Settings {
+            local_zones: vec!["example.com".parse().unwrap()],
+            ..settings()
+        };
+
+        let mut request = ArrayBuffer::new();
+        query("example.com", QType::AXFR).build_into(&mut request);
+        let stream = transfer(&mut request, &settings, &cache).await;
+
+        let messages = split_messages(&stream);
+        let mut types = Vec::new();
+        for message in &messages {
+            let message = proto::Packet::parse(message, 0).unwrap().unwrap();
+            assert!(!message.header().truncated());
+            types.extend(message.answers().map(|answer| answer.typ()));
+        }
+        // The records don't fit in a single message, so they are split over several.
+        assert!(messages.len() > 1, "{} messages", messages.len());
+        assert_eq!(types.len(), 3002);
+        assert_eq!(types[0], proto::Type::SOA);
+        assert_eq!(types[3001], proto::Type::SOA);
+        assert!(types[1..3001].iter().all(|typ| *typ == proto::Type::A));
+    }
+
+    /// A primary of example.com, with serial 7 and a refresh interval of an hour, that answers a
+    /// single zone transfer with `answers` between the SOAs.
+    async fn stub_primary(answers: Vec<Resource>) -> SocketAddr {
+        #[rustfmt::skip]
+        let soa_data = [0, 0, 0, 0, 0, 7, 0, 0, 14, 16, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5];
+        let soa = Resource(
+            DomainName::from_static("example.com"),
+            Arc::new(ResourceData::Generic {
+                typ: proto::Type::SOA,
+                class: proto::Class::IN,
+                ttl: 3600,
+                data: Arc::from(&soa_data[..]),
+            }),
+        );
+        let primary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_addr = primary.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::AsyncReadExt;
+
+            let (mut stream, _) = primary.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap();
+            let mut query = vec![0; len as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            let query = proto::Packet::parse(&query, 0).unwrap().unwrap();
+            assert_eq!(query.questions().next().unwrap().q_type(), QType::AXFR);
+
+            // The zone is sent in two messages, ending with the SOA again.
+            let first = answers.into_iter().fold(
+                DNSPacketBuilder::respond(&query, ResponseCode::None)
+                    .add_answer(Resource(soa.0.clone(), Arc::clone(&soa.1))),
+                |builder, answer| builder.add_answer(answer),
+            );
+            for message in [
+                first,
+                DNSPacketBuilder::respond(&query, ResponseCode::None).add_answer(soa),
+            ] {
+                let mut buffer = ArrayBuffer::new();
+                message.build_into(&mut buffer);
+                stream
+                    .write_all(&(buffer.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(buffer.as_slice()).await.unwrap();
+            }
+        });
+        primary_addr
+    }
+
+    #[tokio::test]
+    async fn secondary_answers_from_transferred_zone() {
+        let www = DomainName::from_static("www.example.com");
+        let primary_addr = stub_primary(vec![
+            Resource(
+                www.clone(),
+                Arc::new(ResourceData::A {
+                    ttl: 60,
+                    addr: Ipv4Addr::new(10, 0, 0, 1),
+                }),
+            ),
+            // The target is compressed against the owner of the A record.
+            Resource(
+                DomainName::from_static("alias.example.com"),
+                Arc::new(ResourceData::Generic {
+                    typ: proto::Type::CNAME,
+                    class: proto::Class::IN,
+                    ttl: 60,
+                    data: Arc::from(www.to_bytes()),
+                }),
+            ),
+        ])
+        .await;
+
+        let secondary: Secondary = format!("example.com@{primary_addr}").parse().unwrap();
+        let settings = Settings {
+            policy: ForwardPolicy::ForwardOnly,
+            secondaries: vec![secondary.clone()],
+            ..settings()
+        };
+        // Until transferred, the zone is not answered for.
+        assert!(!settings.is_local(&www));
+
+        let cache = seeded_cache(Vec::new()).await;
+        let soa = secondary
+            .load(&cache, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(soa.serial, 7);
+        assert_eq!(soa.refresh, 3600);
+        assert!(settings.is_local(&www));
+
+        let response = respond(query("www.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert!(packet.header().authoritive_answer());
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+
+        let response = respond(query("alias.example.com", QType::CNAME), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        let answer = packet.answers().next().unwrap();
+        let target = answer.data_cursor().read_name().unwrap();
+        assert_eq!(target.to_string(), "www.example.com.");
+    }
+
+    #[tokio::test]
+    async fn secondary_ignores_records_outside_the_zone() {
+        let address = |name| {
+            Resource(
+                DomainName::from_static(name),
+                Arc::new(ResourceData::A {
+                    ttl: 60,
+                    addr: Ipv4Addr::new(10, 0, 0, 1),
+                }),
+            )
+        };
+        let primary_addr = stub_primary(vec![
+            address("www.example.com"),
+            address("google.com"),
+            address("notexample.com"),
+        ])
+        .await;
+
+        let secondary: Secondary = format!("example.com@{primary_addr}").parse().unwrap();
+        let cache = seeded_cache(Vec::new()).await;
+        secondary
+            .load(&cache, Duration::from_secs(2))
+            .await
+            .unwrap();
+        let www = DomainName::from_static("www.example.com");
+        assert!(cache.get((&www, proto::Type::A)).is_some());
+        for name in ["google.com", "notexample.com"] {
+            let name = DomainName::from_static(name);
+            assert!(cache.get((&name, proto::Type::A)).is_none(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_shift_to_backup_when_resolver_is_down() {
+        // Receives queries but never responds.
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let settings = Settings {
+            resolver: silent.local_addr().unwrap().into(),
+            backup_resolvers: vec![stub_upstream(Ipv4Addr::new(10, 0, 0, 2)).await],
+            upstream_timeout: Duration::from_millis(100),
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let response = respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.answers().count(), 0);
+
+        for probe in 1..=upstream::FAILED_PROBES_UNTIL_DOWN {
+            let healthy = settings
+                .upstreams
+                .probe(&settings.resolver, settings.upstream_timeout)
+                .await;
+            assert_eq!(healthy, probe < upstream::FAILED_PROBES_UNTIL_DOWN);
+        }
+        assert!(settings
+            .upstreams
+            .health()
+            .contains(&(settings.resolver.clone(), false)));
+
+        let response = respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 2]]);
+    }
+
+    /// Answers every question with an A record of `addr` and counts the queries it answered.
+    struct MockUpstream {
+        addr: Ipv4Addr,
+        queries: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Upstream for MockUpstream {
+        async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError> {
+            self.queries
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let packet = proto::Packet::parse(msg, 0)?.unwrap();
+            let mut builder = DNSPacketBuilder::respond(&packet, ResponseCode::None);
+            for q in packet.questions() {
+                let name: DomainName = (&q.name()).into();
+                builder = builder.add_question(q.into()).add_answer(Resource(
+                    name,
+                    Arc::new(ResourceData::A {
+                        ttl: 60,
+                        addr: self.addr,
+                    }),
+                ));
+            }
+            let mut response = ArrayBuffer::new();
+            builder.build_into(&mut response);
+            Ok(response.as_slice().to_vec())
+        }
+    }
+
+    /// Answers like [MockUpstream] after a delay, tracking the most queries it had at once.
+    struct SlowUpstream {
+        answer: MockUpstream,
+        current: std::sync::atomic::AtomicUsize,
+        most: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Upstream for SlowUpstream {
+        async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError> {
+            use std::sync::atomic::Ordering;
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            self.answer.query(msg).await
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn forwards_in_flight_stay_within_the_limit() {
+        let settings = Arc::new(Settings {
+            resolver: "https://resolver.test/dns-query".parse().unwrap(),
+            inflight: tokio::sync::Semaphore::new(3),
+            ..settings()
+        });
+        let upstream = Arc::new(SlowUpstream {
+            answer: MockUpstream {
+                addr: Ipv4Addr::new(10, 0, 0, 7),
+                queries: Default::default(),
+            },
+            current: Default::default(),
+            most: Default::default(),
+        });
+        settings.upstreams.insert(
+            settings.resolver.clone(),
+            Arc::clone(&upstream) as Arc<dyn Upstream>,
+        );
+        let cache = seeded_cache(Vec::new()).await;
+
+        let requests: Vec<_> = (0..20)
+            .map(|_| {
+                let (settings, cache) = (Arc::clone(&settings), cache.clone());
+                tokio::spawn(async move {
+                    respond(query("codecrafters.io", QType::A), &settings, &cache).await
+                })
+            })
+            .collect();
+        for request in requests {
+            let response = request.await.unwrap();
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            assert_eq!(answer_data(&packet), [[10, 0, 0, 7]]);
+        }
+        let most = upstream.most.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(most <= 3, "{most} queries were in flight at once");
+        assert_eq!(
+            upstream
+                .answer
+                .queries
+                .load(std::sync::atomic::Ordering::SeqCst),
+            20
+        );
+    }
+
+    #[tokio::test]
+    async fn dns64_synthesizes_aaaa_from_a_records() {
+        let settings = Settings {
+            resolver: "https://resolver.test/dns-query".parse().unwrap(),
+            dns64: Some("2001:db8:64::/96".parse().unwrap()),
+            ..settings()
+        };
+        let mock = Arc::new(MockUpstream {
+            addr: Ipv4Addr::new(10, 0, 0, 7),
+            queries: Default::default(),
+        });
+        settings.upstreams.insert(
+            settings.resolver.clone(),
+            Arc::clone(&mock) as Arc<dyn Upstream>,
+        );
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("v4only.example.com"),
+            ResourceData::A {
+                ttl: 60,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+        .await;
+
+        for (name, addr) in [
+            ("v4only.example.com", "2001:db8:64::c000:201"),
+            ("codecrafters.io", "2001:db8:64::a00:7"),
+        ] {
+            let response = respond(query(name, QType::AAAA), &settings, &cache).await;
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            let answers: Vec<_> = packet.answers().collect();
+            assert_eq!(answers.len(), 1, "{name}");
+            assert_eq!(answers[0].typ(), proto::Type::AAAA);
+            let addr: Ipv6Addr = addr.parse().unwrap();
+            assert_eq!(answers[0].data().unwrap(), addr.octets());
+        }
+        assert!("64:ff9b::/64".parse::<Nat64Prefix>().is_err());
+        assert!("64:ff9b::1/96".parse::<Nat64Prefix>().is_err());
+    }
+
+    #[tokio::test]
+    async fn queries_to_encrypted_resolvers_are_padded() {
+        /// Records the size of every query before answering like [MockUpstream].
+        struct SizeUpstream(MockUpstream, std::sync::Mutex<Vec<usize>>);
+
+        #[async_trait::async_trait]
+        impl Upstream for SizeUpstream {
+            async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError> {
+                self.1.lock().unwrap().push(msg.len());
+                self.0.query(msg).await
+            }
+        }
+
+        let settings = Settings {
+            resolver: "https://resolver.test/dns-query".parse().unwrap(),
+            edns_padding: Some(128),
+            forward_rules: vec!["plain.test=udp://192.0.2.53".parse().unwrap()],
+            ..settings()
+        };
+        let upstream = Arc::new(SizeUpstream(
+            MockUpstream {
+                addr: Ipv4Addr::new(10, 0, 0, 7),
+                queries: Default::default(),
+            },
+            Default::default(),
+        ));
+        for resolver in [&settings.resolver, &settings.forward_rules[0].resolver] {
+            settings
+                .upstreams
+                .insert(resolver.clone(), Arc::clone(&upstream) as Arc<dyn Upstream>);
+        }
+        let cache = seeded_cache(Vec::new()).await;
+        respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        respond(query("www.plain.test", QType::A), &settings, &cache).await;
+
+        let sizes = upstream.1.lock().unwrap().clone();
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(sizes[0], 128);
+        // Queries over plain UDP are not padded, as anyone on the path can read them anyway.
+        assert!(sizes[1] < 128);
+    }
+
+    #[tokio::test]
+    async fn forwards_through_plugged_in_upstream() {
+        let settings = Settings {
+            resolver: "https://resolver.test/dns-query".parse().unwrap(),
+            ..settings()
+        };
+        let mock = Arc::new(MockUpstream {
+            addr: Ipv4Addr::new(10, 0, 0, 7),
+            queries: Default::default(),
+        });
+        settings.upstreams.insert(
+            settings.resolver.clone(),
+            Arc::clone(&mock) as Arc<dyn Upstream>,
+        );
+        let cache = seeded_cache(Vec::new()).await;
+        let response = respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 7]]);
+        assert_eq!(mock.queries.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn questions_are_counted_by_type() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            ..settings()
+        };
+        let cache = many_records_cache().await;
+        for q_type in [QType::A, QType::AAAA, QType::A] {
+            respond(query("many.example.com", q_type), &settings, &cache).await;
+        }
+        assert_eq!(settings.stats.count(QType::A), 2);
+        assert_eq!(settings.stats.count(QType::AAAA), 1);
+        assert_eq!(settings.stats.count(QType::MX), 0);
+        assert_eq!(settings.stats.counts(), [(QType::A, 2), (QType::AAAA, 1)]);
+    }
+
+    #[tokio::test]
+    async fn answers_keep_the_class_of_the_question() {
+        let name = DomainName::from_static("version.bind");
+        let txt = |class, text: &[u8]| ResourceData::Generic {
+            typ: proto::Type::TXT,
+            class,
+            ttl: 0,
+            data: Arc::from([&[text.len() as u8][..], text].concat()),
+        };
+        let cache = seeded_cache(vec![
+            (name.clone(), txt(proto::Class::CH, b"chaos")),
+            (name.clone(), txt(proto::Class::IN, b"internet")),
+            (
+                name.clone(),
+                ResourceData::from_parts(proto::Type::A, proto::Class::CH, 60, &[0, 1, 2, 3]),
+            ),
+        ])
+        .await;
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            ..settings()
+        };
+
+        for (q_type, data) in [(QType::TXT, &b"\x05chaos"[..]), (QType::A, &[0, 1, 2, 3])] {
+            let request = DNSPacketBuilder::query(1).add_question(Question::new(
+                q_type,
+                proto::QClass::CH,
+                name.clone(),
+            ));
+            let response = respond(request, &settings, &cache).await;
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            let answers: Vec<_> = packet.answers().collect();
+            assert_eq!(answers.len(), 1, "{q_type:?}");
+            assert_eq!(answers[0].class(), proto::Class::CH);
+            assert_eq!(answers[0].data().unwrap(), data);
+        }
+    }
+
+    #[tokio::test]
+    async fn class_any_matches_records_of_every_class() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let settings = Settings {
+            resolver,
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("cached.example.com"),
+            ResourceData::A {
+                ttl: 60,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+        .await;
+        let request = DNSPacketBuilder::query(1).add_question(Question::new(
+            QType::A,
+            proto::QClass::Any,
+            DomainName::from_static("cached.example.com"),
+        ));
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[192, 0, 2, 1]]);
+        assert_eq!(packet.answers().next().unwrap().class(), proto::Class::IN);
+        assert!(queries.try_recv().is_err(), "Forwarded a cached name");
+    }
+
+    #[tokio::test]
+    async fn cname_is_followed_to_records_of_its_target() {
+        let target = DomainName::from_static("example.com");
+        let a = |last| ResourceData::A {
+            ttl: 60,
+            addr: Ipv4Addr::new(10, 0, 0, last),
+        };
+        let cache = seeded_cache(vec![
+            (
+                DomainName::from_static("www.example.com"),
+                ResourceData::Generic {
+                    typ: proto::Type::CNAME,
+                    class: proto::Class::IN,
+                    ttl: 60,
+                    data: Arc::from(target.to_bytes()),
+                },
+            ),
+            (target.clone(), a(1)),
+            (target.clone(), a(2)),
+        ])
+        .await;
+        let settings = Settings {
+            policy: ForwardPolicy::ForwardOnly,
+            local_zones: vec![target],
+            ..settings()
+        };
+        let response = respond(query("www.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+
+        let answers: Vec<_> = packet
+            .answers()
+            .map(|answer| (answer.name().to_string(), answer.typ()))
+            .collect();
+        assert_eq!(
+            answers,
+            [
+                ("www.example.com.".to_owned(), proto::Type::CNAME),
+                ("example.com.".to_owned(), proto::Type::A),
+                ("example.com.".to_owned(), proto::Type::A),
+            ]
+        );
+        let mut addrs = answer_data(&packet)[1..].to_vec();
+        addrs.sort();
+        assert_eq!(addrs, [[10, 0, 0, 1], [10, 0, 0, 2]]);
+        // Every owner name and the CNAME target are pointers into the question.
+        for answer in packet.answers() {
+            assert_eq!(answer.size_in_packet() - answer.data_len() - 10, 2);
+        }
+        let cname = packet.answers().next().unwrap();
+        assert_eq!(cname.data_len(), 2);
+        assert_eq!(
+            cname.data_cursor().read_name().unwrap().to_string(),
+            "example.com."
+        );
+    }
+
+    #[tokio::test]
+    async fn nodata_and_forwarded_questions_are_echoed_once() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            local_zones: vec![DomainName::from_static("example.com")],
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("www.example.com"),
+            ResourceData::A {
+                ttl: 60,
+                addr: Ipv4Addr::new(10, 0, 0, 2),
+            },
+        )])
+        .await;
+        let request = query("www.example.com", QType::AAAA).add_question(Question::new(
+            QType::A,
+            proto::QClass::IN,
+            DomainName::from_static("codecrafters.io"),
+        ));
+        let response = respond(request, &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+
+        let questions: Vec<_> = packet
+            .questions()
+            .map(|q| (q.name().to_string(), q.q_type()))
+            .collect();
+        assert_eq!(
+            questions,
+            [
+                ("www.example.com.".to_owned(), QType::AAAA),
+                ("codecrafters.io.".to_owned(), QType::A),
+            ]
+        );
+        assert_eq!(packet.header().question_entries(), 2);
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+    }
+
+    #[tokio::test]
+    async fn delayed_responses_do_not_hold_up_other_requests() {
+        // Long enough for a whole delay of slack on a busy machine.
+        let delay = Duration::from_secs(1);
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            response_delay: Some(delay),
+            ..settings()
+        };
+        let server = start_server(settings, seeded_cache(Vec::new()).await, 1).await;
+
+        let mut buf = ArrayBuffer::new();
+        query("www.example.com", QType::A).build_into(&mut buf);
+        let start = tokio::time::Instant::now();
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            client.connect(server).await.unwrap();
+            client.send(&buf).await.unwrap();
+            clients.push(client);
+        }
+        for client in clients {
+            let mut response = [0; 512];
+            client.recv(&mut response).await.unwrap();
+            assert!(
+                start.elapsed() >= delay,
+                "Responded after {:?}",
+                start.elapsed()
+            );
+        }
+        // With a single handler, waiting in it would take one delay per request.
+        assert!(
+            start.elapsed() < delay * 2,
+            "Responded after {:?}",
+            start.elapsed()
+        );
+    }
+
+    #[tokio::test]
+    async fn answer_ttl_override_replaces_every_ttl() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            answer_ttl_override: Some(5),
+            ..settings()
+        };
+        let cache = seeded_cache(vec![
+            (
+                DomainName::from_static("cached.example.com"),
+                ResourceData::A {
+                    ttl: 300,
+                    addr: Ipv4Addr::new(192, 0, 2, 1),
+                },
+            ),
+            (
+                DomainName::from_static("cached.example.com"),
+                ResourceData::A {
+                    ttl: 30,
+                    addr: Ipv4Addr::new(192, 0, 2, 2),
+                },
+            ),
+        ])
+        .await;
+        for (name, answers) in [("cached.example.com", 2), ("codecrafters.io", 1)] {
+            let response = respond(query(name, QType::A), &settings, &cache).await;
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            let ttls: Vec<_> = packet.answers().map(|answer| answer.ttl()).collect();
+            assert_eq!(ttls, vec![5; answers], "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn retransmission_is_not_forwarded_again() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        // The delay keeps the first copy of the request pending while it is retransmitted, with
+        // plenty of slack for a busy machine.
+        let delay = Duration::from_secs(1);
+        let settings = Settings {
+            resolver,
+            response_delay: Some(delay),
+            ..settings()
+        };
+        let server = start_server(settings, seeded_cache(Vec::new()).await, 2).await;
+
+        let mut buf = ArrayBuffer::new();
+        query("www.example.com", QType::A).build_into(&mut buf);
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server).await.unwrap();
+        client.send(&buf).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.send(&buf).await.unwrap();
+
+        let mut response = [0; 512];
+        client.recv(&mut response).await.unwrap();
+        let second = tokio::time::timeout(delay, client.recv(&mut response)).await;
+        assert!(second.is_err(), "Answered the retransmission separately");
+        assert!(queries.try_recv().is_ok());
+        assert!(queries.try_recv().is_err(), "Forwarded the retransmission");
+
+        // Once answered, the same request is a new one.
+        client.send(&buf).await.unwrap();
+        client.recv(&mut response).await.unwrap();
+        assert!(queries.try_recv().is_ok());
+    }
+
+    /// Logs written on this thread while the guard is held.
+    #[derive(Clone, Default)]
+    struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Logs {
+        fn capture() -> (Self, tracing::subscriber::DefaultGuard) {
+            let logs = Logs::default();
+            let subscriber = tracing_subscriber::fmt()
+                .with_max_level(Level::DEBUG)
+                .with_ansi(false)
+                .with_writer({
+                    let logs = logs.clone();
+                    move || logs.clone()
+                })
+                .finish();
+            (logs, tracing::subscriber::set_default(subscriber))
+        }
+
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_packets_are_logged_with_a_hex_dump() {
+        let (logs, _guard) = Logs::capture();
+
+        let settings = Settings {
+            log_bad_packets: true,
+            ..settings()
+        };
+        let cache = seeded_cache(Vec::new()).await;
+        let mut request = ArrayBuffer::new();
+        query("www.example.com", QType::A).build_into(&mut request);
+        // The question ends in the middle of its name.
+        let mut buf = ArrayBuffer::from(&request[..20]);
+        let mut response = ArrayBuffer::new();
+        let span = tracing::info_span!("dns_request", source = %CLIENT);
+        handle_dns_packet(
+            &mut buf,
+            &mut response,
+            Transport::Udp,
+            CLIENT,
+            &settings,
+            &cache,
+        )
+        .instrument(span)
+        .await;
+
+        let logs = logs.take();
+        assert!(logs.contains(&format!("{buf:b}")), "{logs}");
+        assert!(logs.contains("source=192.0.2.1:5353"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn dry_run_sends_nothing_back() {
+        let (resolver, mut queries) = recording_stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let settings = Settings {
+            resolver,
+            dry_run: true,
+            ..settings()
+        };
+        let server = start_server(settings, seeded_cache(Vec::new()).await, 1).await;
+
+        let mut buf = ArrayBuffer::new();
+        query("www.example.com", QType::A).build_into(&mut buf);
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server).await.unwrap();
+        client.send(&buf).await.unwrap();
+        // A request that cannot be parsed is not answered with an error either.
+        client
+            .send(&[4, 210, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 9])
+            .await
+            .unwrap();
+
+        let mut response = [0; 512];
+        let received =
+            tokio::time::timeout(Duration::from_millis(200), client.recv(&mut response)).await;
+        assert!(received.is_err(), "Received a response in dry-run mode");
+        assert!(
+            queries.try_recv().is_err(),
+            "Forwarded a query in dry-run mode"
+        );
+    }
+
+    #[test]
+    fn message_file_reports_malformed_messages() {
+        let mut buf = ArrayBuffer::new();
+        query("www.example.com", QType::A).build_into(&mut buf);
+        let good: String = buf.iter().map(|byte| format!("{byte:02x}")).collect();
+        let file = format!("{good}\n04d2 0100 0001 0000 0000 0000 09\n");
+
+        let output = parse_messages(file.as_bytes());
+        let (first, second) = output.split_once(";; Message 2").unwrap();
+        assert!(first.contains(";www.example.com. IN A"), "{first}");
+        assert!(second.contains("Failed to parse message"), "{second}");
+
+        let mut framed = (buf.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&buf);
+        framed.extend_from_slice(&[0, 20, 1]);
+        let messages = read_messages(&framed);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].as_deref().ok(), Some(buf.as_slice()));
+        assert_eq!(messages[1], Err(MessageFileError::CutOff(2)));
+    }
+
+    #[tokio::test]
+    async fn client_cookie_is_answered_with_a_server_cookie() {
+        let settings = settings();
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("cached.example.com"),
+            ResourceData::A {
+                ttl: 300,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+        .await;
+        let client = *b"client01";
+        let request = query("cached.example.com", QType::A).with_edns(Edns {
+            options: vec![EdnsOption::Cookie {
+                client,
+                server: Arc::from([].as_slice()),
+            }],
+            ..Edns::default()
+        });
+        let response = respond(request, &settings, &cache).await;
+
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.answers().count(), 1);
+        let cookie = packet.edns().unwrap().cookie().unwrap().unwrap();
+        assert_eq!(cookie.client, client);
+        assert_eq!(cookie.server.len(), 16);
+        assert!(settings
+            .cookies
+            .is_valid(client, cookie.server, CLIENT.ip(), cookie::now()));
+    }
+
+    #[tokio::test]
+    async fn server_cookies_are_validated() {
+        let settings = settings();
+        let cache = seeded_cache(Vec::new()).await;
+        let client = *b"client01";
+        let cookie_request = |server: &[u8]| {
+            query("localhost", QType::A).with_edns(Edns {
+                options: vec![EdnsOption::Cookie {
+                    client,
+                    server: Arc::from(server),
+                }],
+                ..Edns::default()
+            })
+        };
+        let now = cookie::now();
+
+        // A valid cookie is returned as is while it is recent.
+        let valid = settings.cookies.generate(client, CLIENT.ip(), now - 600);
+        let response = respond(cookie_request(&valid), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        let cookie = packet.edns().unwrap().cookie().unwrap().unwrap();
+        assert_eq!(cookie.server, valid);
+
+        // An invalid cookie is replaced with a new one.
+        let invalid = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let response = respond(cookie_request(&invalid), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        let cookie = packet.edns().unwrap().cookie().unwrap().unwrap();
+        assert_ne!(cookie.server, invalid);
+        assert!(settings
+            .cookies
+            .is_valid(client, cookie.server, CLIENT.ip(), now));
+
+        // A server cookie must be 8 to 32 bytes long (RFC 7873 section 4).
+        let response = respond(cookie_request(&[1, 2, 3]), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::FormatError.as_u8()
+        );
+    }
+
+    #[tokio::test]
+    async fn questions_are_logged_with_the_source_of_their_answer() {
+        let (logs, _guard) = Logs::capture();
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            ..settings()
+        };
+        let cache = seeded_cache(vec![(
+            DomainName::from_static("cached.example.com"),
+            ResourceData::A {
+                ttl: 300,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        )])
+        .await;
+
+        respond(query("cached.example.com", QType::A), &settings, &cache).await;
+        let logs_of_hit = logs.take();
+        assert!(
+            logs_of_hit.contains("answer_source=\"cache-hit\" answers=1"),
+            "{logs_of_hit}"
+        );
+
+        respond(query("codecrafters.io", QType::A), &settings, &cache).await;
+        let logs_of_forward = logs.take();
+        assert!(
+            logs_of_forward.contains("answer_source=\"forwarded\" answers=1"),
+            "{logs_of_forward}"
+        );
+
+        let request = query("codecrafters.io", QType::A).with_recursion_desired(false);
+        respond(request, &settings, &cache).await;
+        let logs_of_miss = logs.take();
+        assert!(
+            logs_of_miss.contains("answer_source=\"cache-miss\" answers=0"),
+            "{logs_of_miss}"
+        );
+    }
+
+    #[tokio::test]
+    async fn question_pointing_into_the_answers_is_a_format_error() {
+        let cache = seeded_cache(Vec::new()).await;
+        #[rustfmt::skip]
+        let mut buf = ArrayBuffer::from(&[
+            4, 210, 1, 0, 0, 1, 0, 1, 0, 0, 0, 0,
+            // www followed by a pointer to the owner name of the answer.
+            3, b'w', b'w', b'w', 0xc0, 22, 0, 1, 0, 1,
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+            0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1,
+        ][..]);
+        let mut response = ArrayBuffer::new();
+        handle_dns_packet(
+            &mut buf,
+            &mut response,
+            Transport::Udp,
+            CLIENT,
+            &settings(),
+            &cache,
+        )
+        .await;
+
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().id(), 1234);
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::FormatError.as_u8()
+        );
+    }
+
+    #[tokio::test]
+    async fn delegated_child_zone_is_referred_to() {
+        let settings = Settings {
+            resolver: stub_upstream(Ipv4Addr::new(10, 0, 0, 1)).await,
+            local_zones: vec!["example.com".parse().unwrap()],
+            ..settings()
+        };
+        let child = DomainName::from_static("child.example.com");
+        let ns = |target: &'static str| ResourceData::Generic {
+            typ: proto::Type::NS,
+            class: proto::Class::IN,
+            ttl: 3600,
+            data: Arc::from(DomainName::from_static(target).to_bytes()),
+        };
+        let cache = seeded_cache(vec![
+            (child.clone(), ns("ns1.child.example.com")),
+            (child.clone(), ns("ns.example.net")),
+            (
+                DomainName::from_static("ns1.child.example.com"),
+                ResourceData::A {
+                    ttl: 3600,
+                    addr: Ipv4Addr::new(192, 0, 2, 53),
+                },
+            ),
+        ])
+        .await;
+
+        let response = respond(query("www.child.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(packet.header().response_code().as_u8(), 0);
+        assert!(!packet.header().authoritive_answer());
+        assert_eq!(packet.answers().count(), 0);
+        let authority: Vec<_> = packet.authority().collect();
+        assert_eq!(authority.len(), 2);
+        assert!(authority
+            .iter()
+            .all(|ns| ns.typ() == proto::Type::NS && child.equals(&ns.name())));
+        let glue: Vec<_> = packet.additional().collect();
+        assert_eq!(glue.len(), 1);
+        assert_eq!(glue[0].name().to_string(), "ns1.child.example.com.");
+        assert_eq!(glue[0].data().unwrap(), [192, 0, 2, 53]);
+    }
+
+    #[tokio::test]
+    async fn answers_past_the_cap_are_left_out_over_udp() {
+        let settings = Settings {
+            max_answer_records: Some(3),
+            ..settings()
+        };
+        let name = DomainName::from_static("many.example.com");
+        let records = (1..=5)
+            .map(|host| {
+                let addr = Ipv4Addr::new(192, 0, 2, host);
+                (name.clone(), ResourceData::A { ttl: 60, addr })
+            })
+            .collect();
+        let cache = seeded_cache(records).await;
+
+        let response = respond(query("many.example.com", QType::A), &settings, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert!(packet.header().truncated());
+        assert_eq!(packet.answers().count(), 3);
+
+        let mut buf = ArrayBuffer::new();
+        query("many.example.com", QType::A).build_into(&mut buf);
+        let mut response = ArrayBuffer::new();
+        handle_dns_packet(
+            &mut buf,
+            &mut response,
+            Transport::Tcp,
+            CLIENT,
+            &settings,
+            &cache,
+        )
+        .await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert!(!packet.header().truncated());
+        assert_eq!(packet.answers().count(), 5);
+    }
+
+    #[tokio::test]
+    async fn forwarded_authority_and_glue_are_kept() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = UpstreamSpec::from(socket.local_addr().unwrap());
+        let zone = DomainName::from_static("example.org");
+        let ns = DomainName::from_static("ns1.example.org");
+        tokio::spawn({
+            let (zone, ns) = (zone.clone(), ns.clone());
+            async move {
+                let mut buf = [0; 512];
+                while let Ok((size, source)) = socket.recv_from(&mut buf).await {
+                    let packet = proto::Packet::parse(&buf[..size], 0).unwrap().unwrap();
+                    let q = packet.questions().next().unwrap();
+                    let glue = |addr| Arc::new(ResourceData::A { ttl: 60, addr });
+                    let mut response = ArrayBuffer::new();
+                    DNSPacketBuilder::respond(&packet, ResponseCode::None)
+                        .add_question(q.into())
+                        .add_answer(Resource(
+                            (&q.name()).into(),
+                            glue(Ipv4Addr::new(10, 0, 0, 1)),
+                        ))
+                        .add_authority(Resource(
+                            zone.clone(),
+                            Arc::new(ResourceData::Generic {
+                                typ: proto::Type::NS,
+                                class: proto::Class::IN,
+                                ttl: 3600,
+                                data: Arc::from(ns.to_bytes()),
+                            }),
+                        ))
+                        .add_additional(Resource(ns.clone(), glue(Ipv4Addr::new(10, 0, 0, 53))))
+                        .build_into(&mut response);
+                    socket.send_to(&response, source).await.unwrap();
+                }
+            }
+        });
+        let cache = seeded_cache(Vec::new()).await;
+
+        // The records are only passed on when asked for.
+        let without = Settings {
+            resolver: resolver.clone(),
+            ..settings()
+        };
+        let response = respond(query("www.example.org", QType::A), &without, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+        assert_eq!(packet.authority().count(), 0);
+        assert_eq!(packet.additional().count(), 0);
+
+        let with = Settings {
+            resolver: resolver.clone(),
+            forward_authority: true,
+            ..settings()
+        };
+        let response = respond(query("www.example.org", QType::A), &with, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(answer_data(&packet), [[10, 0, 0, 1]]);
+        let authority: Vec<_> = packet.authority().collect();
+        assert_eq!(authority.len(), 1);
+        assert!(authority[0].typ() == proto::Type::NS && zone.equals(&authority[0].name()));
+        let additional: Vec<_> = packet.additional().collect();
+        assert_eq!(additional.len(), 1);
+        assert!(ns.equals(&additional[0].name()));
+        assert_eq!(additional[0].data().unwrap(), [10, 0, 0, 53]);
+
+        // A rewritten answer doesn't come with the records of the resolver.
+        let rewriting = Settings {
+            resolver,
+            forward_authority: true,
+            rewrite_rules: vec!["www.example.org=nxdomain".parse().unwrap()],
+            ..settings()
+        };
+        let response = respond(query("www.example.org", QType::A), &rewriting, &cache).await;
+        let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            packet.header().response_code().as_u8(),
+            ResponseCode::NameError.as_u8()
+        );
+        assert_eq!(packet.authority().count(), 0);
+        assert_eq!(packet.additional().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn dry_run_does_not_transfer_zones() {
+        let cache = zone_cache(10).await;
+        let settings = Settings {
+            local_zones: vec!["example.com".parse().unwrap()],
+            dry_run: true,
+            ..settings()
+        };
+
+        let mut request = ArrayBuffer::new();
+        query("example.com", QType::AXFR).build_into(&mut request);
+        let stream = transfer(&mut request, &settings, &cache).await;
+        assert!(
+            stream.is_empty(),
+            "Sent {} bytes in dry-run mode",
+            stream.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn zone_transfers_are_only_sent_to_key_holders() {
+        let key: TsigKey = "transfer-key:c2VjcmV0IGtleSBmb3IgdGVzdGluZw=="
+            .parse()
+            .unwrap();
+        let forged: TsigKey = "transfer-key:Zm9yZ2VkIGtleQ==".parse().unwrap();
+        let cache = zone_cache(3000).await;
+        let settings = Settings {
+            local_zones: vec!["example.com".parse().unwrap()],
+            tsig_keys: vec![key.clone()],
+            ..settings()
+        };
+        let signed_request = |key: Option<&TsigKey>| {
+            let mut request = ArrayBuffer::new();
+            query("example.com", QType::AXFR).build_into(&mut request);
+            if let Some(key) = key {
+                tsig::sign(&mut request, key, None, tsig::now()).unwrap();
+            }
+            request
+        };
+
+        let response = transfer(&mut signed_request(Some(&forged)), &settings, &cache).await;
+        let response = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            response.header().response_code().as_u8(),
+            ResponseCode::NotAuth.as_u8()
+        );
+        assert_eq!(response.header().answer_entries(), 0);
+
+        let response = transfer(&mut signed_request(None), &settings, &cache).await;
+        let response = proto::Packet::parse(&response, 0).unwrap().unwrap();
+        assert_eq!(
+            response.header().response_code().as_u8(),
+            ResponseCode::Refused.as_u8()
+        );
+        assert_eq!(response.header().answer_entries(), 0);
+
+        let stream = transfer(&mut signed_request(Some(&key)), &settings, &cache).await;
+        let messages = split_messages(&stream);
+        assert!(messages.len() > 1, "{} messages", messages.len());
+        let mut answers = 0;
+        for message in messages {
+            let message = proto::Packet::parse(message, 0).unwrap().unwrap();
+            answers += message.header().answer_entries();
+            let last = message.additional().last().unwrap();
+            assert_eq!(last.typ(), proto::Type::TSIG);
+        }
+        assert_eq!(answers, 3002);
+    }
+
+    /// Answers like [MockUpstream] with the ID of the query changed.
+    struct WrongIdUpstream(MockUpstream);
+
+    #[async_trait::async_trait]
+    impl Upstream for WrongIdUpstream {
+        async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError> {
+            let mut response = self.0.query(msg).await?;
+            response[0] ^= 0xff;
+            Ok(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn response_with_different_id_is_rejected() {
+        let (logs, _guard) = Logs::capture();
+        for combine_questions in [false, true] {
+            let settings = Settings {
+                resolver: "https://resolver.test/dns-query".parse().unwrap(),
+                combine_questions,
+                ..settings()
+            };
+            settings.upstreams.insert(
+                settings.resolver.clone(),
+                Arc::new(WrongIdUpstream(MockUpstream {
+                    addr: Ipv4Addr::new(10, 0, 0, 1),
+                    queries: Default::default(),
+                })),
+            );
+            let cache = seeded_cache(Vec::new()).await;
+
+            let request = query("codecrafters.io", QType::A).add_question(Question::new(
+                QType::AAAA,
+                proto::QClass::IN,
+                DomainName::from_static("codecrafters.io"),
+            ));
+            let response = respond(request, &settings, &cache).await;
+            let packet = proto::Packet::parse(&response, 0).unwrap().unwrap();
+            assert_eq!(
+                packet.header().response_code().as_u8(),
+                ResponseCode::ServerFailure.as_u8()
+            );
+            let logs = logs.take();
+            assert!(
+                logs.contains("error=\"Invalid response from the resolver\""),
+                "{logs}"
+            );
+        }
+    }
+}
@@ -1,21 +1,41 @@
-use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
 
 use bytes::BufMut;
+use thiserror::Error;
 
 use crate::{
     array_buffer::ArrayBuffer,
     domain_name::DomainName,
+    edns::{Edns, EdnsOption},
     header::Header,
-    proto::{HeaderView, Opcode, PacketType, ResponseCode},
+    label::Label,
+    proto::{self, FromPacketBytes, HeaderView, Opcode, PacketType, ResponseCode, Type},
     question::Question,
-    resource::Resource,
+    resource::{Resource, ResourceData},
 };
 
 pub struct DNSPacketBuilder {
     header: Header,
     questions: Vec<Question>,
     answers: Vec<Resource>,
+    authorities: Vec<Resource>,
+    optional_authorities: Vec<Resource>,
+    additionals: Vec<Resource>,
+    edns: Option<Edns>,
     compress: bool,
+    sort_answers: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum FromPacketError {
+    #[error(transparent)]
+    Resource(#[from] proto::ResourceError),
+    #[error(transparent)]
+    Edns(#[from] proto::EdnsError),
 }
 
 impl DNSPacketBuilder {
@@ -23,17 +43,93 @@ impl DNSPacketBuilder {
         let mut header = Header::new(packet.header().id());
         header.opcode = packet.header().opcode();
         header.recursion_desired = packet.header().recursion_desired();
+        header.checking_disabled = packet.header().checking_disabled();
         header.packet_type = PacketType::Response;
         header.response_code = code;
 
+        // Responses to EDNS requests include an OPT record of their own.
+        let edns = packet.edns().map(|edns| Edns {
+            dnssec_ok: edns.dnssec_ok(),
+            ..Edns::default()
+        });
+
         Self {
             header,
             questions: Vec::new(),
             answers: Vec::new(),
+            authorities: Vec::new(),
+            optional_authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns,
             compress: true,
+            sort_answers: false,
         }
     }
 
+    /// A builder with the header, questions and records of `packet`, so a parsed message can be
+    /// changed and written again. The OPT record becomes the EDNS of the builder. Returns an
+    /// error when a record or EDNS option has malformed data.
+    pub fn from_packet(packet: &proto::Packet<'_>) -> Result<Self, FromPacketError> {
+        let view = packet.header();
+        let mut header = Header::new(view.id());
+        header.packet_type = view.packet_type();
+        header.opcode = view.opcode();
+        header.authoritive_answer = view.authoritive_answer();
+        header.truncated = view.truncated();
+        header.recursion_desired = view.recursion_desired();
+        header.recursion_available = view.recursion_available();
+        header.authentic_data = view.authentic_data();
+        header.checking_disabled = view.checking_disabled();
+        header.response_code = view.response_code();
+
+        let owned = |record: proto::Resource<'_>| {
+            ResourceData::try_from(record)
+                .map(|data| Resource(DomainName::from(&record.name()), Arc::new(data)))
+        };
+        let questions: Vec<Question> = packet.questions().map(Question::from).collect();
+        let answers = packet.answers().map(owned).collect::<Result<Vec<_>, _>>()?;
+        let authorities = packet
+            .authority()
+            .map(owned)
+            .collect::<Result<Vec<_>, _>>()?;
+        let additionals = packet
+            .additional()
+            .filter(|record| record.typ() != Type::OPT)
+            .map(owned)
+            .collect::<Result<Vec<_>, _>>()?;
+        let edns = match packet.edns() {
+            Some(view) => Some(Edns {
+                udp_payload_size: view.udp_payload_size(),
+                version: view.version(),
+                dnssec_ok: view.dnssec_ok(),
+                options: view.options()?.into_iter().map(EdnsOption::from).collect(),
+                padding: None,
+            }),
+            None => None,
+        };
+
+        header.question_entries = questions.len() as u16;
+        header.answer_entries = answers.len() as u16;
+        header.authority_entries = authorities.len() as u16;
+        header.additional_entries = additionals.len() as u16;
+        Ok(Self {
+            header,
+            questions,
+            answers,
+            authorities,
+            optional_authorities: Vec::new(),
+            additionals,
+            edns,
+            compress: true,
+            sort_answers: false,
+        })
+    }
+
+    /// The answers added so far, for changing them before building.
+    pub fn answers_mut(&mut self) -> &mut [Resource] {
+        &mut self.answers
+    }
+
     pub fn respond_to(header: HeaderView, code: ResponseCode) -> Self {
         let mut h = Header::new(header.id().unwrap_or_default());
         h.opcode = Opcode::Query;
@@ -45,7 +141,12 @@ impl DNSPacketBuilder {
             header: h,
             questions: Vec::new(),
             answers: Vec::new(),
+            authorities: Vec::new(),
+            optional_authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
             compress: true,
+            sort_answers: false,
         }
     }
 
@@ -58,8 +159,13 @@ impl DNSPacketBuilder {
         DNSPacketBuilder {
             header,
             compress: true,
+            sort_answers: false,
             questions: Vec::new(),
             answers: Vec::new(),
+            authorities: Vec::new(),
+            optional_authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
         }
     }
 
@@ -69,18 +175,152 @@ impl DNSPacketBuilder {
         self
     }
 
+    /// Add a record to the answer section. A record with the same name and data as an already
+    /// added record is skipped.
     pub fn add_answer(mut self, answer: Resource) -> Self {
+        if self
+            .answers
+            .iter()
+            .any(|Resource(name, data)| *name == answer.0 && *data == answer.1)
+        {
+            return self;
+        }
         self.answers.push(answer);
         self.header.answer_entries += 1;
         self
     }
 
-    pub fn build_into<'a>(self, buffer: &'a mut ArrayBuffer) {
+    pub fn add_authority(mut self, authority: Resource) -> Self {
+        self.authorities.push(authority);
+        self.header.authority_entries += 1;
+        self
+    }
+
+    /// Add an authority record that is left out when it doesn't fit, instead of truncating the
+    /// response. Written after the records of [Self::add_authority].
+    pub fn add_optional_authority(mut self, authority: Resource) -> Self {
+        self.optional_authorities.push(authority);
+        self.header.authority_entries += 1;
+        self
+    }
+
+    pub fn add_additional(mut self, additional: Resource) -> Self {
+        self.additionals.push(additional);
+        self.header.additional_entries += 1;
+        self
+    }
+
+    pub fn with_authoritative_answer(mut self, authoritative: bool) -> Self {
+        self.header.authoritive_answer = authoritative;
+        self
+    }
+
+    /// Set the RD bit, asking the server to resolve the query recursively. Queries have it set
+    /// by default.
+    pub fn with_recursion_desired(mut self, desired: bool) -> Self {
+        self.header.recursion_desired = desired;
+        self
+    }
+
+    /// Set the CD bit, accepting data the server has not verified with DNSSEC.
+    pub fn with_checking_disabled(mut self, disabled: bool) -> Self {
+        self.header.checking_disabled = disabled;
+        self
+    }
+
+    /// Set the RA bit, telling the client recursive queries are supported.
+    pub fn with_recursion_available(mut self, available: bool) -> Self {
+        self.header.recursion_available = available;
+        self
+    }
+
+    /// Set the TC bit, telling the client to retry over TCP for the whole response.
+    pub fn with_truncated(mut self, truncated: bool) -> Self {
+        self.header.truncated = truncated;
+        self
+    }
+
+    /// Sort the answers by type and then data when building, for output that does not depend on
+    /// the order the answers were added in.
+    pub fn with_sorted_answers(mut self, sort_answers: bool) -> Self {
+        self.sort_answers = sort_answers;
+        self
+    }
+
+    pub fn with_response_code(mut self, code: ResponseCode) -> Self {
+        self.header.response_code = code;
+        self
+    }
+
+    pub fn with_edns(mut self, edns: Edns) -> Self {
+        self.edns = Some(edns);
+        self
+    }
+
+    /// Set the DO bit of the OPT record, asking for DNSSEC records (RFC 3225). Adds an OPT record
+    /// with the default values if there is none yet.
+    pub fn with_dnssec_ok(mut self, dnssec_ok: bool) -> Self {
+        self.edns.get_or_insert_with(Edns::default).dnssec_ok = dnssec_ok;
+        self
+    }
+
+    /// Attach an extended DNS error (RFC 8914) to the OPT record. The error is only sent in
+    /// responses to EDNS requests.
+    pub fn with_extended_error(mut self, info_code: u16, extra_text: &str) -> Self {
+        if let Some(edns) = &mut self.edns {
+            edns.options.push(EdnsOption::ExtendedError {
+                info_code,
+                extra_text: Arc::from(extra_text),
+            });
+        }
+        self
+    }
+
+    /// Attach a client and server cookie (RFC 7873) to the OPT record. Like extended errors,
+    /// cookies are only sent in responses to EDNS requests.
+    pub fn with_cookie(mut self, client: [u8; 8], server: &[u8]) -> Self {
+        if let Some(edns) = &mut self.edns {
+            edns.options.push(EdnsOption::Cookie {
+                client,
+                server: Arc::from(server),
+            });
+        }
+        self
+    }
+
+    /// Pad the packet to a multiple of `block` bytes (RFC 7830). Like extended errors, padding
+    /// is only sent in responses to EDNS requests.
+    pub fn with_padding(mut self, block: u16) -> Self {
+        if let Some(edns) = &mut self.edns {
+            edns.padding = Some(block);
+        }
+        self
+    }
+
+    pub fn build_into<'a>(mut self, buffer: &'a mut ArrayBuffer) {
+        // An extended response code can only be carried with an OPT record.
+        let extended_response_code = self.header.response_code.extended_bits();
+        if extended_response_code != 0 && self.edns.is_none() {
+            self.edns = Some(Edns::default());
+        }
+        if self.edns.is_some() {
+            self.header.additional_entries += 1;
+        }
+
+        // The counts are written again once the sections are, as records that don't fit in the
+        // buffer are left out.
         self.header.write_into(buffer);
 
+        if self.sort_answers {
+            self.answers.sort_by(|Resource(_, a), Resource(_, b)| {
+                (a.typ().as_u16(), a.data().as_ref()).cmp(&(b.typ().as_u16(), b.data().as_ref()))
+            });
+        }
+
         let mut written_names: Vec<(u64, usize)> = Vec::new();
         //let mut truncate = false;
 
+        let mut questions = 0;
         for question in self.questions {
             let start = buffer.len();
             match write_name(buffer, question.name(), self.compress, &mut written_names) {
@@ -100,15 +340,73 @@ impl DNSPacketBuilder {
 
             buffer.put_u16(question.q_type().as_u16());
             buffer.put_u16(question.q_class().as_u16());
+            questions += 1;
         }
 
         /*truncate = truncate || */
-        write_resource_list(
+        let answers = write_resource_list(
             buffer,
             self.answers.into_iter(),
             self.compress,
+            true,
             &mut written_names,
         );
+        let mut truncated = answers < self.header.answer_entries;
+        let required_authorities = self.authorities.len() as u16;
+        let mut authorities = match truncated {
+            true => 0,
+            false => write_resource_list(
+                buffer,
+                self.authorities.into_iter(),
+                self.compress,
+                true,
+                &mut written_names,
+            ),
+        };
+        truncated = truncated || authorities < required_authorities;
+        if !truncated {
+            authorities += write_resource_list(
+                buffer,
+                self.optional_authorities.into_iter(),
+                self.compress,
+                false,
+                &mut written_names,
+            );
+        }
+        // Additional records are optional, so leaving some out doesn't truncate the response
+        // (RFC 2181 section 9).
+        let mut additionals = match truncated {
+            true => 0,
+            false => write_resource_list(
+                buffer,
+                self.additionals.into_iter(),
+                self.compress,
+                false,
+                &mut written_names,
+            ),
+        };
+
+        if let Some(mut edns) = self.edns {
+            if let Some(block) = edns.padding.filter(|&block| block > 0) {
+                // The option header counts towards the padded size too.
+                let unpadded = buffer.len() + edns.size_in_packet() + 4;
+                let len = (block as usize - unpadded % block as usize) % block as usize;
+                // Padding is left out rather than making the OPT record not fit.
+                if buffer.remaining_mut() >= edns.size_in_packet() + 4 + len {
+                    edns.options.push(EdnsOption::Padding(len as u16));
+                }
+            }
+            if buffer.remaining_mut() >= edns.size_in_packet() {
+                edns.write_into(buffer, extended_response_code);
+                additionals += 1;
+            }
+        }
+
+        let written = [questions, answers, authorities, additionals];
+        let counts = &mut buffer.as_slice_mut()[4..12];
+        for (count, written) in counts.chunks_exact_mut(2).zip(written) {
+            count.copy_from_slice(&written.to_be_bytes());
+        }
     }
 }
 
@@ -117,126 +415,578 @@ fn set_truncated(buffer: &mut ArrayBuffer, new_len: usize) {
     buffer.as_slice_mut()[2] |= 2;
 }
 
+/// Write the records of `iter` until one does not fit, setting the TC bit then if `truncate` is
+/// set. Returns the number of records written.
 fn write_resource_list(
     buffer: &mut ArrayBuffer,
     iter: impl Iterator<Item = Resource>,
     compress: bool,
+    truncate: bool,
     written_names: &mut Vec<(u64, usize)>,
-) -> bool {
+) -> u16 {
+    let mut written = 0;
     for Resource(name, data) in iter {
         let start = buffer.len();
 
         match write_name(buffer, &name, compress, written_names) {
             Ok(()) => {}
             Err(TooLong) => {
-                set_truncated(buffer, start);
-                return true;
+                cut_off(buffer, start, truncate);
+                return written;
             }
         };
 
-        let dat = data.data();
+        // A records make up most answers, so their octets are written straight from the address.
+        let (octets, cow);
+        let dat: &[u8] = match &*data {
+            ResourceData::A { addr, .. } => {
+                octets = addr.octets();
+                &octets
+            }
+            _ => {
+                cow = data.data();
+                cow.as_ref()
+            }
+        };
         if buffer.remaining_mut() < 10 + dat.len() {
-            set_truncated(buffer, start);
-            return true;
+            cut_off(buffer, start, truncate);
+            return written;
         }
 
         buffer.put_u16(data.typ().as_u16());
         buffer.put_u16(data.class().as_u16());
-        buffer.put_u32(*data.ttl());
-        buffer.put_u16(dat.len() as u16);
-        buffer.put_slice(dat.as_ref());
+        buffer.put_u32(data.ttl());
+        // The length is written after the data, as a compressed name makes it shorter than the
+        // data. The uncompressed data was checked to fit, so the name always does.
+        let rdlength = buffer.len();
+        buffer.put_u16(0);
+        match rdata_name(*data.typ(), dat).filter(|_| compress) {
+            Some((prefix, target)) => {
+                buffer.put_slice(&dat[..prefix]);
+                let _ = write_name(buffer, &target, compress, written_names);
+            }
+            None => buffer.put_slice(dat),
+        }
+        let rdata_len = (buffer.len() - rdlength - 2) as u16;
+        buffer.as_slice_mut()[rdlength..rdlength + 2].copy_from_slice(&rdata_len.to_be_bytes());
+        written += 1;
     }
 
-    return false;
+    written
+}
+
+/// Remove a record that did not fit from `buffer`.
+fn cut_off(buffer: &mut ArrayBuffer, start: usize, truncate: bool) {
+    match truncate {
+        true => set_truncated(buffer, start),
+        false => buffer.set_len(start),
+    }
 }
 
 struct TooLong;
 
+/// The name at the end of the data of the record types whose names may be compressed (RFC 3597),
+/// along with the length of the data before it.
+fn rdata_name(typ: Type, data: &[u8]) -> Option<(usize, DomainName)> {
+    let prefix = match typ {
+        Type::CNAME | Type::NS | Type::PTR => 0,
+        Type::MX => 2,
+        _ => return None,
+    };
+    let name = proto::DomainName::parse(data, prefix).ok()??;
+    if prefix + name.size_in_packet() != data.len() {
+        return None;
+    }
+    Some((prefix, DomainName::from(&name)))
+}
+
 fn write_name(
     buffer: &mut ArrayBuffer,
     domain_name: &DomainName,
     compress: bool,
     written_names: &mut Vec<(u64, usize)>,
 ) -> Result<(), TooLong> {
-    use std::hash::Hash;
-    let mut hasher = DefaultHasher::default();
-    domain_name.hash(&mut hasher);
-    let hash = hasher.finish();
+    // The offsets of the suffixes of this name are recorded as they are written, but only the
+    // names written before this one are looked up. They are removed again if the name does not
+    // fit.
+    let earlier = written_names.len();
+    let too_long = |written_names: &mut Vec<(u64, usize)>| {
+        written_names.truncate(earlier);
+        Err(TooLong)
+    };
 
-    if compress
-        && written_names
-            .iter()
-            .any(|(name_hash, _)| *name_hash == hash)
-    {
-        if buffer.remaining_mut() < 2 {
-            return Err(TooLong);
-        }
-        let (_, offset) = written_names
-            .iter()
-            .find(|(name_hash, _)| *name_hash == hash)
-            .unwrap();
-        buffer.put_u8((((*offset >> 8) as u8) & 0x3f) | 0xc0);
-        buffer.put_u8(*offset as u8);
-        Ok(())
-    } else {
-        let mut pointer = 0;
-        for (index, label) in domain_name.labels().enumerate() {
-            if buffer.remaining_mut() < 1 + label.len() {
-                return Err(TooLong);
-            }
-            buffer.put_u8(label.len() as u8);
-            buffer.put_slice(label.as_bytes());
-
-            if index + 1 == domain_name.len() {
-                break;
-            }
-            if compress {
-                let next_hash = {
-                    let mut hasher = DefaultHasher::default();
-                    domain_name
-                        .labels()
-                        .skip(index + 1)
-                        .for_each(|label| label.hash(&mut hasher));
-                    hasher.finish()
-                };
-
-                if let Some((_, offset)) = written_names
-                    .iter()
-                    .find(|(name_hash, _)| *name_hash == next_hash)
-                {
-                    pointer = domain_name.len() - index;
-                    if buffer.remaining_mut() < 2 {
-                        return Err(TooLong);
-                    }
-                    buffer.put_u8((((*offset >> 8) as u8) & 0x3f) | 0xc0);
-                    buffer.put_u8(*offset as u8);
-                    break;
+    for (index, label) in domain_name.labels().enumerate() {
+        let hash = suffix_hash(domain_name.labels().skip(index));
+        if compress {
+            if let Some((_, offset)) = written_names[..earlier]
+                .iter()
+                .find(|(name_hash, _)| *name_hash == hash)
+            {
+                if buffer.remaining_mut() < 2 {
+                    return too_long(written_names);
                 }
+                buffer.put_u8((((*offset >> 8) as u8) & 0x3f) | 0xc0);
+                buffer.put_u8(*offset as u8);
+                return Ok(());
             }
         }
-        if pointer == 0 {
-            if buffer.remaining_mut() < 1 {
-                return Err(TooLong);
-            }
-            buffer.put_u8(0);
+
+        if buffer.remaining_mut() < 1 + label.len() {
+            return too_long(written_names);
+        }
+        // Pointers only have 14 bits for the offset.
+        if buffer.len() <= 0x3fff {
+            written_names.push((hash, buffer.len()));
+        }
+        buffer.put_u8(label.len() as u8);
+        buffer.put_slice(label.as_bytes());
+    }
+
+    if buffer.remaining_mut() < 1 {
+        return too_long(written_names);
+    }
+    buffer.put_u8(0);
+    Ok(())
+}
+
+/// Hashes the exact bytes of the labels. Unlike the hash of [Label] this does not fold case, so
+/// a pointer never changes the case of a name, like the question a client expects echoed.
+fn suffix_hash(labels: impl Iterator<Item = Label>) -> u64 {
+    let mut hasher = DefaultHasher::default();
+    labels.for_each(|label| label.as_bytes().hash(&mut hasher));
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        proto::{self, FromPacketBytes, QClass, QType},
+        resource::ResourceData,
+    };
+
+    fn question(name: &'static str) -> Question {
+        Question::new(QType::A, QClass::IN, DomainName::from_static(name))
+    }
+
+    /// Whether `a` and `b` hold the same message: equal header fields and, for every section, the
+    /// same entries in any order. Names are compared expanded and without case.
+    fn packets_semantically_equal(a: &proto::Packet<'_>, b: &proto::Packet<'_>) -> bool {
+        fn header(packet: &proto::Packet<'_>) -> [u16; 10] {
+            let h = packet.header();
+            [
+                h.id(),
+                h.packet_type().as_u8().into(),
+                h.opcode().as_u8().into(),
+                h.authoritive_answer().into(),
+                h.truncated().into(),
+                h.recursion_desired().into(),
+                h.recursion_available().into(),
+                h.authentic_data().into(),
+                h.checking_disabled().into(),
+                h.response_code().as_u8().into(),
+            ]
+        }
+        fn name(name: &proto::DomainName<'_>) -> Vec<u8> {
+            DomainName::from(name).canonical().to_bytes()
+        }
+        type Entry = (Vec<u8>, u16, u16, u32, Vec<u8>);
+        fn section<'a>(records: impl Iterator<Item = proto::Resource<'a>>) -> Option<Vec<Entry>> {
+            let mut entries = records
+                .map(|record| {
+                    let data = ResourceData::try_from(record).ok()?;
+                    Some((
+                        name(&record.name()),
+                        data.typ().as_u16(),
+                        data.class().as_u16(),
+                        data.ttl(),
+                        data.data().as_ref().to_vec(),
+                    ))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            entries.sort();
+            Some(entries)
         }
+        let questions = |packet: &proto::Packet<'_>| {
+            let mut questions: Vec<_> = packet
+                .questions()
+                .map(|q| (name(&q.name()), q.q_type().as_u16(), q.q_class().as_u16()))
+                .collect();
+            questions.sort();
+            questions
+        };
 
-        // 1 => 1..=1 = 1     | [1 - 1..]
-        // 2 => 1..=2 = 1, 2  | [2 - 1..], [2 - 2..]
-        for i in 1..=domain_name.len() - pointer {
-            let labels: Vec<_> = domain_name.labels().skip(domain_name.len() - i).collect();
+        header(a) == header(b)
+            && questions(a) == questions(b)
+            && section(a.answers()) == section(b.answers())
+            && section(a.authority()) == section(b.authority())
+            && section(a.additional()) == section(b.additional())
+    }
 
-            // The 1 here refers to the last "null" in the name sequence.
-            // While the 1 in the map refers to the length byte.
-            let buffer_len: usize = 1 + labels.iter().map(|label| label.len() + 1).sum::<usize>();
-            let hash = {
-                let mut hasher = DefaultHasher::default();
-                labels.iter().for_each(|label| label.hash(&mut hasher));
-                hasher.finish()
-            };
-            written_names.push((hash, buffer.len() - buffer_len))
+    #[test]
+    fn questions_compress_against_earlier_questions() {
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_question(question("www.example.com"))
+            .add_question(question("mail.example.com"))
+            .add_question(question("a.b.example.com"))
+            .add_question(question("mail.example.com"))
+            .build_into(&mut buffer);
+
+        // The first question is written in full right after the header.
+        let first = 12;
+        let example_com = first + 4;
+        let second = first + 17 + 4;
+        // mail + a pointer to example.com
+        assert_eq!(
+            buffer[second..second + 7],
+            [4, b'm', b'a', b'i', b'l', 0xc0, example_com as u8]
+        );
+        // a + b + a pointer to example.com
+        let third = second + 7 + 4;
+        assert_eq!(
+            buffer[third..third + 6],
+            [1, b'a', 1, b'b', 0xc0, example_com as u8]
+        );
+        // A pointer to the second question
+        let fourth = third + 6 + 4;
+        assert_eq!(buffer[fourth..fourth + 2], [0xc0, second as u8]);
+        assert_eq!(buffer.len(), fourth + 2 + 4);
+
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        let names: Vec<String> = packet.questions().map(|q| q.name().to_string()).collect();
+        assert_eq!(
+            names,
+            [
+                "www.example.com.",
+                "mail.example.com.",
+                "a.b.example.com.",
+                "mail.example.com."
+            ]
+        );
+    }
+
+    #[test]
+    fn compression_preserves_case() {
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_question(question("EXAMPLE.com"))
+            .add_question(question("www.example.COM"))
+            .add_question(question("mail.example.COM"))
+            .build_into(&mut buffer);
+
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        let names: Vec<String> = packet.questions().map(|q| q.name().to_string()).collect();
+        assert_eq!(
+            names,
+            ["EXAMPLE.com.", "www.example.COM.", "mail.example.COM."]
+        );
+        // Only the name with the same case is compressed.
+        assert_eq!(buffer.len(), 12 + (13 + 4) + (17 + 4) + (7 + 4));
+    }
+
+    #[test]
+    fn compression_and_order_do_not_change_the_message() {
+        let name = DomainName::from_static("www.example.com");
+        let target = DomainName::from_static("web.example.com");
+        let cname = || {
+            Resource(
+                name.clone(),
+                Arc::new(ResourceData::Generic {
+                    typ: Type::CNAME,
+                    class: proto::Class::IN,
+                    ttl: 300,
+                    data: Arc::from(target.to_bytes()),
+                }),
+            )
+        };
+        let a = |ttl| {
+            Resource(
+                DomainName::from_static("WEB.example.com"),
+                Arc::new(ResourceData::A {
+                    ttl,
+                    addr: [192, 0, 2, 1].into(),
+                }),
+            )
+        };
+        let message = |compress, answers: Vec<Resource>| {
+            let mut builder = DNSPacketBuilder::query(7).add_question(question("www.example.com"));
+            builder.compress = compress;
+            for answer in answers {
+                builder = builder.add_answer(answer);
+            }
+            let mut buffer = ArrayBuffer::new();
+            builder.build_into(&mut buffer);
+            buffer
+        };
+        let compressed = message(true, vec![cname(), a(60)]);
+        let expanded = message(false, vec![a(60), cname()]);
+        let other_ttl = message(true, vec![cname(), a(30)]);
+        assert_ne!(compressed.as_slice(), expanded.as_slice());
+
+        let parse = |buffer| proto::Packet::parse(buffer, 0).unwrap().unwrap();
+        assert!(packets_semantically_equal(
+            &parse(&compressed),
+            &parse(&expanded)
+        ));
+        assert!(!packets_semantically_equal(
+            &parse(&compressed),
+            &parse(&other_ttl)
+        ));
+    }
+
+    #[test]
+    fn query_carries_the_requested_flags_and_opt() {
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_question(question("example.com"))
+            .with_recursion_desired(false)
+            .with_checking_disabled(true)
+            .with_dnssec_ok(true)
+            .build_into(&mut buffer);
+
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert!(!packet.header().recursion_desired());
+        assert!(packet.header().checking_disabled());
+        assert_eq!(packet.header().additional_entries(), 1);
+        let edns = packet.edns().expect("Query to have an OPT record");
+        assert!(edns.dnssec_ok());
+        assert_eq!(edns.udp_payload_size(), Edns::default().udp_payload_size);
+
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(2)
+            .add_question(question("example.com"))
+            .with_edns(Edns {
+                udp_payload_size: 4096,
+                ..Edns::default()
+            })
+            .build_into(&mut buffer);
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert!(packet.header().recursion_desired());
+        let edns = packet.edns().expect("Query to have an OPT record");
+        assert!(!edns.dnssec_ok());
+        assert_eq!(edns.udp_payload_size(), 4096);
+    }
+
+    #[test]
+    fn duplicate_answers_are_written_once() {
+        let name = DomainName::from_static("codecrafters.io");
+        let record = ResourceData::A {
+            ttl: 60,
+            addr: [8, 8, 8, 8].into(),
+        };
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_answer(Resource(name.clone(), Arc::new(record.clone())))
+            .add_answer(Resource(name, Arc::new(record)))
+            .build_into(&mut buffer);
+
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert_eq!(packet.header().answer_entries(), 1);
+        assert_eq!(packet.answers().count(), 1);
+    }
+
+    #[test]
+    fn counts_match_the_records_that_fit() {
+        let name = DomainName::from_static("codecrafters.io");
+        let a = Resource(
+            name.clone(),
+            Arc::new(ResourceData::A {
+                ttl: 60,
+                addr: [8, 8, 8, 8].into(),
+            }),
+        );
+        let txt = |len: usize| {
+            Resource(
+                name.clone(),
+                Arc::new(ResourceData::Generic {
+                    typ: proto::Type::TXT,
+                    class: proto::Class::IN,
+                    ttl: 60,
+                    data: Arc::from([&[len as u8 - 1][..], &vec![b'a'; len - 1]].concat()),
+                }),
+            )
+        };
+        let mut buffer = ArrayBuffer::new().with_max_len(200);
+        DNSPacketBuilder::query(1)
+            .add_question(question("codecrafters.io"))
+            .add_answer(a)
+            .add_additional(txt(20))
+            .add_additional(txt(200))
+            .add_additional(txt(20))
+            .with_edns(Edns::default())
+            .build_into(&mut buffer);
+
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert_eq!(packet.header().question_entries(), 1);
+        assert_eq!(packet.header().answer_entries(), 1);
+        assert_eq!(packet.header().authority_entries(), 0);
+        // The first TXT record and the OPT record fit, the rest of the section is dropped.
+        assert_eq!(packet.header().additional_entries(), 2);
+        assert_eq!(packet.additional().count(), 2);
+        assert!(packet.edns().is_some());
+        assert!(!packet.header().truncated());
+    }
+
+    #[test]
+    fn optional_authorities_are_left_out_without_truncating() {
+        let ns = |name: &'static str| {
+            Resource(
+                DomainName::from_static("codecrafters.io"),
+                Arc::new(ResourceData::Generic {
+                    typ: proto::Type::NS,
+                    class: proto::Class::IN,
+                    ttl: 60,
+                    data: Arc::from(DomainName::from_static(name).to_bytes()),
+                }),
+            )
+        };
+        let mut buffer = ArrayBuffer::new().with_max_len(100);
+        DNSPacketBuilder::query(1)
+            .add_question(question("codecrafters.io"))
+            .add_authority(ns("ns1.codecrafters.io"))
+            .add_optional_authority(ns("ns2.a-much-longer-name-than-fits.codecrafters.io"))
+            .build_into(&mut buffer);
+
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert_eq!(packet.header().authority_entries(), 1);
+        assert_eq!(packet.authority().count(), 1);
+        assert!(!packet.header().truncated());
+    }
+
+    #[test]
+    fn rdlength_counts_compressed_name() {
+        let target = DomainName::from_static("example.com").to_bytes();
+        let record = ResourceData::Generic {
+            typ: proto::Type::CNAME,
+            class: proto::Class::IN,
+            ttl: 60,
+            data: Arc::from(target),
+        };
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_answer(Resource(
+                DomainName::from_static("www.example.com"),
+                Arc::new(record),
+            ))
+            .build_into(&mut buffer);
+
+        // The owner name is written in full, and the target is a pointer to example.com in it.
+        let rdlength = 12 + 17 + 8;
+        assert_eq!(buffer[rdlength..rdlength + 2], [0, 2]);
+        assert_eq!(buffer[rdlength + 2..], [0xc0, 12 + 4]);
+
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        let answer = packet.answers().next().unwrap();
+        assert_eq!(answer.data().unwrap(), [0xc0, 12 + 4]);
+    }
+
+    #[test]
+    fn sorted_answers_are_ordered_by_type_and_data() {
+        let name = DomainName::from_static("codecrafters.io");
+        let a = |addr: [u8; 4]| {
+            Resource(
+                name.clone(),
+                Arc::new(ResourceData::A {
+                    ttl: 60,
+                    addr: addr.into(),
+                }),
+            )
+        };
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .with_sorted_answers(true)
+            .add_answer(a([8, 8, 8, 8]))
+            .add_answer(a([8, 8, 4, 4]))
+            .add_answer(a([1, 1, 1, 1]))
+            .build_into(&mut buffer);
+
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        let data: Vec<_> = packet.answers().map(|a| a.data().unwrap()).collect();
+        assert_eq!(data, [[1, 1, 1, 1], [8, 8, 4, 4], [8, 8, 8, 8]]);
+    }
+
+    #[test]
+    fn padded_response_is_a_multiple_of_the_block() {
+        let mut request = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_question(question("www.example.com"))
+            .with_edns(Edns::default())
+            .build_into(&mut request);
+        let request = proto::Packet::parse(&request, 0).unwrap().unwrap();
+
+        for block in [1, 64, 128, 468] {
+            let mut buffer = ArrayBuffer::new();
+            DNSPacketBuilder::respond(&request, ResponseCode::None)
+                .add_question(question("www.example.com"))
+                .with_padding(block)
+                .build_into(&mut buffer);
+            assert_eq!(buffer.len() % block as usize, 0, "{block}");
+            let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+            let options = packet.edns().unwrap().options().unwrap();
+            assert!(matches!(
+                options[..],
+                [proto::EdnsOption::Unknown {
+                    code: proto::PADDING,
+                    ..
+                }]
+            ));
         }
+    }
+
+    #[test]
+    fn imported_packet_is_written_again_with_a_changed_answer() {
+        let name = DomainName::from_static("example.com");
+        let ns = DomainName::from_static("ns.example.com");
+        let a = |owner: &DomainName, last| {
+            Resource(
+                owner.clone(),
+                Arc::new(ResourceData::A {
+                    ttl: 60,
+                    addr: std::net::Ipv4Addr::new(192, 0, 2, last),
+                }),
+            )
+        };
+        let mut original = ArrayBuffer::new();
+        DNSPacketBuilder::query(7)
+            .with_response_code(ResponseCode::NameError)
+            .with_authoritative_answer(true)
+            .add_question(question("example.com"))
+            .add_answer(a(&name, 1))
+            .add_answer(a(&name, 2))
+            .add_authority(Resource(
+                name.clone(),
+                Arc::new(ResourceData::Generic {
+                    typ: Type::NS,
+                    class: proto::Class::IN,
+                    ttl: 60,
+                    data: Arc::from(ns.to_bytes()),
+                }),
+            ))
+            .add_additional(a(&ns, 53))
+            .with_edns(Edns {
+                dnssec_ok: true,
+                ..Edns::default()
+            })
+            .build_into(&mut original);
+
+        let parsed = proto::Packet::parse(&original, 0).unwrap().unwrap();
+        let mut builder = DNSPacketBuilder::from_packet(&parsed).unwrap();
+        builder.answers_mut()[1] = a(&name, 3);
+        let mut rewritten = ArrayBuffer::new();
+        builder.build_into(&mut rewritten);
 
-        Ok(())
+        // Only the last octet of the second answer changed.
+        let changed: Vec<_> = (0..original.len())
+            .filter(|&i| original[i] != rewritten[i])
+            .collect();
+        assert_eq!(original.len(), rewritten.len());
+        assert_eq!(changed.len(), 1);
+        let packet = proto::Packet::parse(&rewritten, 0).unwrap().unwrap();
+        let data: Vec<_> = packet.answers().map(|a| a.data().unwrap()).collect();
+        assert_eq!(data, [[192, 0, 2, 1], [192, 0, 2, 3]]);
+        assert!(packet.header().authoritive_answer());
+        assert_eq!(packet.additional().count(), 2);
+        assert!(packet.edns().unwrap().dnssec_ok());
     }
 }
@@ -0,0 +1,148 @@
+//! Packets rendered in presentation format, laid out like the output of dig: the header as
+//! comments, followed by one line for every entry of each section.
+
+use std::{
+    fmt::Write,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use crate::{
+    domain_name::DomainName,
+    proto::{self, Type},
+    resource::ResourceData,
+};
+
+/// The lines of `packet` in presentation format.
+pub fn render(packet: &proto::Packet<'_>) -> String {
+    let header = packet.header();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        ";; ->>HEADER<<- opcode: {:?}, status: {:?}, id: {}",
+        header.opcode(),
+        header.response_code(),
+        header.id()
+    );
+    let flags: Vec<_> = [
+        (
+            matches!(header.packet_type(), proto::PacketType::Response),
+            "qr",
+        ),
+        (header.authoritive_answer(), "aa"),
+        (header.truncated(), "tc"),
+        (header.recursion_desired(), "rd"),
+        (header.recursion_available(), "ra"),
+        (header.authentic_data(), "ad"),
+        (header.checking_disabled(), "cd"),
+    ]
+    .into_iter()
+    .filter_map(|(set, flag)| set.then_some(flag))
+    .collect();
+    let _ = writeln!(
+        out,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags.join(" "),
+        header.question_entries(),
+        header.answer_entries(),
+        header.authority_entries(),
+        header.additional_entries()
+    );
+
+    if header.question_entries() > 0 {
+        let _ = writeln!(out, "\n;; QUESTION SECTION:");
+        for question in packet.questions() {
+            let _ = writeln!(out, ";{question}");
+        }
+    }
+    for (title, records) in [
+        ("ANSWER", packet.answers().collect::<Vec<_>>()),
+        ("AUTHORITY", packet.authority().collect()),
+        ("ADDITIONAL", packet.additional().collect()),
+    ] {
+        if records.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "\n;; {title} SECTION:");
+        for record in records {
+            let _ = writeln!(out, "{record} {}", rdata(&record));
+        }
+    }
+    out
+}
+
+/// The data of `record` in presentation format. Types without a known format use the generic
+/// format of RFC 3597, `\# length hex`.
+pub fn rdata(record: &proto::Resource<'_>) -> String {
+    let Ok(data) = ResourceData::try_from(*record) else {
+        return "; invalid data".to_owned();
+    };
+    let data = data.data();
+    let data = data.as_ref();
+    let text = match record.typ() {
+        Type::A => <[u8; 4]>::try_from(data)
+            .ok()
+            .map(|octets| Ipv4Addr::from(octets).to_string()),
+        Type::AAAA => <[u8; 16]>::try_from(data)
+            .ok()
+            .map(|octets| Ipv6Addr::from(octets).to_string()),
+        Type::CNAME | Type::NS | Type::PTR => DomainName::from_wire(data, 0)
+            .ok()
+            .map(|name| name.to_string()),
+        Type::MX if data.len() > 2 => DomainName::from_wire(data, 2)
+            .ok()
+            .map(|name| format!("{} {name}", u16::from_be_bytes([data[0], data[1]]))),
+        _ => None,
+    };
+    text.unwrap_or_else(|| {
+        let hex: String = data.iter().map(|byte| format!("{byte:02x}")).collect();
+        format!("\\# {} {hex}", data.len())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        array_buffer::ArrayBuffer,
+        packet::DNSPacketBuilder,
+        proto::{FromPacketBytes, QClass, QType},
+        question::Question,
+        resource::Resource,
+    };
+
+    #[test]
+    fn renders_sections_like_dig() {
+        let name = DomainName::from_static("example.com");
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(7)
+            .add_question(Question::new(QType::A, QClass::IN, name.clone()))
+            .add_answer(Resource(
+                name.clone(),
+                Arc::new(ResourceData::A {
+                    ttl: 60,
+                    addr: Ipv4Addr::new(10, 0, 0, 1),
+                }),
+            ))
+            .add_answer(Resource(
+                name,
+                Arc::new(ResourceData::Generic {
+                    typ: Type::from(65280),
+                    class: proto::Class::IN,
+                    ttl: 60,
+                    data: Arc::from(&[1, 2][..]),
+                }),
+            ))
+            .build_into(&mut buffer);
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        let rendered = render(&packet);
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(
+            lines[1],
+            ";; flags: rd; QUERY: 1, ANSWER: 2, AUTHORITY: 0, ADDITIONAL: 0"
+        );
+        assert!(lines[7].ends_with(" 10.0.0.1"), "{}", lines[7]);
+        assert!(lines[8].ends_with(" \\# 2 0102"), "{}", lines[8]);
+    }
+}
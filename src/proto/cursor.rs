@@ -0,0 +1,142 @@
+use bytes::Buf;
+use thiserror::Error;
+
+use super::{domain_name::DomainName, label::LabelError, FromPacketBytes};
+
+/// A read cursor over the bytes of a packet. All reads are bounds-checked and return an error
+/// instead of panicking when the packet is too short.
+///
+/// The cursor keeps a reference to the whole packet and not only the remaining bytes, as domain
+/// names may point backwards into the packet.
+#[derive(Debug, Clone, Copy)]
+pub struct Cursor<'data> {
+    buffer: &'data [u8],
+    offset: usize,
+}
+
+#[derive(Debug, Error)]
+pub enum CursorError {
+    #[error("The amount of remaining bytes in the buffer ({remaining}) at offset {offset} is not enough to read {expected} bytes")]
+    EOF {
+        offset: usize,
+        remaining: usize,
+        expected: usize,
+    },
+    #[error(transparent)]
+    Label(#[from] LabelError),
+}
+
+impl<'data> Cursor<'data> {
+    pub const fn new(buffer: &'data [u8], offset: usize) -> Self {
+        Self { buffer, offset }
+    }
+
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn ensure(&self, expected: usize) -> Result<(), CursorError> {
+        if self.remaining() < expected {
+            return Err(CursorError::EOF {
+                offset: self.offset,
+                remaining: self.remaining(),
+                expected,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<(), CursorError> {
+        self.ensure(len)?;
+        self.offset += len;
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, CursorError> {
+        self.ensure(1)?;
+        Ok(self.get_u8())
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, CursorError> {
+        self.ensure(2)?;
+        Ok(self.get_u16())
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, CursorError> {
+        self.ensure(4)?;
+        Ok(self.get_u32())
+    }
+
+    pub fn read_slice(&mut self, len: usize) -> Result<&'data [u8], CursorError> {
+        self.ensure(len)?;
+        let slice = &self.buffer[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    /// Read a domain name at the current position and move past the bytes it occupies in this
+    /// position of the packet. Pointers are followed when validating the name but not counted.
+    pub fn read_name(&mut self) -> Result<DomainName<'data>, CursorError> {
+        let Some(name) = DomainName::parse(self.buffer, self.offset)? else {
+            return Err(CursorError::EOF {
+                offset: self.offset,
+                remaining: self.remaining(),
+                expected: 1,
+            });
+        };
+        self.skip(name.size_in_packet())?;
+        Ok(name)
+    }
+}
+
+impl<'data> Buf for Cursor<'data> {
+    fn remaining(&self) -> usize {
+        self.buffer.len().saturating_sub(self.offset)
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.buffer.get(self.offset..).unwrap_or_default()
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        assert!(cnt <= self.remaining(), "Cursor beyond len");
+        self.offset += cnt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_are_bounds_checked() {
+        let bytes = [0u8, 1, 0, 0, 0, 2, 3];
+        let mut cursor = Cursor::new(&bytes, 0);
+        assert_eq!(cursor.read_u16().unwrap(), 1);
+        assert_eq!(cursor.read_u32().unwrap(), 2);
+        assert_eq!(cursor.read_u8().unwrap(), 3);
+        assert!(matches!(
+            cursor.read_u8(),
+            Err(CursorError::EOF {
+                offset: 7,
+                remaining: 0,
+                expected: 1
+            })
+        ));
+        assert!(matches!(
+            Cursor::new(&bytes, 5).read_u32(),
+            Err(CursorError::EOF { remaining: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn read_name_moves_past_name() {
+        let bytes = [3, b'c', b'o', b'm', 0, 0, 1, 0xc0, 0];
+        let mut cursor = Cursor::new(&bytes, 0);
+        assert_eq!(cursor.read_name().unwrap().to_string(), "com.");
+        assert_eq!(cursor.offset(), 5);
+        assert_eq!(cursor.read_u16().unwrap(), 1);
+        assert_eq!(cursor.read_name().unwrap().to_string(), "com.");
+        assert_eq!(cursor.remaining(), 0);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt, hash::Hash};
+use core::{fmt, hash::Hash};
 
 use super::label::{Label, LabelError};
 
@@ -9,10 +9,9 @@ impl<'data> DomainName<'data> {
     pub fn size_in_packet(&self) -> usize {
         let mut len = 0;
         if let Some(start) = self.0 {
-            for label in start {
-                len += match label
-                    .expect("Domain name labels to be validated before calling size_in_packet")
-                {
+            // The labels are validated in parsing, so the walk only stops early on a broken name.
+            for label in start.into_iter().map_while(Result::ok) {
+                len += match label {
                     Label::Data { data, .. } => 1 + data.len(),
                     Label::Pointer { .. } => return len + 2,
                 }
@@ -26,12 +25,22 @@ impl<'data> DomainName<'data> {
         self.1
     }
 
+    /// Whether both names have the same labels, ignoring ASCII case. Names are compared by their
+    /// labels and not their bytes, so a compressed name equals the same name written out.
+    pub fn eq_ignore_ascii_case(&self, other: &DomainName<'_>) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .zip(other.iter())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &'data str> {
-        // Unwrap is safe as we check all labels during parse
+        // All labels are checked during parse
         self.0
             .into_iter()
             .flat_map(|l| l)
-            .map(|l| l.unwrap())
+            .map_while(Result::ok)
             .filter_map(|l| l.data())
     }
 }
@@ -57,10 +66,10 @@ impl<'data> super::FromPacketBytes<'data> for DomainName<'data> {
 }
 
 impl<'data> fmt::Display for DomainName<'data> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         if let Some(start) = self.0 {
             for item in start {
-                let item = item.map_err(|_| std::fmt::Error)?;
+                let item = item.map_err(|_| core::fmt::Error)?;
                 if let Some(data) = item.data() {
                     write!(f, "{data}.")?;
                 }
@@ -73,11 +82,11 @@ impl<'data> fmt::Display for DomainName<'data> {
 }
 
 impl<'data> fmt::Debug for DomainName<'data> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         if let Some(start) = self.0 {
             write!(f, "\"")?;
             for item in start {
-                let item = item.map_err(|_| std::fmt::Error)?;
+                let item = item.map_err(|_| core::fmt::Error)?;
                 if let Some(data) = item.data() {
                     write!(f, "{data}.")?;
                 }
@@ -91,12 +100,10 @@ impl<'data> fmt::Debug for DomainName<'data> {
 }
 
 impl<'data> Hash for DomainName<'data> {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         if let Some(start) = self.0 {
-            for label in start {
-                label
-                    .expect("Domain name labels to be validated before running hash.")
-                    .hash(state);
+            for label in start.into_iter().map_while(Result::ok) {
+                label.hash(state);
             }
         }
     }
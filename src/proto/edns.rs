@@ -0,0 +1,325 @@
+//! The OPT pseudo record (RFC 6891) signals support for EDNS. It is placed in the additional
+//! section and reuses the fixed resource fields for its own purposes.
+//! ```text
+//!     +------------+--------------+------------------------------+
+//!     | Field Name | Field Type   | Description                  |
+//!     +------------+--------------+------------------------------+
+//!     | NAME       | domain name  | MUST be 0 (root domain)      |
+//!     | TYPE       | u_int16_t    | OPT (41)                     |
+//!     | CLASS      | u_int16_t    | requestor's UDP payload size |
+//!     | TTL        | u_int32_t    | extended RCODE and flags     |
+//!     | RDLEN      | u_int16_t    | length of all RDATA          |
+//!     | RDATA      | octet stream | {attribute,value} pairs      |
+//!     +------------+--------------+------------------------------+
+//! ```
+//!
+//! The TTL field is split into the following.
+//! ```text
+//!                 +0 (MSB)                            +1 (LSB)
+//!      +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//!   0: |         EXTENDED-RCODE        |            VERSION            |
+//!      +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//!   2: | DO|                           Z                               |
+//!      +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//! ```
+
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use thiserror::Error;
+
+use super::{
+    cursor::{Cursor, CursorError},
+    resource::{Resource, ResourceError},
+    types::Type,
+};
+
+/// Option code of EDNS Client Subnet (RFC 7871).
+pub const CLIENT_SUBNET: u16 = 8;
+/// Option code of DNS Cookies (RFC 7873).
+pub const COOKIE: u16 = 10;
+/// Option code of Extended DNS Errors (RFC 8914).
+pub const EXTENDED_ERROR: u16 = 15;
+/// Option code of the Padding option (RFC 7830).
+pub const PADDING: u16 = 12;
+
+#[derive(Debug, Error)]
+pub enum EdnsError {
+    #[error(transparent)]
+    Cursor(#[from] CursorError),
+    #[error(transparent)]
+    Resource(#[from] ResourceError),
+    #[error("The client subnet address family {0} is not supported")]
+    UnknownFamily(u16),
+    #[error("The client subnet prefix of {prefix} is too long for the address family {family}")]
+    PrefixTooLong { family: u16, prefix: u8 },
+    #[error("The client subnet address is {found} bytes but the prefix needs {expected} bytes")]
+    AddressLength { expected: usize, found: usize },
+    #[error("The extra text of an extended error is not UTF-8: {0}")]
+    ExtraText(#[from] core::str::Utf8Error),
+    #[error("A cookie of {0} bytes is neither a client cookie nor a client and server cookie")]
+    CookieLength(usize),
+}
+
+/// An option carried in the RDATA of the OPT record. The options are encoded as the following.
+/// ```text
+///                    +0 (MSB)                            +1 (LSB)
+///         +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+///      0: |                          OPTION-CODE                          |
+///         +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+///      2: |                         OPTION-LENGTH                         |
+///         +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+///      4: |                                                               |
+///         /                          OPTION-DATA                          /
+///         /                                                               /
+///         +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdnsOption<'data> {
+    ClientSubnet(ClientSubnet),
+    ExtendedError {
+        info_code: u16,
+        extra_text: &'data str,
+    },
+    Cookie(Cookie<'data>),
+    Unknown {
+        code: u16,
+        data: &'data [u8],
+    },
+}
+
+/// DNS Cookie (RFC 7873). An 8 byte client cookie, followed by a server cookie of 8 to 32 bytes
+/// when the client has learned one from an earlier response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cookie<'data> {
+    pub client: [u8; 8],
+    pub server: &'data [u8],
+}
+
+/// EDNS Client Subnet (RFC 7871). The address is truncated to the source prefix on the wire
+/// and padded with zeroes when parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientSubnet {
+    pub source_prefix: u8,
+    pub scope_prefix: u8,
+    pub address: IpAddr,
+}
+
+#[derive(Clone, Copy)]
+pub struct EdnsView<'data> {
+    resource: Resource<'data>,
+}
+
+impl<'data> EdnsView<'data> {
+    /// View a resource as an OPT record. Returns `None` when the resource is not an OPT record.
+    pub fn from_resource(resource: Resource<'data>) -> Option<Self> {
+        if resource.typ() != Type::OPT {
+            return None;
+        }
+        Some(Self { resource })
+    }
+
+    /// The number of octets of the largest UDP payload that can be reassembled and delivered in
+    /// the requestor's network stack.
+    pub fn udp_payload_size(&self) -> u16 {
+        self.resource.class().as_u16()
+    }
+
+    /// The upper 8 bits of the 12 bit response code. The lower 4 bits are in the header.
+    pub fn extended_response_code(&self) -> u8 {
+        (self.resource.ttl() >> 24) as u8
+    }
+
+    /// The version of EDNS the sender implements.
+    pub fn version(&self) -> u8 {
+        (self.resource.ttl() >> 16) as u8
+    }
+
+    /// DNSSEC OK - the sender is able to accept DNSSEC security records.
+    pub fn dnssec_ok(&self) -> bool {
+        (self.resource.ttl() & 0x8000) == 0x8000
+    }
+
+    /// The options carried in the record.
+    pub fn options(&self) -> Result<Vec<EdnsOption<'data>>, EdnsError> {
+        let data = self.resource.data()?;
+        let mut cursor = Cursor::new(data, 0);
+        let mut options = Vec::new();
+        while cursor.offset() < data.len() {
+            let code = cursor.read_u16()?;
+            let len = cursor.read_u16()? as usize;
+            options.push(EdnsOption::parse(code, cursor.read_slice(len)?)?);
+        }
+        Ok(options)
+    }
+
+    /// The extended error of a response as its info code and extra text, if it has one.
+    pub fn extended_error(&self) -> Result<Option<(u16, &'data str)>, EdnsError> {
+        Ok(self.options()?.into_iter().find_map(|option| match option {
+            EdnsOption::ExtendedError {
+                info_code,
+                extra_text,
+            } => Some((info_code, extra_text)),
+            _ => None,
+        }))
+    }
+
+    /// The client subnet option, if the sender included one.
+    pub fn client_subnet(&self) -> Result<Option<ClientSubnet>, EdnsError> {
+        Ok(self.options()?.into_iter().find_map(|option| match option {
+            EdnsOption::ClientSubnet(subnet) => Some(subnet),
+            _ => None,
+        }))
+    }
+
+    /// The cookie option, if the sender included one.
+    pub fn cookie(&self) -> Result<Option<Cookie<'data>>, EdnsError> {
+        Ok(self.options()?.into_iter().find_map(|option| match option {
+            EdnsOption::Cookie(cookie) => Some(cookie),
+            _ => None,
+        }))
+    }
+}
+
+impl<'data> EdnsOption<'data> {
+    pub fn parse(code: u16, data: &'data [u8]) -> Result<Self, EdnsError> {
+        Ok(match code {
+            CLIENT_SUBNET => Self::ClientSubnet(ClientSubnet::parse(data)?),
+            EXTENDED_ERROR => {
+                let mut cursor = Cursor::new(data, 0);
+                let info_code = cursor.read_u16()?;
+                Self::ExtendedError {
+                    info_code,
+                    extra_text: core::str::from_utf8(&data[cursor.offset()..])?,
+                }
+            }
+            COOKIE => {
+                if data.len() != 8 && !(16..=40).contains(&data.len()) {
+                    return Err(EdnsError::CookieLength(data.len()));
+                }
+                let mut client = [0; 8];
+                client.copy_from_slice(&data[..8]);
+                Self::Cookie(Cookie {
+                    client,
+                    server: &data[8..],
+                })
+            }
+            code => Self::Unknown { code, data },
+        })
+    }
+
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::ClientSubnet(_) => CLIENT_SUBNET,
+            Self::ExtendedError { .. } => EXTENDED_ERROR,
+            Self::Cookie(_) => COOKIE,
+            Self::Unknown { code, .. } => *code,
+        }
+    }
+}
+
+impl ClientSubnet {
+    pub fn parse(data: &[u8]) -> Result<Self, EdnsError> {
+        let mut cursor = Cursor::new(data, 0);
+        let family = cursor.read_u16()?;
+        let source_prefix = cursor.read_u8()?;
+        let scope_prefix = cursor.read_u8()?;
+        let address = cursor.read_slice(data.len() - cursor.offset())?;
+
+        let max_prefix = match family {
+            1 => 32,
+            2 => 128,
+            family => return Err(EdnsError::UnknownFamily(family)),
+        };
+        if source_prefix > max_prefix || scope_prefix > max_prefix {
+            return Err(EdnsError::PrefixTooLong {
+                family,
+                prefix: source_prefix.max(scope_prefix),
+            });
+        }
+        let expected = (source_prefix as usize).div_ceil(8);
+        if address.len() != expected {
+            return Err(EdnsError::AddressLength {
+                expected,
+                found: address.len(),
+            });
+        }
+
+        let address = if family == 1 {
+            let mut octets = [0; 4];
+            octets[..address.len()].copy_from_slice(address);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        } else {
+            let mut octets = [0; 16];
+            octets[..address.len()].copy_from_slice(address);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        };
+
+        Ok(Self {
+            source_prefix,
+            scope_prefix,
+            address,
+        })
+    }
+
+    /// The address family as defined by IANA. 1 for IPv4 and 2 for IPv6.
+    pub fn family(&self) -> u16 {
+        match self.address {
+            IpAddr::V4(_) => 1,
+            IpAddr::V6(_) => 2,
+        }
+    }
+}
+
+impl<'a> fmt::Debug for EdnsView<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EdnsView")
+            .field("udp_payload_size", &self.udp_payload_size())
+            .field("extended_response_code", &self.extended_response_code())
+            .field("version", &self.version())
+            .field("dnssec_ok", &self.dnssec_ok())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::FromPacketBytes;
+
+    #[test]
+    fn parse_client_subnet() {
+        #[rustfmt::skip]
+        let record = [
+            0, 0, 41, 4, 0, 0, 0, 0, 0, 0, 11,
+            // Client subnet 192.0.2.0/24
+            0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2,
+        ];
+        let resource = Resource::parse(&record, 0).unwrap().unwrap();
+        let edns = EdnsView::from_resource(resource).unwrap();
+        assert_eq!(edns.udp_payload_size(), 1024);
+
+        let options = edns.options().unwrap();
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].code(), CLIENT_SUBNET);
+        let subnet = edns.client_subnet().unwrap().unwrap();
+        assert_eq!(subnet.family(), 1);
+        assert_eq!(subnet.source_prefix, 24);
+        assert_eq!(subnet.scope_prefix, 0);
+        assert_eq!(subnet.address, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)));
+    }
+
+    #[test]
+    fn client_subnet_address_must_match_prefix() {
+        assert!(matches!(
+            ClientSubnet::parse(&[0, 1, 24, 0, 192, 0, 2, 1]),
+            Err(EdnsError::AddressLength {
+                expected: 3,
+                found: 4
+            })
+        ));
+    }
+}
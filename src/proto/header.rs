@@ -7,7 +7,7 @@
 //!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 //!     |                      ID                       |
 //!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
-//!     |QR|   Opcode  |AA|TC|RD|RA|   Z    |   RCODE   |
+//!     |QR|   Opcode  |AA|TC|RD|RA| Z|AD|CD|   RCODE   |
 //!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 //!     |                    QDCOUNT                    |
 //!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
@@ -32,7 +32,9 @@
 //! - **RD**: If the name server does not an authority for the domain name then recursivly pursue
 //!   the query.
 //! - **RA**: Set in responses to indicate that the server supports recursive queries.
-//! - **Z**: Reserved bits for future use. Must be 0 in all queries and responses.
+//! - **Z**: Reserved bit for future use. Must be 0 in all queries and responses.
+//! - **AD**: Set in responses whose data the name server has verified with DNSSEC (RFC 4035).
+//! - **CD**: Set in queries to accept data the name server has not verified with DNSSEC.
 //! - **RCODE**: A response code only relevant when responding or reading a response. It can
 //!   indicate various error contitions or success. Read the enum [ResponseCode] for a little more
 //!   info on the various conditions.
@@ -45,28 +47,29 @@
 //!
 //! To default way to get information is from the [HeaderView] struct. It does not do any
 //! validation on initial creation as that is deferred to the retreival methods. If validation is
-//! desired immediatly, then use [HeaderView::new_validated] to get a validated view of the
+//! desired immediatly, then use [HeaderViewValidated::new] to get a validated view of the
 //! header.
 //!
 //! ```
-//! let header: [u8; 12] = [4, 210, 16, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+//! # use dns_starter_rust::proto::{HeaderView, HeaderViewValidated, Opcode, PacketType};
+//! let header: [u8; 12] = [4, 210, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0];
 //!
 //! let view = HeaderView::new(&header);
-//! assert_eq!(view.packet_type(), Some(PacketType::Response));
-//! assert_eq!(view.opcode(), Some(Opcode::Query));
+//! assert!(matches!(view.packet_type(), Some(PacketType::Response)));
+//! assert!(matches!(view.opcode(), Some(Opcode::Query)));
 //! assert_eq!(view.question_entries(), Some(1));
 //! println!("{view:?}");
 //!
-//! let view = HeaderView::new_validated(&header)
+//! let view = HeaderViewValidated::new(&header)
 //!     .expect("Header values to be correct")
 //!     .expect("Header to not be empty");
-//! assert_eq!(view.packet_type(), PacketType::Response);
-//! assert_eq!(view.opcode(), Opcode::Query);
+//! assert!(matches!(view.packet_type(), PacketType::Response));
+//! assert!(matches!(view.opcode(), Opcode::Query));
 //! assert_eq!(view.question_entries(), 1);
 //! println!("{view:?}");
 //! ```
 
-use std::{fmt::Debug, marker::PhantomData};
+use core::{fmt::Debug, marker::PhantomData};
 
 use thiserror::Error;
 
@@ -95,6 +98,8 @@ pub enum Opcode {
     Query,
     InverseQuery,
     Status,
+    /// A primary telling its secondaries the zone changed (RFC 1996).
+    Notify,
     Unknown(u8),
 }
 
@@ -104,6 +109,7 @@ impl Opcode {
             Opcode::Query => 0,
             Opcode::InverseQuery => 1,
             Opcode::Status => 2,
+            Opcode::Notify => 4,
             Opcode::Unknown(code) => *code,
         }
     }
@@ -145,10 +151,18 @@ pub enum ResponseCode {
     /// name server may not wish to perform a particular operation
     /// (e.g., zone transfer) for particular data.
     Refused,
+    /// The server is not authoritative for the zone, or the request
+    /// was not signed with a key the server knows (RFC 8945).
+    NotAuth,
+    /// The responder does not implement the EDNS version of the
+    /// request. This is an extended response code which needs an
+    /// OPT record to be carried in a response.
+    BadVersion,
 }
 
 impl ResponseCode {
-    pub const fn as_u8(&self) -> u8 {
+    /// The full 12 bit response code.
+    pub const fn as_u16(&self) -> u16 {
         match self {
             ResponseCode::None => 0,
             ResponseCode::FormatError => 1,
@@ -156,8 +170,20 @@ impl ResponseCode {
             ResponseCode::NameError => 3,
             ResponseCode::NotImplemented => 4,
             ResponseCode::Refused => 5,
+            ResponseCode::NotAuth => 9,
+            ResponseCode::BadVersion => 16,
         }
     }
+
+    /// The lower 4 bits of the response code, which are carried in the header.
+    pub const fn as_u8(&self) -> u8 {
+        (self.as_u16() & 0xf) as u8
+    }
+
+    /// The upper 8 bits of the response code, which are carried in the OPT record.
+    pub const fn extended_bits(&self) -> u8 {
+        (self.as_u16() >> 4) as u8
+    }
 }
 impl<'data, State> GenericHeaderView<'data, State> {
     pub const SIZE: usize = 12;
@@ -208,6 +234,7 @@ impl<'data> GenericHeaderView<'data, Invalid> {
             0 => Opcode::Query,
             1 => Opcode::InverseQuery,
             2 => Opcode::Status,
+            4 => Opcode::Notify,
             code => Opcode::Unknown(code),
         })
     }
@@ -266,7 +293,33 @@ impl<'data> GenericHeaderView<'data, Invalid> {
         if self.0.len() < 4 {
             None
         } else {
-            Some((self.0[3] & 0xf0) == 0xf0)
+            Some((self.0[3] & 0x80) == 0x80)
+        }
+    }
+
+    /// Authentic Data - set in a response when the name server
+    /// has verified all the data in the answer and authority
+    /// sections with DNSSEC (RFC 4035).
+    ///
+    /// Field: AD
+    pub const fn authentic_data(&self) -> Option<bool> {
+        if self.0.len() < 4 {
+            None
+        } else {
+            Some((self.0[3] & 0x20) == 0x20)
+        }
+    }
+
+    /// Checking Disabled - set in a query when the resolver
+    /// accepts data the name server has not verified with
+    /// DNSSEC (RFC 4035).
+    ///
+    /// Field: CD
+    pub const fn checking_disabled(&self) -> Option<bool> {
+        if self.0.len() < 4 {
+            None
+        } else {
+            Some((self.0[3] & 0x10) == 0x10)
         }
     }
 
@@ -284,6 +337,7 @@ impl<'data> GenericHeaderView<'data, Invalid> {
             3 => ResponseCode::NameError,
             4 => ResponseCode::NotImplemented,
             5 => ResponseCode::Refused,
+            9 => ResponseCode::NotAuth,
             code => return Err(UnknownResponseCodeError(code)),
         }))
     }
@@ -343,7 +397,7 @@ impl<'data> GenericHeaderView<'data, Valid> {
             return Err(HeaderViewError::IncorrectHeaderSize(buffer.len()));
         }
         match buffer[3] & 0xf {
-            0..=5 => {}
+            0..=5 | 9 => {}
             code => {
                 return Err(HeaderViewError::UnknownResponseCode(
                     UnknownResponseCodeError(code),
@@ -385,6 +439,7 @@ impl<'data> GenericHeaderView<'data, Valid> {
             0 => Opcode::Query,
             1 => Opcode::InverseQuery,
             2 => Opcode::Status,
+            4 => Opcode::Notify,
             code => Opcode::Unknown(code),
         }
     }
@@ -428,7 +483,25 @@ impl<'data> GenericHeaderView<'data, Valid> {
     ///
     /// Field: RA
     pub const fn recursion_available(&self) -> bool {
-        (self.0[3] & 0xf0) == 0xf0
+        (self.0[3] & 0x80) == 0x80
+    }
+
+    /// Authentic Data - set in a response when the name server
+    /// has verified all the data in the answer and authority
+    /// sections with DNSSEC (RFC 4035).
+    ///
+    /// Field: AD
+    pub const fn authentic_data(&self) -> bool {
+        (self.0[3] & 0x20) == 0x20
+    }
+
+    /// Checking Disabled - set in a query when the resolver
+    /// accepts data the name server has not verified with
+    /// DNSSEC (RFC 4035).
+    ///
+    /// Field: CD
+    pub const fn checking_disabled(&self) -> bool {
+        (self.0[3] & 0x10) == 0x10
     }
 
     /// Response code - this 4 bit field is set as part of responses.
@@ -442,6 +515,7 @@ impl<'data> GenericHeaderView<'data, Valid> {
             3 => ResponseCode::NameError,
             4 => ResponseCode::NotImplemented,
             5 => ResponseCode::Refused,
+            9 => ResponseCode::NotAuth,
             _ => panic!("Response code should already be checked"),
         }
     }
@@ -481,7 +555,7 @@ impl<'data> GenericHeaderView<'data, Valid> {
 }
 
 impl<'data> Debug for GenericHeaderView<'data, Invalid> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut ds = f.debug_struct("HeaderView");
         if let Some(val) = self.id() {
             ds.field("id", &val);
@@ -517,6 +591,16 @@ impl<'data> Debug for GenericHeaderView<'data, Invalid> {
         } else {
             return ds.finish_non_exhaustive();
         }
+        if let Some(val) = self.authentic_data() {
+            ds.field("authentic_data", &val);
+        } else {
+            return ds.finish_non_exhaustive();
+        }
+        if let Some(val) = self.checking_disabled() {
+            ds.field("checking_disabled", &val);
+        } else {
+            return ds.finish_non_exhaustive();
+        }
         let _ = match self.response_code() {
             Ok(Some(val)) => ds.field("response_code", &val),
             Ok(None) => return ds.finish_non_exhaustive(),
@@ -547,7 +631,7 @@ impl<'data> Debug for GenericHeaderView<'data, Invalid> {
 }
 
 impl<'data> Debug for GenericHeaderView<'data, Valid> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("HeaderView")
             .field("id", &self.id())
             .field("packet_type", &self.packet_type())
@@ -556,6 +640,8 @@ impl<'data> Debug for GenericHeaderView<'data, Valid> {
             .field("truncated", &self.truncated())
             .field("recursion_desired", &self.recursion_desired())
             .field("recursion_available", &self.recursion_available())
+            .field("authentic_data", &self.authentic_data())
+            .field("checking_disabled", &self.checking_disabled())
             .field("response_code", &self.response_code())
             .field("question_entries", &self.question_entries())
             .field("answer_entries", &self.answer_entries())
@@ -569,8 +655,10 @@ impl<'data> super::FromPacketBytes<'data> for GenericHeaderView<'data, Valid> {
     type Error = HeaderViewError;
 
     fn parse(bytes: &'data [u8], offset: usize) -> Result<Option<Self>, Self::Error> {
-        if bytes.len() - offset < Self::SIZE {
-            return Err(HeaderViewError::IncorrectHeaderSize(bytes.len() - offset));
+        // An offset past the end of the buffer leaves nothing to read rather than underflowing.
+        let available = bytes.len().saturating_sub(offset);
+        if available < Self::SIZE {
+            return Err(HeaderViewError::IncorrectHeaderSize(available));
         }
         Self::new(&bytes[offset..offset + Self::SIZE])
     }
@@ -1,4 +1,4 @@
-use std::{fmt::Display, hash::Hash};
+use core::{fmt::Display, hash::Hash};
 
 use thiserror::Error;
 
@@ -89,9 +89,11 @@ impl<'data> super::FromPacketBytes<'data> for Label<'data> {
         let mut cursor = 0;
         while cursor < len {
             match &bytes[offset + 1 + cursor] {
-                b'A'..=b'Z' | b'a'..=b'z' => {}
-                b'0'..=b'9' if cursor != 0 => {}
+                // Labels may start with a digit since RFC 1123.
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => {}
                 b'-' if cursor != 0 && cursor + 1 != len => {}
+                // Service labels like _dmarc and _tcp are outside LDH but common in practice.
+                b'_' if cursor == 0 => {}
                 c => return Err(IllegalLabelChar(*c)),
             }
             cursor += 1;
@@ -100,7 +102,7 @@ impl<'data> super::FromPacketBytes<'data> for Label<'data> {
         Ok(Some(Self::Data {
             // SAFETY: All chars has already been validated to be ascii and as ascii is a valid
             // subset of UTF-8 then this is correct.
-            data: unsafe { std::str::from_utf8_unchecked(&bytes[offset + 1..offset + 1 + len]) },
+            data: unsafe { core::str::from_utf8_unchecked(&bytes[offset + 1..offset + 1 + len]) },
             offset,
             buffer: bytes,
         }))
@@ -115,7 +117,7 @@ impl<'data> IntoIterator for Label<'data> {
         LabelIter {
             yielded_self: false,
             label: Some(self),
-            pointer_mask: 0,
+            last_pointer: None,
         }
     }
 }
@@ -123,7 +125,9 @@ impl<'data> IntoIterator for Label<'data> {
 pub struct LabelIter<'data> {
     yielded_self: bool,
     label: Option<Label<'data>>,
-    pointer_mask: u16,
+    /// The target of the last followed pointer. Pointers must point to prior occurrences of a
+    /// name, so each pointer has to point before the last one or the name loops.
+    last_pointer: Option<usize>,
 }
 
 impl<'data> Iterator for LabelIter<'data> {
@@ -140,22 +144,27 @@ impl<'data> Iterator for LabelIter<'data> {
                 offset,
                 buffer,
             } => Label::parse(buffer, offset + 1 + data.len()),
-            Label::Pointer { offset, .. } if (self.pointer_mask & (1u16 << offset)) != 0 => {
+            Label::Pointer { offset, .. }
+                if self.last_pointer.is_some_and(|last| offset >= last) =>
+            {
                 Err(LabelError::IllegalLabelPointer(offset as u16))
             }
-            Label::Pointer { offset, buffer } => Label::parse(buffer, offset),
+            Label::Pointer { offset, buffer } => {
+                self.last_pointer = Some(offset);
+                Label::parse(buffer, offset)
+            }
+        };
+        // An error ends the name, so the iterator does not yield the same error forever.
+        self.label = match &next {
+            Ok(Some(label)) => Some(*label),
+            _ => None,
         };
-        if let Ok(Some(label)) = &next {
-            self.label = Some(*label);
-        } else if let Ok(None) = &next {
-            self.label = None;
-        }
         next.transpose()
     }
 }
 
 impl<'data> Display for Label<'data> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Label::Data { data, .. } => write!(f, "{data}."),
             Label::Pointer { .. } => Ok(()),
@@ -164,7 +173,7 @@ impl<'data> Display for Label<'data> {
 }
 
 impl<'data> Hash for Label<'data> {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         match self {
             Label::Data { data, .. } => {
                 for c in data.as_bytes() {
@@ -178,3 +187,17 @@ impl<'data> Hash for Label<'data> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looping_pointer_ends_the_iteration() {
+        let name = [0xc0, 0];
+        let label = Label::parse(&name, 0).unwrap().unwrap();
+        let labels: Vec<_> = label.into_iter().take(10).collect();
+        assert_eq!(labels.len(), 3);
+        assert!(matches!(labels[2], Err(LabelError::IllegalLabelPointer(0))));
+    }
+}
@@ -60,8 +60,8 @@ macro_rules! define_type {
             }
         }
 
-        impl std::hash::Hash for $typ {
-            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        impl core::hash::Hash for $typ {
+            fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
                 match self {
                     $(Self::$name => state.write_u16($num),)*
                     Self::$unknown(val) => state.write_u16(*val),
@@ -69,8 +69,8 @@ macro_rules! define_type {
             }
         }
 
-        impl std::hash::Hash for $qtyp {
-            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        impl core::hash::Hash for $qtyp {
+            fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
                 match self {
                     $(Self::$name => state.write_u16($num),)*
                     $(Self::$ext_name => state.write_u16($ext_num),)*
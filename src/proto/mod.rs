@@ -1,26 +1,35 @@
 mod class;
+mod cursor;
 mod domain_name;
+mod edns;
 mod header;
 mod label;
 mod macros;
 mod packet;
 mod question;
 mod resource;
+mod tsig;
 mod types;
 
 pub use self::class::{Class, QClass};
+pub use self::cursor::{Cursor, CursorError};
 pub use self::domain_name::DomainName;
+pub use self::edns::{
+    ClientSubnet, Cookie, EdnsError, EdnsOption, EdnsView, CLIENT_SUBNET, COOKIE, EXTENDED_ERROR,
+    PADDING,
+};
 pub use self::header::{
     HeaderView, HeaderViewError, HeaderViewValidated, Opcode, PacketType, ResponseCode,
     UnknownResponseCodeError,
 };
 pub use self::label::{Label, LabelError};
-pub use self::packet::{Packet, PacketError};
+pub use self::packet::{Packet, PacketError, Section};
 pub use self::question::{Question, QuestionError};
 pub use self::resource::{Resource, ResourceError};
+pub use self::tsig::{Tsig, TsigError};
 pub use self::types::{QType, Type};
 
-use std::fmt;
+use core::fmt;
 
 pub(self) struct DebugList<F, I>(F)
 where
@@ -1,10 +1,11 @@
 use thiserror::Error;
 
-use std::fmt;
+use alloc::vec::Vec;
+use core::fmt;
 
 use super::{
-    DebugList, FromPacketBytes, HeaderViewError, HeaderViewValidated, Question, QuestionError,
-    Resource, ResourceError,
+    DebugList, EdnsView, FromPacketBytes, HeaderView, HeaderViewError, HeaderViewValidated,
+    Question, QuestionError, Resource, ResourceError,
 };
 
 pub struct Packet<'data> {
@@ -76,6 +77,127 @@ impl<'data> Packet<'data> {
             self.first_additional,
         )
     }
+
+    /// Every record of the packet with the section it is in, in the order of the sections.
+    pub fn all_records(&self) -> impl Iterator<Item = (Section, Resource<'data>)> {
+        let tag = |section| move |record| (section, record);
+        self.answers()
+            .map(tag(Section::Answers))
+            .chain(self.authority().map(tag(Section::Authority)))
+            .chain(self.additional().map(tag(Section::Additional)))
+    }
+
+    /// The OPT record of the packet if the sender supports EDNS.
+    pub fn edns(&self) -> Option<EdnsView<'data>> {
+        self.additional().find_map(EdnsView::from_resource)
+    }
+
+    /// Check the whole packet and collect every problem, where [Packet::parse] stops at the
+    /// first. An entry with an error is skipped using only its length fields, so the entries
+    /// after it are still checked. Checking stops once the length fields point outside of the
+    /// packet. Meant for diagnostics, not for serving requests.
+    pub fn validate_verbose(bytes: &'data [u8]) -> Vec<PacketError> {
+        let mut errors = Vec::new();
+        if let Err(e) = HeaderViewValidated::parse(bytes, 0) {
+            errors.push(PacketError::Header(e));
+        }
+        if bytes.len() < HeaderViewValidated::SIZE {
+            return errors;
+        }
+        let header = HeaderView::new(bytes);
+        let mut offset = HeaderViewValidated::SIZE;
+
+        let questions = header.question_entries().unwrap_or_default() as usize;
+        for found in 0..questions {
+            if offset >= bytes.len() {
+                errors.push(if found == 0 {
+                    PacketError::NoQuestions
+                } else {
+                    PacketError::TooFewQuestions {
+                        expected: questions,
+                        found,
+                    }
+                });
+                return errors;
+            }
+            if let Err(e) = Question::parse(bytes, offset) {
+                errors.push(PacketError::Question(e));
+            }
+            match skip_name(bytes, offset).map(|end| end + 4) {
+                Some(end) if end <= bytes.len() => offset = end,
+                _ => return errors,
+            }
+        }
+
+        let sections = [
+            (header.answer_entries(), Section::Answers),
+            (header.authority_entries(), Section::Authority),
+            (header.additional_entries(), Section::Additional),
+        ];
+        for (entries, section) in sections {
+            let entries = entries.unwrap_or_default() as usize;
+            for found in 0..entries {
+                if offset >= bytes.len() {
+                    errors.push(section.too_few(entries, found));
+                    return errors;
+                }
+                if let Err(e) = Resource::parse(bytes, offset) {
+                    errors.push(section.error(e));
+                }
+                // The data length follows the type, class and TTL.
+                let end = skip_name(bytes, offset)
+                    .and_then(|end| bytes.get(end + 8..end + 10).map(|len| (end + 10, len)))
+                    .map(|(end, len)| end + u16::from_be_bytes([len[0], len[1]]) as usize);
+                match end {
+                    Some(end) if end <= bytes.len() => offset = end,
+                    _ => return errors,
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// A section of a packet holding records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Answers,
+    Authority,
+    Additional,
+}
+
+impl Section {
+    fn error(self, error: ResourceError) -> PacketError {
+        match self {
+            Section::Answers => PacketError::Answer(error),
+            Section::Authority => PacketError::AuthoritiveItem(error),
+            Section::Additional => PacketError::AdditionalItem(error),
+        }
+    }
+
+    fn too_few(self, expected: usize, found: usize) -> PacketError {
+        match (self, found) {
+            (Section::Answers, 0) => PacketError::NoAnswers,
+            (Section::Answers, _) => PacketError::TooFewAnswers { expected, found },
+            (Section::Authority, 0) => PacketError::NoAuthorityItems,
+            (Section::Authority, _) => PacketError::TooFewAuthoriryItems { expected, found },
+            (Section::Additional, 0) => PacketError::NoAdditionalItems,
+            (Section::Additional, _) => PacketError::TooFewAdditionalItems { expected, found },
+        }
+    }
+}
+
+/// The offset after the name at `offset`, found only from the length fields of its labels.
+/// Pointers are not followed.
+fn skip_name(bytes: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *bytes.get(offset)?;
+        match len {
+            0 => return Some(offset + 1),
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            len => offset += 1 + len as usize,
+        }
+    }
 }
 
 impl<'data> FromPacketBytes<'data> for Packet<'data> {
@@ -242,9 +364,11 @@ impl<'data> Iterator for QuestionIter<'data> {
             self.1 = None;
         } else {
             self.0 -= 1;
-            // Unwrap once to remove the Result as it has already been checked in parsing the
-            // buffer.
-            self.1 = Question::parse(item.buffer, item.offset + item.size_in_packet()).unwrap();
+            // The entries were all checked in parsing the buffer, so an error here ends the
+            // iteration rather than panicking.
+            self.1 = Question::parse(item.buffer, item.offset + item.size_in_packet())
+                .ok()
+                .flatten();
         }
         Some(item)
     }
@@ -262,9 +386,11 @@ impl<'data> Iterator for ResourceIter<'data> {
             self.1 = None;
         } else {
             self.0 -= 1;
-            // Unwrap once to remove the Result as it has already been checked in parsing the
-            // buffer.
-            self.1 = Resource::parse(item.buffer, item.offset + item.size_in_packet()).unwrap();
+            // The entries were all checked in parsing the buffer, so an error here ends the
+            // iteration rather than panicking.
+            self.1 = Resource::parse(item.buffer, item.offset + item.size_in_packet())
+                .ok()
+                .flatten();
         }
         Some(item)
     }
@@ -281,3 +407,92 @@ impl<'data> fmt::Debug for Packet<'data> {
         s.finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{LabelError, Type};
+
+    #[test]
+    fn all_records_are_tagged_with_their_section() {
+        #[rustfmt::skip]
+        let packet = [
+            // No questions, two answers, one authority item and one additional item
+            0, 1, 0x81, 0x80, 0, 0, 0, 2, 0, 1, 0, 1,
+            // . A IN 60 192.0.2.1
+            0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1,
+            // . A IN 60 192.0.2.2
+            0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 2,
+            // . NS IN 60 .
+            0, 0, 2, 0, 1, 0, 0, 0, 60, 0, 1, 0,
+            // . TXT IN 60 ""
+            0, 0, 16, 0, 1, 0, 0, 0, 60, 0, 1, 0,
+        ];
+        let packet = Packet::parse(&packet, 0).unwrap().unwrap();
+        let records: Vec<_> = packet
+            .all_records()
+            .map(|(section, record)| (section, record.typ()))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                (Section::Answers, Type::A),
+                (Section::Answers, Type::A),
+                (Section::Authority, Type::NS),
+                (Section::Additional, Type::TXT),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_verbose_collects_every_error() {
+        #[rustfmt::skip]
+        let packet = [
+            // An unknown response code of 15, two questions and two answers
+            0, 1, 0x81, 0x8f, 0, 2, 0, 2, 0, 0, 0, 0,
+            // a_b.com. A IN, with an illegal character
+            3, b'a', b'_', b'b', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+            // com. A IN
+            3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+            // A pointer to com. A IN 60, with 4 bytes of data where only 2 remain
+            0xc0, 25, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 8, 8,
+        ];
+        assert!(Packet::parse(&packet, 0).is_err());
+
+        let errors = Packet::validate_verbose(&packet);
+        assert!(matches!(
+            errors[..],
+            [
+                PacketError::Header(HeaderViewError::UnknownResponseCode(_)),
+                PacketError::Question(QuestionError::Label(LabelError::IllegalLabelChar(b'_'))),
+                PacketError::Answer(ResourceError::EOF),
+            ]
+        ));
+    }
+
+    #[test]
+    fn validate_verbose_reports_missing_entries() {
+        #[rustfmt::skip]
+        let packet = [
+            0, 1, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0,
+            3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+            0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 8, 8, 8, 8,
+        ];
+        let errors = Packet::validate_verbose(&packet);
+        assert!(matches!(
+            errors[..],
+            [PacketError::TooFewAnswers {
+                expected: 2,
+                found: 1
+            }]
+        ));
+    }
+
+    #[test]
+    fn offset_past_the_end_is_an_error() {
+        assert!(matches!(
+            Packet::parse(&[0; 4], 8),
+            Err(PacketError::Header(HeaderViewError::IncorrectHeaderSize(0)))
+        ));
+    }
+}
@@ -1,9 +1,13 @@
-use std::fmt;
+use core::fmt;
 
 use thiserror::Error;
 
 use super::{
-    class::QClass, domain_name::DomainName, label::LabelError, types::QType, FromPacketBytes,
+    class::QClass,
+    cursor::{Cursor, CursorError},
+    domain_name::DomainName,
+    label::{Label, LabelError},
+    types::QType,
 };
 
 #[derive(Clone, Copy)]
@@ -18,43 +22,48 @@ pub enum QuestionError {
     Label(#[from] LabelError),
     #[error("TODO")]
     EOF,
+    #[error("The question name points to {0}, which is not before the question")]
+    ForwardPointer(usize),
 }
 
 impl<'data> Question<'data> {
+    fn cursor(&self) -> Cursor<'data> {
+        Cursor::new(self.buffer, self.offset)
+    }
+
+    /// A cursor placed after the question name, at the start of the fixed size fields.
+    fn fields(&self) -> Cursor<'data> {
+        let mut cursor = self.cursor();
+        cursor
+            .read_name()
+            .expect("Domain name to be checked before an instance of Question was created");
+        cursor
+    }
+
     pub fn name(&self) -> DomainName<'data> {
-        DomainName::parse(self.buffer, self.offset)
+        self.cursor()
+            .read_name()
             .expect("Domain name to be checked before an instance of Question was created")
-            .expect("Domain name to be present")
     }
 
     pub fn q_type(&self) -> QType {
-        let name_size = self.name().size_in_packet();
-        QType::try_from(u16::from_be_bytes([
-            *self
-                .buffer
-                .get(self.offset + name_size)
+        QType::from(
+            self.fields()
+                .read_u16()
                 .expect("Q type value to be present"),
-            *self
-                .buffer
-                .get(self.offset + name_size + 1)
-                .expect("Q type value to be present"),
-        ]))
-        .expect("Q type to be valid")
+        )
     }
 
     pub fn q_class(&self) -> QClass {
-        let name_size = self.name().size_in_packet();
-        QClass::try_from(u16::from_be_bytes([
-            *self
-                .buffer
-                .get(self.offset + name_size + 2)
-                .expect("Q class value to be present"),
-            *self
-                .buffer
-                .get(self.offset + name_size + 3)
-                .expect("Q class value to be present"),
-        ]))
-        .expect("Q class to be valid")
+        let mut cursor = self.fields();
+        cursor.skip(2).expect("Q type value to be present");
+        QClass::from(cursor.read_u16().expect("Q class value to be present"))
+    }
+
+    /// The bytes of the question exactly as they are in the packet. A name compressed with a
+    /// pointer is not expanded, so the pointer is still relative to the whole packet.
+    pub fn as_bytes(&self) -> &'data [u8] {
+        &self.buffer[self.offset..self.offset + self.size_in_packet()]
     }
 
     pub fn size_in_packet(&self) -> usize {
@@ -66,12 +75,20 @@ impl<'data> super::FromPacketBytes<'data> for Question<'data> {
     type Error = QuestionError;
 
     fn parse(bytes: &'data [u8], offset: usize) -> Result<Option<Self>, Self::Error> {
-        let Some(name) = DomainName::parse(&bytes, offset)? else {
-            return Ok(None);
-        };
-        let name_size = name.size_in_packet();
-        if offset + name_size + 4 > bytes.len() {
-            return Err(QuestionError::EOF);
+        let mut cursor = Cursor::new(bytes, offset);
+        cursor.read_name()?;
+        cursor.skip(4)?;
+        // The questions come before every record, so a compressed question name can only point
+        // back into the questions before it. Later pointers of the name point further back.
+        let pointer = Label::parse(bytes, offset)?
+            .into_iter()
+            .flatten()
+            .find_map(|label| match label {
+                Ok(Label::Pointer { offset, .. }) => Some(offset),
+                _ => None,
+            });
+        if let Some(target) = pointer.filter(|&target| target >= offset) {
+            return Err(QuestionError::ForwardPointer(target));
         }
         Ok(Some(Self {
             buffer: bytes,
@@ -80,8 +97,28 @@ impl<'data> super::FromPacketBytes<'data> for Question<'data> {
     }
 }
 
+impl From<CursorError> for QuestionError {
+    fn from(value: CursorError) -> Self {
+        match value {
+            CursorError::EOF { .. } => QuestionError::EOF,
+            CursorError::Label(err) => QuestionError::Label(err),
+        }
+    }
+}
+
+/// Questions are equal when their contents are, no matter which packet they are in.
+impl<'a, 'b> PartialEq<Question<'b>> for Question<'a> {
+    fn eq(&self, other: &Question<'b>) -> bool {
+        self.name().eq_ignore_ascii_case(&other.name())
+            && self.q_type() == other.q_type()
+            && self.q_class() == other.q_class()
+    }
+}
+
+impl<'a> Eq for Question<'a> {}
+
 impl<'a> fmt::Display for Question<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
             "{} {:?} {:?}",
@@ -101,3 +138,66 @@ impl<'a> fmt::Debug for Question<'a> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::FromPacketBytes;
+
+    #[test]
+    fn as_bytes_round_trips() {
+        let packet = [0xff, 3, b'c', b'o', b'm', 0, 0, 15, 0, 1, 0xff];
+        let question = Question::parse(&packet, 1).unwrap().unwrap();
+        let bytes = question.as_bytes();
+        assert_eq!(bytes.len(), question.size_in_packet());
+        assert_eq!(bytes, &packet[1..10]);
+
+        let reparsed = Question::parse(bytes, 0).unwrap().unwrap();
+        assert_eq!(reparsed, question);
+        assert_eq!(reparsed.name().to_string(), "com.");
+        assert_eq!(reparsed.q_type(), QType::MX);
+    }
+
+    #[test]
+    fn equality_ignores_name_case() {
+        let lower = [3, b'c', b'o', b'm', 0, 0, 15, 0, 1];
+        let upper = [3, b'C', b'O', b'M', 0, 0, 15, 0, 1];
+        let other_type = [3, b'c', b'o', b'm', 0, 0, 1, 0, 1];
+        let question = Question::parse(&lower, 0).unwrap().unwrap();
+        assert_eq!(question, Question::parse(&upper, 0).unwrap().unwrap());
+        assert_ne!(question, Question::parse(&other_type, 0).unwrap().unwrap());
+    }
+
+    #[test]
+    fn leading_underscore_labels() {
+        #[rustfmt::skip]
+        let packet = [
+            6, b'_', b'd', b'm', b'a', b'r', b'c', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+            3, b'c', b'o', b'm', 0, 0, 16, 0, 1,
+        ];
+        let question = Question::parse(&packet, 0).unwrap().unwrap();
+        assert_eq!(question.name().to_string(), "_dmarc.example.com.");
+
+        // Only a leading underscore is allowed.
+        let mut packet = packet;
+        packet[1..3].copy_from_slice(b"d_");
+        assert!(Question::parse(&packet, 0).is_err());
+    }
+
+    #[test]
+    fn pointers_must_point_before_the_question() {
+        let packet = [
+            3, b'c', b'o', b'm', 0, 3, b'w', b'w', b'w', 0xc0, 0, 0, 1, 0, 1,
+        ];
+        let question = Question::parse(&packet, 5).unwrap().unwrap();
+        assert_eq!(question.name().to_string(), "www.com.");
+
+        let packet = [
+            3, b'w', b'w', b'w', 0xc0, 10, 0, 1, 0, 1, 3, b'c', b'o', b'm', 0,
+        ];
+        assert!(matches!(
+            Question::parse(&packet, 0),
+            Err(QuestionError::ForwardPointer(10))
+        ));
+    }
+}
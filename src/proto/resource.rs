@@ -1,16 +1,23 @@
 use thiserror::Error;
 
-use std::fmt;
+use core::fmt;
 
 use super::{
-    class::Class, domain_name::DomainName, label::LabelError, types::Type, DebugList,
-    FromPacketBytes,
+    class::Class,
+    cursor::{Cursor, CursorError},
+    domain_name::DomainName,
+    label::LabelError,
+    types::Type,
+    DebugList, FromPacketBytes,
 };
 
 #[derive(Clone, Copy)]
 pub struct Resource<'data> {
     pub(super) offset: usize,
     pub(super) buffer: &'data [u8],
+    /// The size of the owner name in the packet, measured when parsed so the fixed size fields
+    /// can be found without walking the labels again.
+    pub(super) name_size: usize,
 }
 
 #[derive(Debug, Error)]
@@ -19,68 +26,71 @@ pub enum ResourceError {
     Label(#[from] LabelError),
     #[error("TODO")]
     EOF,
+    #[error("A record of type {typ:?} can't have {len} bytes of data")]
+    DataLength { typ: Type, len: usize },
 }
 
 impl<'data> Resource<'data> {
+    fn cursor(&self) -> Cursor<'data> {
+        Cursor::new(self.buffer, self.offset)
+    }
+
+    /// A cursor placed after the owner name, at the start of the fixed size fields.
+    fn fields(&self) -> Cursor<'data> {
+        Cursor::new(self.buffer, self.offset + self.name_size)
+    }
+
     pub fn name(&self) -> DomainName<'data> {
-        DomainName::parse(self.buffer, self.offset)
-            .expect("Domain name to be checked before an instance of Question was created")
-            .expect("Domain name to be present")
+        self.cursor()
+            .read_name()
+            .expect("Domain name to be checked before an instance of Resource was created")
     }
 
     pub fn typ(&self) -> Type {
-        let name_size = self.name().size_in_packet();
-        Type::try_from(u16::from_be_bytes([
-            self.buffer[self.offset + name_size],
-            self.buffer[self.offset + name_size + 1],
-        ]))
-        .expect("Type to be valid")
+        Type::from(self.fields().read_u16().expect("Type to be present"))
     }
 
     pub fn class(&self) -> Class {
-        let name_size = self.name().size_in_packet();
-        Class::try_from(u16::from_be_bytes([
-            self.buffer[self.offset + name_size + 2],
-            self.buffer[self.offset + name_size + 3],
-        ]))
-        .expect("Class to be valid")
+        let mut cursor = self.fields();
+        cursor.skip(2).expect("Type to be present");
+        Class::from(cursor.read_u16().expect("Class to be present"))
     }
 
     pub fn ttl(&self) -> u32 {
-        let name_size = self.name().size_in_packet();
-        u32::from_be_bytes([
-            self.buffer[self.offset + name_size + 4],
-            self.buffer[self.offset + name_size + 5],
-            self.buffer[self.offset + name_size + 6],
-            self.buffer[self.offset + name_size + 7],
-        ])
+        let mut cursor = self.fields();
+        cursor.skip(4).expect("Type and class to be present");
+        cursor.read_u32().expect("TTL to be present")
     }
 
     pub fn data_len(&self) -> usize {
-        let name_size = self.name().size_in_packet();
-        u16::from_be_bytes([
-            self.buffer[self.offset + name_size + 8],
-            self.buffer[self.offset + name_size + 9],
-        ]) as usize
+        let mut cursor = self.fields();
+        cursor.skip(8).expect("Type, class and TTL to be present");
+        cursor.read_u16().expect("Data length to be present") as usize
+    }
+
+    /// The RDATA of the resource. Returns an error if the data length points outside of the
+    /// packet.
+    pub fn data(&self) -> Result<&'data [u8], ResourceError> {
+        let mut cursor = self.fields();
+        cursor.skip(8)?;
+        let data_len = cursor.read_u16()? as usize;
+        Ok(cursor.read_slice(data_len)?)
+    }
+
+    /// A cursor at the start of the RDATA. It reads from the whole packet, so compressed names in
+    /// the data can be followed.
+    pub fn data_cursor(&self) -> Cursor<'data> {
+        Cursor::new(self.buffer, self.offset + self.name_size + 10)
     }
 
-    pub fn data(&self) -> &'data [u8] {
-        let name_size = self.name().size_in_packet();
-        let start = self.offset + name_size + 10;
-        let data_len = u16::from_be_bytes([
-            self.buffer[self.offset + name_size + 8],
-            self.buffer[self.offset + name_size + 9],
-        ]) as usize;
-        &self.buffer[start..start + data_len]
+    /// The bytes of the resource exactly as they are in the packet. A name compressed with a
+    /// pointer is not expanded, so the pointer is still relative to the whole packet.
+    pub fn as_bytes(&self) -> &'data [u8] {
+        &self.buffer[self.offset..self.offset + self.size_in_packet()]
     }
 
     pub fn size_in_packet(&self) -> usize {
-        let name_size = self.name().size_in_packet();
-        let data_len = u16::from_be_bytes([
-            self.buffer[self.offset + name_size + 8],
-            self.buffer[self.offset + name_size + 9],
-        ]) as usize;
-        10 + name_size + data_len
+        10 + self.name_size + self.data_len()
     }
 }
 
@@ -88,28 +98,57 @@ impl<'data> FromPacketBytes<'data> for Resource<'data> {
     type Error = ResourceError;
 
     fn parse(bytes: &'data [u8], offset: usize) -> Result<Option<Self>, Self::Error> {
-        let Some(name) = DomainName::parse(bytes, offset)? else {
-            return Ok(None);
+        let mut cursor = Cursor::new(bytes, offset);
+        let name_size = cursor.read_name()?.size_in_packet();
+        let typ = Type::from(cursor.read_u16()?);
+        let class = Class::from(cursor.read_u16()?);
+        cursor.skip(4)?;
+        let data_length = cursor.read_u16()? as usize;
+        // Addresses of class IN have a fixed size, which is relied on when reading them.
+        let expected = match (typ, class) {
+            (Type::A, Class::IN) => Some(4),
+            (Type::AAAA, Class::IN) => Some(16),
+            _ => None,
         };
-        let name_size = name.size_in_packet();
-        if offset + name_size + 10 > bytes.len() {
-            return Err(ResourceError::EOF);
-        }
-        let data_length =
-            u16::from_be_bytes([bytes[offset + name_size + 8], bytes[offset + name_size + 9]])
-                as usize;
-        if offset + name_size + 10 + data_length > bytes.len() {
-            return Err(ResourceError::EOF);
+        if expected.is_some_and(|expected| expected != data_length) {
+            return Err(ResourceError::DataLength {
+                typ,
+                len: data_length,
+            });
         }
+        cursor.skip(data_length)?;
         Ok(Some(Self {
             buffer: bytes,
             offset,
+            name_size,
         }))
     }
 }
 
+impl From<CursorError> for ResourceError {
+    fn from(value: CursorError) -> Self {
+        match value {
+            CursorError::EOF { .. } => ResourceError::EOF,
+            CursorError::Label(err) => ResourceError::Label(err),
+        }
+    }
+}
+
+/// Resources are equal when their contents are, no matter which packet they are in.
+impl<'a, 'b> PartialEq<Resource<'b>> for Resource<'a> {
+    fn eq(&self, other: &Resource<'b>) -> bool {
+        self.name().eq_ignore_ascii_case(&other.name())
+            && self.typ() == other.typ()
+            && self.class() == other.class()
+            && self.ttl() == other.ttl()
+            && self.data().ok() == other.data().ok()
+    }
+}
+
+impl<'a> Eq for Resource<'a> {}
+
 impl<'a> fmt::Display for Resource<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
             "{} {} {:?} {:?}",
@@ -129,7 +168,120 @@ impl<'a> fmt::Debug for Resource<'a> {
             .field("type", &self.typ())
             .field("class", &self.class())
             .field("data_len", &self.data_len())
-            .field("data", &DebugList(|| self.data().iter()))
+            .field(
+                "data",
+                &DebugList(|| self.data().unwrap_or_default().iter()),
+            )
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // codecrafters.io. IN A 60 8.8.8.8
+    const RESOURCE: [u8; 31] = [
+        12, b'c', b'o', b'd', b'e', b'c', b'r', b'a', b'f', b't', b'e', b'r', b's', 2, b'i', b'o',
+        0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 8, 8, 8, 8,
+    ];
+
+    #[test]
+    fn parse_resource() {
+        let resource = Resource::parse(&RESOURCE, 0).unwrap().unwrap();
+        assert_eq!(resource.typ(), Type::A);
+        assert_eq!(resource.class(), Class::IN);
+        assert_eq!(resource.ttl(), 60);
+        assert_eq!(resource.data().unwrap(), &[8, 8, 8, 8]);
+        assert_eq!(resource.size_in_packet(), RESOURCE.len());
+    }
+
+    #[test]
+    fn address_with_wrong_length_is_rejected() {
+        // codecrafters.io. IN A 60 8.8.8
+        let mut short = RESOURCE[..RESOURCE.len() - 1].to_vec();
+        short[26] = 3;
+        assert!(matches!(
+            Resource::parse(&short, 0),
+            Err(ResourceError::DataLength {
+                typ: Type::A,
+                len: 3
+            })
+        ));
+        assert!(Resource::parse(&RESOURCE, 0).unwrap().is_some());
+
+        // The A records of other classes have a different format.
+        let mut chaos = short;
+        chaos[19] = 3;
+        assert!(Resource::parse(&chaos, 0).unwrap().is_some());
+    }
+
+    #[test]
+    fn accessors_after_compressed_name() {
+        // A second record for the same name, pointing back to the first.
+        let mut packet = RESOURCE.to_vec();
+        packet.extend_from_slice(&[0xc0, 0, 0, 16, 0, 1, 0, 0, 1, 0, 0, 2, 1, 2]);
+        let resource = Resource::parse(&packet, RESOURCE.len()).unwrap().unwrap();
+        assert_eq!(resource.name().to_string(), "codecrafters.io.");
+        assert_eq!(resource.typ(), Type::TXT);
+        assert_eq!(resource.class(), Class::IN);
+        assert_eq!(resource.ttl(), 256);
+        assert_eq!(resource.data_len(), 2);
+        assert_eq!(resource.data().unwrap(), &[1, 2]);
+        assert_eq!(resource.size_in_packet(), packet.len() - RESOURCE.len());
+    }
+
+    #[test]
+    fn truncated_resource_is_an_error() {
+        for len in 0..RESOURCE.len() {
+            assert!(
+                Resource::parse(&RESOURCE[..len], 0).is_err(),
+                "Resource truncated to {len} bytes parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn data_outside_packet_is_an_error() {
+        let resource = Resource {
+            offset: 0,
+            buffer: &RESOURCE[..RESOURCE.len() - 1],
+            name_size: 17,
+        };
+        assert!(matches!(resource.data(), Err(ResourceError::EOF)));
+    }
+
+    #[test]
+    fn as_bytes_round_trips() {
+        let mut packet = vec![0xff; 3];
+        packet.extend_from_slice(&RESOURCE);
+        let resource = Resource::parse(&packet, 3).unwrap().unwrap();
+        let bytes = resource.as_bytes();
+        assert_eq!(bytes.len(), resource.size_in_packet());
+        assert_eq!(bytes, &RESOURCE);
+
+        let reparsed = Resource::parse(bytes, 0).unwrap().unwrap();
+        assert_eq!(reparsed, resource);
+    }
+
+    #[test]
+    fn equality_compares_contents() {
+        let resource = Resource::parse(&RESOURCE, 0).unwrap().unwrap();
+
+        // The same record with its name in upper case, after a compressed copy of it.
+        let mut packet = RESOURCE.to_vec();
+        packet[1..13].make_ascii_uppercase();
+        packet.extend_from_slice(&[0xc0, 0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 8, 8, 8, 8]);
+        let uppercase = Resource::parse(&packet, 0).unwrap().unwrap();
+        let compressed = Resource::parse(&packet, RESOURCE.len()).unwrap().unwrap();
+        assert_eq!(resource, uppercase);
+        assert_eq!(resource, compressed);
+
+        let mut other_ttl = RESOURCE;
+        other_ttl[24] = 61;
+        assert_ne!(resource, Resource::parse(&other_ttl, 0).unwrap().unwrap());
+        let mut other_data = RESOURCE;
+        other_data[30] = 4;
+        assert_ne!(resource, Resource::parse(&other_data, 0).unwrap().unwrap());
+    }
+}
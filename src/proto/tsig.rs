@@ -0,0 +1,155 @@
+//! The TSIG record (RFC 8945) is a meta record placed last in the additional section of a signed
+//! message. Its RDATA has the following structure, and multibyte items are in big endian order.
+//! ```text
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     /                 ALGORITHM NAME                /
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     |                                               |
+//!     |          TIME SIGNED                          |
+//!     |                                               |
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     |                     FUDGE                     |
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     |                    MAC SIZE                   |
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     /                      MAC                      /
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     |                  ORIGINAL ID                  |
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     |                     ERROR                     |
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     |                   OTHER LEN                   |
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//!     /                   OTHER DATA                  /
+//!     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+//! ```
+
+use core::fmt;
+
+use thiserror::Error;
+
+use super::{
+    cursor::{Cursor, CursorError},
+    domain_name::DomainName,
+    resource::Resource,
+    types::Type,
+};
+
+#[derive(Clone, Copy)]
+pub struct Tsig<'data> {
+    resource: Resource<'data>,
+    algorithm: DomainName<'data>,
+    time_signed: u64,
+    fudge: u16,
+    mac: &'data [u8],
+    original_id: u16,
+    error: u16,
+    other: &'data [u8],
+}
+
+#[derive(Debug, Error)]
+pub enum TsigError {
+    #[error(transparent)]
+    Cursor(#[from] CursorError),
+    #[error("The TSIG record data is {expected} bytes but {found} bytes were used")]
+    DataLength { expected: usize, found: usize },
+}
+
+impl<'data> Tsig<'data> {
+    /// Read the TSIG record from a resource. Returns `None` when the resource is not a TSIG
+    /// record.
+    pub fn from_resource(resource: Resource<'data>) -> Result<Option<Self>, TsigError> {
+        if resource.typ() != Type::TSIG {
+            return Ok(None);
+        }
+        let mut cursor = Cursor::new(resource.buffer, resource.offset);
+        cursor.read_name()?;
+        cursor.skip(8)?;
+        let data_len = cursor.read_u16()? as usize;
+        let data_start = cursor.offset();
+
+        let algorithm = cursor.read_name()?;
+        let time_signed = ((cursor.read_u16()? as u64) << 32) | (cursor.read_u32()? as u64);
+        let fudge = cursor.read_u16()?;
+        let mac_len = cursor.read_u16()? as usize;
+        let mac = cursor.read_slice(mac_len)?;
+        let original_id = cursor.read_u16()?;
+        let error = cursor.read_u16()?;
+        let other_len = cursor.read_u16()? as usize;
+        let other = cursor.read_slice(other_len)?;
+
+        if cursor.offset() - data_start != data_len {
+            return Err(TsigError::DataLength {
+                expected: data_len,
+                found: cursor.offset() - data_start,
+            });
+        }
+
+        Ok(Some(Self {
+            resource,
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        }))
+    }
+
+    /// The name of the key used to sign the message.
+    pub fn key_name(&self) -> DomainName<'data> {
+        self.resource.name()
+    }
+
+    /// The offset of the TSIG record in the packet. Everything before it is covered by the MAC.
+    pub fn offset(&self) -> usize {
+        self.resource.offset
+    }
+
+    pub fn algorithm(&self) -> DomainName<'data> {
+        self.algorithm
+    }
+
+    /// Seconds since the UNIX epoch when the message was signed.
+    pub fn time_signed(&self) -> u64 {
+        self.time_signed
+    }
+
+    /// Seconds of error permitted in [Tsig::time_signed].
+    pub fn fudge(&self) -> u16 {
+        self.fudge
+    }
+
+    pub fn mac(&self) -> &'data [u8] {
+        self.mac
+    }
+
+    /// The message id before any forwarding server changed it.
+    pub fn original_id(&self) -> u16 {
+        self.original_id
+    }
+
+    pub fn error(&self) -> u16 {
+        self.error
+    }
+
+    pub fn other(&self) -> &'data [u8] {
+        self.other
+    }
+}
+
+impl<'a> fmt::Debug for Tsig<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tsig")
+            .field("key_name", &self.key_name())
+            .field("algorithm", &self.algorithm)
+            .field("time_signed", &self.time_signed)
+            .field("fudge", &self.fudge)
+            .field("mac", &self.mac)
+            .field("original_id", &self.original_id)
+            .field("error", &self.error)
+            .field("other", &self.other)
+            .finish()
+    }
+}
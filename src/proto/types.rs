@@ -16,7 +16,26 @@ pub [Type, QType, Unknown]:
     MINFO => 14,
     MX => 15,
     TXT => 16,
+    RP => 17,
+    AFSDB => 18,
+    AAAA => 28,
+    LOC => 29,
+    SRV => 33,
+    NAPTR => 35,
     OPT => 41,
+    DS => 43,
+    SSHFP => 44,
+    RRSIG => 46,
+    NSEC => 47,
+    DNSKEY => 48,
+    NSEC3 => 50,
+    TLSA => 52,
+    OPENPGPKEY => 61,
+    CSYNC => 62,
+    SVCB => 64,
+    HTTPS => 65,
+    TSIG => 250,
+    CAA => 257,
 
     [AXFR => 252],
     [MAILB => 253],
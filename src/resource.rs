@@ -1,4 +1,8 @@
-use std::{net::Ipv4Addr, sync::Arc};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::Instant,
+};
 
 use crate::{
     domain_name::DomainName,
@@ -14,59 +18,478 @@ pub enum ResourceData {
         ttl: u32,
         addr: Ipv4Addr,
     },
-    //AAAA {
-    //    ttl: u32,
-    //    addr: Ipv6Addr,
-    //},
+    AAAA {
+        ttl: u32,
+        addr: Ipv6Addr,
+    },
+    /// The data of any other type. Names in the data are stored uncompressed, as a compression
+    /// pointer is only valid within the packet it was read from. Only the types of RFC 1035 with
+    /// names in their data may be compressed (RFC 3597 section 4), so those names are expanded
+    /// when read from a packet. The data of every other type, including unknown ones, never holds
+    /// a pointer and is kept byte for byte.
     Generic {
         typ: Type,
         class: Class,
         ttl: u32,
         data: Arc<[u8]>,
     },
+    /// Child-to-parent synchronization of class IN (RFC 7477). The types to synchronize are a
+    /// type bit map as in NSEC records.
+    Csync {
+        ttl: u32,
+        soa_serial: u32,
+        flags: u16,
+        type_bitmap: Arc<[u8]>,
+    },
+    /// An OpenPGP public key of class IN (RFC 7929), kept as the bytes of the key.
+    OpenPgpKey {
+        ttl: u32,
+        key: Arc<[u8]>,
+    },
+    /// An OPT pseudo-record (RFC 6891). Its class is the UDP payload size of the sender and its
+    /// TTL holds the upper bits of the response code, the EDNS version and the flags, so they are
+    /// kept apart instead of passing for a class and TTL.
+    Opt {
+        udp_payload_size: u16,
+        extended_rcode: u8,
+        version: u8,
+        flags: u16,
+        data: Arc<[u8]>,
+    },
 }
 
 impl ResourceData {
-    pub fn class(&self) -> &Class {
+    /// The record with the type, class, TTL and data of its wire format. Records of class IN of
+    /// the types with a variant are stored as that variant.
+    pub fn from_parts(typ: Type, class: Class, ttl: u32, data: &[u8]) -> Self {
+        match (typ, class) {
+            (Type::OPT, _) => Self::opt(class, ttl, Arc::from(data)),
+            (Type::A, Class::IN) if data.len() == 4 => Self::A {
+                ttl,
+                addr: Ipv4Addr::from(<[u8; 4]>::try_from(data).unwrap()),
+            },
+            (Type::AAAA, Class::IN) if data.len() == 16 => Self::AAAA {
+                ttl,
+                addr: Ipv6Addr::from(<[u8; 16]>::try_from(data).unwrap()),
+            },
+            (Type::CSYNC, Class::IN) if data.len() >= 6 => Self::Csync {
+                ttl,
+                soa_serial: u32::from_be_bytes(data[..4].try_into().unwrap()),
+                flags: u16::from_be_bytes([data[4], data[5]]),
+                type_bitmap: Arc::from(&data[6..]),
+            },
+            (Type::OPENPGPKEY, Class::IN) => Self::OpenPgpKey {
+                ttl,
+                key: Arc::from(data),
+            },
+            _ => Self::Generic {
+                typ,
+                class,
+                ttl,
+                data: Arc::from(data),
+            },
+        }
+    }
+
+    fn opt(class: Class, ttl: u32, data: Arc<[u8]>) -> Self {
+        let [extended_rcode, version, flags @ ..] = ttl.to_be_bytes();
+        Self::Opt {
+            udp_payload_size: class.as_u16(),
+            extended_rcode,
+            version,
+            flags: u16::from_be_bytes(flags),
+            data,
+        }
+    }
+
+    /// The class as written in the record. For OPT it is the UDP payload size.
+    pub fn class(&self) -> Class {
         match self {
-            Self::A { .. } => &Class::IN,
-            //Self::AAAA { .. } => &Class::IN,
-            Self::Generic { class, .. } => class,
+            Self::A { .. } => Class::IN,
+            Self::AAAA { .. } => Class::IN,
+            Self::Csync { .. } => Class::IN,
+            Self::OpenPgpKey { .. } => Class::IN,
+            Self::Generic { class, .. } => *class,
+            Self::Opt {
+                udp_payload_size, ..
+            } => Class::from(*udp_payload_size),
         }
     }
 
     pub fn typ(&self) -> &Type {
         match self {
             Self::A { .. } => &Type::A,
-            //Self::AAAA { .. } => &Type::AAAA,
+            Self::AAAA { .. } => &Type::AAAA,
+            Self::Csync { .. } => &Type::CSYNC,
+            Self::OpenPgpKey { .. } => &Type::OPENPGPKEY,
             Self::Generic { typ, .. } => typ,
+            Self::Opt { .. } => &Type::OPT,
         }
     }
 
-    pub fn ttl(&self) -> &u32 {
+    /// The TTL as written in the record. For OPT it holds the extended response code, version
+    /// and flags.
+    pub fn ttl(&self) -> u32 {
         match self {
-            Self::A { ttl, .. } => ttl,
-            //Self::AAAA { ttl, .. } => ttl,
-            Self::Generic { ttl, .. } => ttl,
+            Self::A { ttl, .. } => *ttl,
+            Self::AAAA { ttl, .. } => *ttl,
+            Self::Csync { ttl, .. } => *ttl,
+            Self::OpenPgpKey { ttl, .. } => *ttl,
+            Self::Generic { ttl, .. } => *ttl,
+            Self::Opt {
+                extended_rcode,
+                version,
+                flags,
+                ..
+            } => {
+                let [high, low] = flags.to_be_bytes();
+                u32::from_be_bytes([*extended_rcode, *version, high, low])
+            }
+        }
+    }
+
+    /// The record with its TTL replaced by `ttl`. The TTL of OPT records is not a TTL, so they
+    /// are returned unchanged.
+    pub fn with_ttl(&self, ttl: u32) -> Self {
+        let mut data = self.clone();
+        match &mut data {
+            Self::A { ttl: old, .. }
+            | Self::AAAA { ttl: old, .. }
+            | Self::Csync { ttl: old, .. }
+            | Self::OpenPgpKey { ttl: old, .. }
+            | Self::Generic { ttl: old, .. } => *old = ttl,
+            Self::Opt { .. } => {}
         }
+        data
     }
 
     pub fn data(&self) -> CowData<'_> {
         match self {
-            Self::A { addr, .. } => CowData::Owned(Arc::from(addr.octets())),
-            //Self::AAAA { addr, .. } => &addr.octets(),
-            Self::Generic { data, .. } => CowData::Owned(Arc::clone(&data)),
+            Self::A { addr, .. } => CowData::from(*addr),
+            Self::AAAA { addr, .. } => CowData::from(*addr),
+            Self::Csync {
+                soa_serial,
+                flags,
+                type_bitmap,
+                ..
+            } => {
+                let mut data = Vec::with_capacity(6 + type_bitmap.len());
+                data.extend_from_slice(&soa_serial.to_be_bytes());
+                data.extend_from_slice(&flags.to_be_bytes());
+                data.extend_from_slice(type_bitmap);
+                CowData::Owned(Arc::from(data))
+            }
+            Self::Generic { data, .. }
+            | Self::OpenPgpKey { key: data, .. }
+            | Self::Opt { data, .. } => CowData::Owned(Arc::clone(&data)),
         }
     }
 }
 
-impl<'data> From<crate::proto::Resource<'data>> for ResourceData {
-    fn from(value: crate::proto::Resource<'data>) -> Self {
-        ResourceData::Generic {
+/// Record data with the time it was received, so the TTL it has left can be told later on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Received {
+    pub data: ResourceData,
+    pub at: Instant,
+}
+
+impl Received {
+    /// The TTL left at `now`, which is the TTL of the record less the whole seconds since it was
+    /// received, and zero once it expired.
+    pub fn ttl_remaining(&self, now: Instant) -> u32 {
+        let elapsed = now.saturating_duration_since(self.at).as_secs();
+        self.data
+            .ttl()
+            .saturating_sub(u32::try_from(elapsed).unwrap_or(u32::MAX))
+    }
+}
+
+/// The length of the data before the names in the data of `typ`, and the number of names in a
+/// row after it, for the types whose names may be compressed.
+fn compressible_names(typ: Type) -> Option<(usize, usize)> {
+    match typ {
+        Type::NS
+        | Type::MD
+        | Type::MF
+        | Type::CNAME
+        | Type::MB
+        | Type::MG
+        | Type::MR
+        | Type::PTR => Some((0, 1)),
+        Type::SOA | Type::MINFO => Some((0, 2)),
+        Type::MX => Some((2, 1)),
+        _ => None,
+    }
+}
+
+impl<'data> TryFrom<crate::proto::Resource<'data>> for ResourceData {
+    type Error = crate::proto::ResourceError;
+
+    fn try_from(value: crate::proto::Resource<'data>) -> Result<Self, Self::Error> {
+        let data = match compressible_names(value.typ()) {
+            Some((prefix, names)) => {
+                let mut cursor = value.data_cursor();
+                let end = cursor.offset() + value.data_len();
+                let mut data = cursor.read_slice(prefix)?.to_vec();
+                for _ in 0..names {
+                    data.extend(DomainName::from(&cursor.read_name()?).to_bytes());
+                }
+                // The names must end within the data.
+                let rest = end
+                    .checked_sub(cursor.offset())
+                    .ok_or(crate::proto::ResourceError::EOF)?;
+                data.extend_from_slice(cursor.read_slice(rest)?);
+                Arc::from(data)
+            }
+            None => Arc::from(value.data()?),
+        };
+        if value.typ() == Type::OPT {
+            return Ok(ResourceData::opt(value.class(), value.ttl(), data));
+        }
+        Ok(ResourceData::Generic {
             typ: value.typ(),
             class: value.class(),
             ttl: value.ttl(),
-            data: Arc::from(value.data()),
+            data,
+        })
+    }
+}
+
+impl<'data> crate::proto::Resource<'data> {
+    /// The owner name and data of the record as owned values, so they outlive the packet buffer.
+    /// Records of the types with a variant are stored as it, like with
+    /// [ResourceData::from_parts].
+    pub fn into_owned(self) -> Result<(DomainName, ResourceData), crate::proto::ResourceError> {
+        let data = match ResourceData::try_from(self)? {
+            ResourceData::Generic {
+                typ: typ @ (Type::A | Type::AAAA | Type::CSYNC | Type::OPENPGPKEY),
+                class,
+                ttl,
+                data,
+            } => ResourceData::from_parts(typ, class, ttl, &data),
+            data => data,
+        };
+        Ok((DomainName::from(&self.name()), data))
+    }
+
+    /// Like [Resource::into_owned](crate::proto::Resource::into_owned), with the data
+    /// timestamped with `at`, the time the packet was received.
+    pub fn into_received(
+        self,
+        at: Instant,
+    ) -> Result<(DomainName, Received), crate::proto::ResourceError> {
+        let (name, data) = self.into_owned()?;
+        Ok((name, Received { data, at }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        array_buffer::ArrayBuffer,
+        packet::DNSPacketBuilder,
+        proto::{self, FromPacketBytes, QClass, QType},
+        question::Question,
+    };
+
+    /// Read the answers of `packet` and write them into a packet without a question, so no name
+    /// is at the same offset as before.
+    fn reserialize(packet: &ArrayBuffer) -> Vec<(Type, Vec<u8>)> {
+        let packet = proto::Packet::parse(packet, 0).unwrap().unwrap();
+        let mut builder = DNSPacketBuilder::query(2);
+        for answer in packet.answers() {
+            let name = DomainName::from(&answer.name());
+            let data = ResourceData::try_from(answer).unwrap();
+            builder = builder.add_answer(Resource(name, Arc::new(data)));
         }
+        let mut buffer = ArrayBuffer::new();
+        builder.build_into(&mut buffer);
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        packet
+            .answers()
+            .map(|answer| {
+                let data = ResourceData::try_from(answer).unwrap();
+                (*data.typ(), data.data().as_ref().to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records_survive_moving_between_packets() {
+        let name = DomainName::from_static("www.example.com");
+        let target = DomainName::from_static("example.com");
+        // Looks like a pointer to the question name, but an unknown type holds no names.
+        let unknown = vec![0xc0, 0x0c, 1, 2];
+        let mut packet = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_question(Question::new(QType::A, QClass::IN, name.clone()))
+            .add_answer(Resource(
+                name.clone(),
+                Arc::new(ResourceData::Generic {
+                    typ: Type::CNAME,
+                    class: Class::IN,
+                    ttl: 60,
+                    data: Arc::from(target.to_bytes()),
+                }),
+            ))
+            .add_answer(Resource(
+                name,
+                Arc::new(ResourceData::Generic {
+                    typ: Type::from(65280),
+                    class: Class::IN,
+                    ttl: 60,
+                    data: Arc::from(unknown.clone()),
+                }),
+            ))
+            .build_into(&mut packet);
+
+        assert_eq!(
+            reserialize(&packet),
+            [
+                (Type::CNAME, target.to_bytes()),
+                (Type::from(65280), unknown)
+            ]
+        );
+    }
+
+    #[test]
+    fn wks_is_passed_through_unchanged() {
+        let name = DomainName::from_static("example.com");
+        // 192.0.2.1, TCP, with the bits of ports 21, 23 and 25 set.
+        let wks = vec![192, 0, 2, 1, 6, 0, 0, 0, 0b0101_0100];
+        let mut packet = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_question(Question::new(QType::WKS, QClass::IN, name.clone()))
+            .add_answer(Resource(
+                name,
+                Arc::new(ResourceData::Generic {
+                    typ: Type::from(11),
+                    class: Class::IN,
+                    ttl: 60,
+                    data: Arc::from(wks.clone()),
+                }),
+            ))
+            .build_into(&mut packet);
+
+        let parsed = proto::Packet::parse(&packet, 0).unwrap().unwrap();
+        let answer = parsed.answers().next().unwrap();
+        assert_eq!(answer.to_string(), "example.com. 60 IN WKS");
+        assert_eq!(reserialize(&packet), [(Type::WKS, wks)]);
+    }
+
+    #[test]
+    fn owned_record_outlives_the_packet() {
+        let name = DomainName::from_static("www.example.com");
+        let owned = {
+            let mut packet = ArrayBuffer::new();
+            DNSPacketBuilder::query(1)
+                .add_question(Question::new(QType::A, QClass::IN, name.clone()))
+                .add_answer(Resource(
+                    name.clone(),
+                    Arc::new(ResourceData::A {
+                        ttl: 60,
+                        addr: Ipv4Addr::new(192, 0, 2, 1),
+                    }),
+                ))
+                .build_into(&mut packet);
+            let parsed = proto::Packet::parse(&packet, 0).unwrap().unwrap();
+            let answer = parsed.answers().next().unwrap();
+            answer.into_owned().unwrap()
+        };
+        assert_eq!(owned.0, name);
+        assert_eq!(
+            owned.1,
+            ResourceData::A {
+                ttl: 60,
+                addr: Ipv4Addr::new(192, 0, 2, 1),
+            }
+        );
+    }
+
+    #[test]
+    fn remaining_ttl_counts_down_from_receipt() {
+        let mut packet = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_answer(Resource(
+                DomainName::from_static("www.example.com"),
+                Arc::new(ResourceData::A {
+                    ttl: 60,
+                    addr: Ipv4Addr::new(192, 0, 2, 1),
+                }),
+            ))
+            .build_into(&mut packet);
+        let parsed = proto::Packet::parse(&packet, 0).unwrap().unwrap();
+        let received_at = Instant::now();
+        let (_, received) = parsed
+            .answers()
+            .next()
+            .unwrap()
+            .into_received(received_at)
+            .unwrap();
+
+        let after = |secs: u64| received_at + std::time::Duration::from_millis(secs * 1000 + 500);
+        assert_eq!(received.ttl_remaining(received_at), 60);
+        assert_eq!(received.ttl_remaining(after(10)), 50);
+        assert_eq!(received.ttl_remaining(after(59)), 1);
+        assert_eq!(received.ttl_remaining(after(3600)), 0);
+        // The wire TTL is left as it was.
+        assert_eq!(received.data.ttl(), 60);
+    }
+
+    #[test]
+    fn csync_and_openpgpkey_survive_a_round_trip() {
+        let name = DomainName::from_static("example.com");
+        let csync = ResourceData::Csync {
+            ttl: 60,
+            soa_serial: 2024,
+            flags: 3,
+            // Window 0 with the bits of A, NS and AAAA set.
+            type_bitmap: Arc::from(&[0, 4, 0b0110_0000, 0, 0, 0b0000_1000][..]),
+        };
+        let key = ResourceData::OpenPgpKey {
+            ttl: 3600,
+            key: Arc::from(&b"\x99\x01\x0d\x04not really a key"[..]),
+        };
+        let mut packet = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_answer(Resource(name.clone(), Arc::new(csync.clone())))
+            .add_answer(Resource(name, Arc::new(key.clone())))
+            .build_into(&mut packet);
+
+        let parsed = proto::Packet::parse(&packet, 0).unwrap().unwrap();
+        let answers: Vec<_> = parsed.answers().collect();
+        assert_eq!(answers[0].typ(), Type::CSYNC);
+        assert_eq!(
+            answers[0].data().unwrap(),
+            [0, 0, 0x07, 0xe8, 0, 3, 0, 4, 0x60, 0, 0, 8]
+        );
+        assert_eq!(answers[0].into_owned().unwrap().1, csync);
+        assert_eq!(answers[1].typ(), Type::OPENPGPKEY);
+        assert_eq!(answers[1].into_owned().unwrap().1, key);
+    }
+
+    #[test]
+    fn opt_keeps_its_payload_size_and_flags() {
+        let opt = ResourceData::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 1,
+            version: 0,
+            flags: 0x8000,
+            // An empty NSID option.
+            data: Arc::from(&[0, 3, 0, 0][..]),
+        };
+        let mut packet = ArrayBuffer::new();
+        DNSPacketBuilder::query(1)
+            .add_additional(Resource(DomainName::from_static(""), Arc::new(opt.clone())))
+            .build_into(&mut packet);
+
+        let parsed = proto::Packet::parse(&packet, 0).unwrap().unwrap();
+        let edns = parsed.edns().expect("Packet to have an OPT record");
+        assert_eq!(edns.udp_payload_size(), 1232);
+        assert_eq!(edns.extended_response_code(), 1);
+        assert!(edns.dnssec_ok());
+        let additional = parsed.additional().next().unwrap();
+        assert_eq!(ResourceData::try_from(additional).unwrap(), opt);
     }
 }
@@ -0,0 +1,67 @@
+//! Detection of retransmitted requests. A client that gets no timely response over UDP sends the
+//! same request again, with the same ID and questions from the same address. While the first
+//! copy is being answered the retransmission is dropped, as the response to the first copy
+//! answers it too, instead of forwarding the questions again.
+
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::proto::{self, FromPacketBytes};
+
+/// The source, ID and questions of a request.
+type RequestKey = (SocketAddr, u16, Vec<u8>);
+
+#[derive(Debug, Default)]
+pub struct Retransmits {
+    pending: Mutex<HashSet<RequestKey>>,
+}
+
+impl Retransmits {
+    /// Start answering `request` from `source`. Returns `None` for a retransmission of a request
+    /// that is still being answered. The request is answered until the returned guard is dropped.
+    pub fn begin(self: &Arc<Self>, source: SocketAddr, request: &[u8]) -> Option<Pending> {
+        let Ok(Some(packet)) = proto::Packet::parse(request, 0) else {
+            // A request that can't be parsed is answered with an error without forwarding it.
+            return Some(Pending {
+                retransmits: Arc::clone(self),
+                key: None,
+            });
+        };
+        let questions = packet
+            .questions()
+            .flat_map(|q| q.as_bytes().to_vec())
+            .collect();
+        let key = (source, packet.header().id(), questions);
+        let inserted = self
+            .pending
+            .lock()
+            .expect("Pending requests to not be poisoned")
+            .insert(key.clone());
+        inserted.then(|| Pending {
+            retransmits: Arc::clone(self),
+            key: Some(key),
+        })
+    }
+}
+
+/// A request being answered. Copies of it are retransmissions until this is dropped.
+#[derive(Debug)]
+pub struct Pending {
+    retransmits: Arc<Retransmits>,
+    key: Option<RequestKey>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(key) = &self.key {
+            self.retransmits
+                .pending
+                .lock()
+                .expect("Pending requests to not be poisoned")
+                .remove(key);
+        }
+    }
+}
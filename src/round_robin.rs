@@ -0,0 +1,117 @@
+//! Rotation of answers with multiple records between responses, so clients that use the first
+//! record spread their load over every record (DNS round-robin). Answers can also be shuffled
+//! randomly instead, which needs no state per name.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::{domain_name::DomainName, proto::QType};
+
+/// The most names and types counters are kept for. Once reached the counters start over, which
+/// only makes the next responses start the rotation from the first record again.
+const MAX_COUNTERS: usize = 10_000;
+
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    counters: RwLock<HashMap<(DomainName, QType), AtomicUsize>>,
+}
+
+impl RoundRobin {
+    /// The number of places to rotate the records of type `q_type` of `name` by in the next
+    /// response.
+    pub fn next(&self, name: &DomainName, q_type: QType) -> usize {
+        let key = (name.clone(), q_type);
+        if let Some(counter) = self
+            .counters
+            .read()
+            .expect("Round-robin counters to not be poisoned")
+            .get(&key)
+        {
+            return counter.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut counters = self
+            .counters
+            .write()
+            .expect("Round-robin counters to not be poisoned");
+        if counters.len() >= MAX_COUNTERS && !counters.contains_key(&key) {
+            counters.clear();
+        }
+        counters
+            .entry(key)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Rotate the records of type `q_type` of `name` for the next response.
+    pub fn rotate<T>(&self, name: &DomainName, q_type: QType, records: &mut [T]) {
+        if records.len() > 1 {
+            let mid = self.next(name, q_type) % records.len();
+            records.rotate_left(mid);
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.counters
+            .read()
+            .expect("Round-robin counters to not be poisoned")
+            .len()
+    }
+}
+
+/// Random permutation of answers with multiple records.
+#[derive(Debug)]
+pub struct Shuffle {
+    rng: Mutex<StdRng>,
+}
+
+impl Shuffle {
+    /// A shuffle with a fixed seed, giving the same permutations every time.
+    pub fn from_seed(seed: u64) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    pub fn shuffle<T>(&self, records: &mut [T]) {
+        if records.len() > 1 {
+            records.shuffle(&mut *self.rng.lock().expect("Shuffle RNG to not be poisoned"));
+        }
+    }
+}
+
+impl Default for Shuffle {
+    fn default() -> Self {
+        Self {
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_kept_per_type_and_bounded() {
+        let round_robin = RoundRobin::default();
+        let name = DomainName::from_static("codecrafters.io");
+        assert_eq!(round_robin.next(&name, QType::A), 0);
+        assert_eq!(round_robin.next(&name, QType::A), 1);
+        assert_eq!(round_robin.next(&name, QType::AAAA), 0);
+
+        for i in 0..MAX_COUNTERS {
+            let other = format!("host{i}.codecrafters.io").parse().unwrap();
+            round_robin.next(&other, QType::A);
+        }
+        assert!(round_robin.len() <= MAX_COUNTERS);
+        assert_eq!(round_robin.next(&name, QType::A), 0);
+    }
+}
@@ -0,0 +1,300 @@
+//! Zones answered as a secondary. The records of the zone are copied from its primary with a zone
+//! transfer (AXFR, RFC 5936) at startup, retried until the primary answers, and transferred again
+//! once the primary has a newer serial in its SOA. The serial is checked every refresh interval
+//! of the SOA, or right away when the primary sends a NOTIFY (RFC 1996).
+//!
+//! Records removed from the zone by the primary stay in the cache, as the cache has no way to
+//! remove records yet.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Notify,
+};
+
+use crate::{
+    array_buffer::ArrayBuffer,
+    cache::EVCache,
+    domain_name::{DomainName, DomainNameParseError},
+    id::IdGenerator,
+    packet::DNSPacketBuilder,
+    proto::{self, Cursor, CursorError, FromPacketBytes, QClass, QType, Type},
+    question::Question,
+    resource::ResourceData,
+};
+
+/// A zone transferred from `primary`, parsed from the format zone@primary.
+#[derive(Debug, Clone)]
+pub struct Secondary {
+    pub zone: DomainName,
+    pub primary: SocketAddr,
+    notify: Arc<Notify>,
+    ids: Arc<IdGenerator>,
+    /// The zone has been transferred at least once.
+    loaded: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Error)]
+pub enum SecondaryParseError {
+    #[error("Expected a secondary zone in the format zone@primary")]
+    MissingPrimary,
+    #[error(transparent)]
+    Zone(#[from] DomainNameParseError),
+    #[error(transparent)]
+    Primary(#[from] std::net::AddrParseError),
+}
+
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    ParsePacket(#[from] proto::PacketError),
+    #[error(transparent)]
+    Record(#[from] CursorError),
+    #[error(transparent)]
+    Resource(#[from] proto::ResourceError),
+    #[error("The primary responded with {0:?}")]
+    ResponseCode(proto::ResponseCode),
+    #[error("The response does not start with the SOA of the zone")]
+    MissingSoa,
+    #[error("The primary did not respond in time")]
+    TimedOut,
+    #[error("The cache is no longer accepting records")]
+    CacheOperatorGone,
+}
+
+/// The bounds of the refresh interval in seconds, so an SOA can neither make the secondary check
+/// its primary nonstop nor put the next check off for weeks.
+pub const MIN_REFRESH: u32 = 60;
+pub const MAX_REFRESH: u32 = 24 * 60 * 60;
+
+/// The fields of an SOA record used to keep a secondary zone up to date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Soa {
+    pub serial: u32,
+    /// Seconds between checks of the serial of the primary.
+    pub refresh: u32,
+}
+
+impl std::str::FromStr for Secondary {
+    type Err = SecondaryParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (zone, primary) = s
+            .split_once('@')
+            .ok_or(SecondaryParseError::MissingPrimary)?;
+        Ok(Self {
+            zone: zone.parse()?,
+            primary: primary.parse()?,
+            notify: Arc::default(),
+            ids: Arc::default(),
+            loaded: Arc::default(),
+        })
+    }
+}
+
+impl Secondary {
+    /// Check the serial of the primary without waiting for the refresh interval.
+    pub fn notify(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Whether the records of the zone are in the cache, so it can be answered for.
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::Acquire)
+    }
+
+    /// Transfer the zone and publish its records to `cache`. Records outside the zone are left
+    /// out, as a primary has no say over other names (RFC 5936 section 3.5). Returns the SOA of
+    /// the transferred zone.
+    pub async fn load(&self, cache: &EVCache, timeout: Duration) -> Result<Soa, TransferError> {
+        let records = self.query(QType::AXFR, timeout).await?;
+        let soa = Soa::parse(records[0].1.data().as_ref())?;
+
+        let mut bulk = cache.bulk();
+        for (name, data) in records {
+            if !name.is_subdomain_of(&self.zone) {
+                tracing::warn!(zone = %self.zone, %name, "Ignoring record outside the zone");
+                continue;
+            }
+            bulk = bulk
+                .insert(&name, data)
+                .await
+                .map_err(|_| TransferError::CacheOperatorGone)?;
+        }
+        bulk.publish()
+            .await
+            .map_err(|_| TransferError::CacheOperatorGone)?;
+        self.loaded.store(true, Ordering::Release);
+        tracing::info!(zone = %self.zone, serial = soa.serial, "Transferred secondary zone");
+        Ok(soa)
+    }
+
+    /// Keep the zone in `cache` up to date, starting from the zone with the SOA `soa`. Without
+    /// one, as the zone could not be transferred yet, the transfer is retried every
+    /// [`MIN_REFRESH`] seconds until it succeeds. Runs until the task is aborted.
+    pub async fn refresh(self, cache: EVCache, timeout: Duration, mut soa: Option<Soa>) {
+        loop {
+            let interval = soa.map_or(Duration::from_secs(MIN_REFRESH.into()), |soa| {
+                soa.refresh_interval()
+            });
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = self.notify.notified() => {}
+            }
+            let Some(current) = soa else {
+                match self.load(&cache, timeout).await {
+                    Ok(new) => soa = Some(new),
+                    Err(e) => tracing::warn!(zone = %self.zone, message = %e, "Failed to transfer"),
+                }
+                continue;
+            };
+            let primary = match self.query(QType::SOA, timeout).await {
+                Ok(records) => Soa::parse(records[0].1.data().as_ref()),
+                Err(e) => {
+                    tracing::warn!(zone = %self.zone, message = %e, "Failed to check the serial");
+                    continue;
+                }
+            };
+            match primary {
+                Ok(primary) if serial_is_newer(primary.serial, current.serial) => {
+                    match self.load(&cache, timeout).await {
+                        Ok(new) => soa = Some(new),
+                        Err(e) => {
+                            tracing::warn!(zone = %self.zone, message = %e, "Failed to transfer")
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!(zone = %self.zone, message = %e, "Invalid SOA"),
+            }
+        }
+    }
+
+    /// Ask the primary for the records of type `q_type` at the apex of the zone over TCP. A zone
+    /// transfer is read until it ends with the SOA it started with, which is left out of the
+    /// returned records. `timeout` bounds the wait for each message rather than the whole
+    /// transfer, so large zones can take as long as they need while the primary keeps sending.
+    async fn query(
+        &self,
+        q_type: QType,
+        timeout: Duration,
+    ) -> Result<Vec<(DomainName, ResourceData)>, TransferError> {
+        let mut stream = within(timeout, TcpStream::connect(self.primary)).await?;
+        let id = self.ids.next();
+        let mut request = ArrayBuffer::new();
+        DNSPacketBuilder::query(id)
+            .add_question(Question::new(q_type, QClass::IN, self.zone.clone()))
+            .build_into(&mut request);
+        let len = (request.len() as u16).to_be_bytes();
+        within(timeout, stream.write_all(&len)).await?;
+        within(timeout, stream.write_all(request.as_slice())).await?;
+
+        let mut records = Vec::new();
+        let mut message = Vec::new();
+        loop {
+            let len = within(timeout, stream.read_u16()).await?;
+            message.resize(len as usize, 0);
+            within(timeout, stream.read_exact(&mut message)).await?;
+            let Some(packet) = proto::Packet::parse(&message, 0)? else {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            };
+            if packet.header().id() != id {
+                return Err(io::Error::other("Response to another query").into());
+            }
+            let code = packet.header().response_code();
+            if code.as_u8() != 0 {
+                return Err(TransferError::ResponseCode(code));
+            }
+            for answer in packet.answers() {
+                records.push((
+                    DomainName::from(&answer.name()),
+                    ResourceData::try_from(answer)?,
+                ));
+            }
+            let ended = records.len() > 1
+                && records
+                    .last()
+                    .is_some_and(|(_, data)| *data.typ() == Type::SOA);
+            if q_type != QType::AXFR || ended {
+                break;
+            }
+        }
+        if q_type == QType::AXFR {
+            records.pop();
+        }
+        match records.first() {
+            Some((name, data)) if *name == self.zone && *data.typ() == Type::SOA => Ok(records),
+            _ => Err(TransferError::MissingSoa),
+        }
+    }
+}
+
+/// Wait for `future` for at most `timeout`.
+async fn within<T>(
+    timeout: Duration,
+    future: impl std::future::Future<Output = io::Result<T>>,
+) -> Result<T, TransferError> {
+    Ok(tokio::time::timeout(timeout, future)
+        .await
+        .map_err(|_| TransferError::TimedOut)??)
+}
+
+impl Soa {
+    /// Read the fields from the data of an SOA record with uncompressed names.
+    pub fn parse(data: &[u8]) -> Result<Self, CursorError> {
+        let mut cursor = Cursor::new(data, 0);
+        cursor.read_name()?;
+        cursor.read_name()?;
+        Ok(Self {
+            serial: cursor.read_u32()?,
+            refresh: cursor.read_u32()?,
+        })
+    }
+
+    /// The time until the next check of the serial, with the refresh of the SOA clamped between
+    /// [`MIN_REFRESH`] and [`MAX_REFRESH`].
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh.clamp(MIN_REFRESH, MAX_REFRESH).into())
+    }
+}
+
+/// Whether `serial` is newer than `other` in serial number arithmetic, which lets serials wrap
+/// around (RFC 1982).
+pub fn serial_is_newer(serial: u32, other: u32) -> bool {
+    serial != other && serial.wrapping_sub(other) < 1 << 31
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serials_wrap_around() {
+        assert!(serial_is_newer(2, 1));
+        assert!(!serial_is_newer(1, 2));
+        assert!(!serial_is_newer(7, 7));
+        assert!(serial_is_newer(3, u32::MAX - 3));
+        assert!(!serial_is_newer(u32::MAX - 3, 3));
+    }
+
+    #[test]
+    fn refresh_interval_is_clamped() {
+        let interval = |refresh| Soa { serial: 1, refresh }.refresh_interval().as_secs();
+        assert_eq!(interval(0), MIN_REFRESH.into());
+        assert_eq!(interval(3600), 3600);
+        assert_eq!(interval(u32::MAX), MAX_REFRESH.into());
+    }
+}
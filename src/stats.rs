@@ -0,0 +1,59 @@
+//! Counters of the questions answered by the server, broken down by type, to show the mix of
+//! traffic it handles.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+use crate::proto::QType;
+
+#[derive(Debug, Default)]
+pub struct QueryStats {
+    /// Keyed by the number of the type, so the counts are listed in type order.
+    counters: RwLock<BTreeMap<u16, AtomicU64>>,
+}
+
+impl QueryStats {
+    /// Count a question of type `q_type`.
+    pub fn record(&self, q_type: QType) {
+        let typ = q_type.as_u16();
+        if let Some(counter) = self
+            .counters
+            .read()
+            .expect("Query counters to not be poisoned")
+            .get(&typ)
+        {
+            counter.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.counters
+            .write()
+            .expect("Query counters to not be poisoned")
+            .entry(typ)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of questions of type `q_type` counted so far.
+    pub fn count(&self, q_type: QType) -> u64 {
+        self.counters
+            .read()
+            .expect("Query counters to not be poisoned")
+            .get(&q_type.as_u16())
+            .map_or(0, |counter| counter.load(Ordering::Relaxed))
+    }
+
+    /// The number of questions of every type counted at least once, in type order.
+    pub fn counts(&self) -> Vec<(QType, u64)> {
+        self.counters
+            .read()
+            .expect("Query counters to not be poisoned")
+            .iter()
+            .map(|(typ, counter)| (QType::from(*typ), counter.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
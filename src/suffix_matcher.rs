@@ -0,0 +1,122 @@
+//! Matching names against a set of names, like a blocklist. The names are kept in a trie keyed
+//! by their labels from the root down, so a lookup takes one step per label of the name no
+//! matter how many names were inserted. Labels are compared ignoring ASCII case.
+
+use std::collections::HashMap;
+
+use crate::{domain_name::DomainName, label::Label};
+
+/// How a name matched the names of a [SuffixMatcher].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    /// The name was inserted with [SuffixMatcher::insert_exact].
+    Exact,
+    /// A parent of the name was inserted with [SuffixMatcher::insert_wildcard], like
+    /// `*.example.com` matching `www.example.com`.
+    Wildcard,
+    /// The name or a parent of it was inserted with [SuffixMatcher::insert].
+    Subtree,
+    None,
+}
+
+#[derive(Debug, Default)]
+pub struct SuffixMatcher {
+    root: Node,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    children: HashMap<Label, Node>,
+    exact: bool,
+    wildcard: bool,
+    subtree: bool,
+}
+
+impl SuffixMatcher {
+    /// Match `name` and every name below it.
+    pub fn insert(&mut self, name: &DomainName) {
+        self.node_mut(name).subtree = true;
+    }
+
+    /// Match `name` only.
+    pub fn insert_exact(&mut self, name: &DomainName) {
+        self.node_mut(name).exact = true;
+    }
+
+    /// Match the names below `name`, but not `name` itself.
+    pub fn insert_wildcard(&mut self, name: &DomainName) {
+        self.node_mut(name).wildcard = true;
+    }
+
+    /// How `name` is matched. The most specific match wins: an exact match, then the match of
+    /// the longest inserted parent.
+    pub fn matches(&self, name: &DomainName) -> MatchKind {
+        let labels: Vec<_> = name.labels().collect();
+        let mut node = &self.root;
+        let mut kind = MatchKind::None;
+        for label in labels.iter().rev() {
+            // The names below this node include `name`.
+            if node.subtree {
+                kind = MatchKind::Subtree;
+            } else if node.wildcard {
+                kind = MatchKind::Wildcard;
+            }
+            match node.children.get(label) {
+                Some(child) => node = child,
+                None => return kind,
+            }
+        }
+        if node.exact {
+            MatchKind::Exact
+        } else if node.subtree {
+            MatchKind::Subtree
+        } else {
+            kind
+        }
+    }
+
+    fn node_mut(&mut self, name: &DomainName) -> &mut Node {
+        let labels: Vec<_> = name.labels().collect();
+        let mut node = &mut self.root;
+        for label in labels.into_iter().rev() {
+            node = node.children.entry(label).or_default();
+        }
+        node
+    }
+}
+
+/// A matcher of the names and every name below them.
+impl FromIterator<DomainName> for SuffixMatcher {
+    fn from_iter<T: IntoIterator<Item = DomainName>>(iter: T) -> Self {
+        let mut matcher = Self::default();
+        for name in iter {
+            matcher.insert(&name);
+        }
+        matcher
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_wildcard_and_subtree_matches() {
+        let mut matcher = SuffixMatcher::default();
+        matcher.insert_exact(&DomainName::from_static("exact.example.com"));
+        matcher.insert_wildcard(&DomainName::from_static("wild.example.com"));
+        matcher.insert(&DomainName::from_static("ads.example.net"));
+
+        let kind = |name: &'static str| matcher.matches(&DomainName::from_static(name));
+        assert_eq!(kind("exact.example.com"), MatchKind::Exact);
+        assert_eq!(kind("EXACT.example.com"), MatchKind::Exact);
+        assert_eq!(kind("www.exact.example.com"), MatchKind::None);
+        assert_eq!(kind("www.wild.example.com"), MatchKind::Wildcard);
+        assert_eq!(kind("a.b.wild.example.com"), MatchKind::Wildcard);
+        assert_eq!(kind("wild.example.com"), MatchKind::None);
+        assert_eq!(kind("ads.example.net"), MatchKind::Subtree);
+        assert_eq!(kind("x.ads.example.net"), MatchKind::Subtree);
+        assert_eq!(kind("example.com"), MatchKind::None);
+        assert_eq!(kind("bads.example.net"), MatchKind::None);
+    }
+}
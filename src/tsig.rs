@@ -0,0 +1,331 @@
+//! Transaction signatures (RFC 8945). A request signed with a known key is verified before it is
+//! answered and the response is signed with the same key. Only HMAC-SHA256 is supported.
+
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::Engine;
+use bytes::BufMut;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{
+    array_buffer::ArrayBuffer,
+    domain_name::{DomainName, DomainNameParseError},
+    proto::{self, QClass, Type},
+};
+
+const ALGORITHM: &str = "hmac-sha256";
+const FUDGE: u16 = 300;
+/// The size of an HMAC-SHA256 MAC.
+const MAC_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone)]
+pub struct TsigKey {
+    name: DomainName,
+    secret: Arc<[u8]>,
+}
+
+#[derive(Debug, Error)]
+pub enum TsigKeyParseError {
+    #[error("Expected a key in the format name:secret")]
+    MissingSecret,
+    #[error(transparent)]
+    Name(#[from] DomainNameParseError),
+    #[error("The secret is not valid base64: {0}")]
+    Secret(#[from] base64::DecodeError),
+}
+
+#[derive(Debug, Error)]
+pub enum TsigError {
+    #[error("The transaction signature record is malformed: {0}")]
+    Malformed(#[from] proto::TsigError),
+    #[error("The message was signed with an unknown key or algorithm")]
+    BadKey,
+    #[error("The transaction signature does not match the message")]
+    BadSig,
+    #[error("The message was signed at {time_signed} which is outside of the allowed time window")]
+    BadTime { time_signed: u64 },
+}
+
+/// A request that carried a valid transaction signature. The response to it must be signed with
+/// the same key and include the MAC of the request.
+#[derive(Debug)]
+pub struct Signed {
+    pub key: TsigKey,
+    pub mac: Box<[u8]>,
+}
+
+impl TsigKey {
+    pub fn new(name: DomainName, secret: impl Into<Arc<[u8]>>) -> Self {
+        Self {
+            name,
+            secret: secret.into(),
+        }
+    }
+
+    fn hmac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC to accept keys of any size")
+    }
+}
+
+impl FromStr for TsigKey {
+    type Err = TsigKeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, secret) = s.split_once(':').ok_or(TsigKeyParseError::MissingSecret)?;
+        let secret = base64::engine::general_purpose::STANDARD.decode(secret)?;
+        Ok(Self::new(name.parse()?, secret))
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Verify the transaction signature of a packet, if it has one. `bytes` must be the buffer the
+/// packet was parsed from. Returns `None` for unsigned packets.
+pub fn verify(
+    keys: &[TsigKey],
+    packet: &proto::Packet<'_>,
+    bytes: &[u8],
+    now: u64,
+) -> Result<Option<Signed>, TsigError> {
+    // The TSIG record is always the last record of the message.
+    let Some(last) = packet.additional().last() else {
+        return Ok(None);
+    };
+    let Some(tsig) = proto::Tsig::from_resource(last)? else {
+        return Ok(None);
+    };
+
+    let key_name = tsig.key_name();
+    let Some(key) = keys.iter().find(|key| key.name.equals(&key_name)) else {
+        return Err(TsigError::BadKey);
+    };
+    if !tsig.algorithm().iter().eq([ALGORITHM]) {
+        return Err(TsigError::BadKey);
+    }
+
+    // The MAC covers the message as it was before the TSIG record was added.
+    let mut message = bytes[..tsig.offset()].to_vec();
+    message[0..2].copy_from_slice(&tsig.original_id().to_be_bytes());
+    let additional_entries = packet.header().additional_entries() - 1;
+    message[10..12].copy_from_slice(&additional_entries.to_be_bytes());
+
+    let mut mac = key.hmac();
+    mac.update(&message);
+    mac.update(&variables(
+        key,
+        tsig.time_signed(),
+        tsig.fudge(),
+        tsig.error(),
+        tsig.other(),
+    ));
+    mac.verify_slice(tsig.mac())
+        .map_err(|_| TsigError::BadSig)?;
+
+    if now.abs_diff(tsig.time_signed()) > tsig.fudge() as u64 {
+        return Err(TsigError::BadTime {
+            time_signed: tsig.time_signed(),
+        });
+    }
+
+    Ok(Some(Signed {
+        key: key.clone(),
+        mac: tsig.mac().into(),
+    }))
+}
+
+/// Append a TSIG record to a built message. When signing a response `request_mac` is the MAC of
+/// the signed request. Returns the MAC of the message, or `None` when there is no room left in
+/// the buffer for the record.
+pub fn sign(
+    buffer: &mut ArrayBuffer,
+    key: &TsigKey,
+    request_mac: Option<&[u8]>,
+    time_signed: u64,
+) -> Option<Box<[u8]>> {
+    let variables = variables(key, time_signed, FUDGE, 0, &[]);
+    append(buffer, key, request_mac, &variables, time_signed)
+}
+
+/// Append a TSIG record to a message after the first of a response spanning several messages,
+/// like a zone transfer. Only the timers are covered with the message and the MAC of the
+/// previous message (RFC 8945 section 5.3.1).
+pub fn sign_subsequent(
+    buffer: &mut ArrayBuffer,
+    key: &TsigKey,
+    prior_mac: &[u8],
+    time_signed: u64,
+) -> Option<Box<[u8]>> {
+    let mut timers = Vec::new();
+    timers.put_u16((time_signed >> 32) as u16);
+    timers.put_u32(time_signed as u32);
+    timers.put_u16(FUDGE);
+    append(buffer, key, Some(prior_mac), &timers, time_signed)
+}
+
+/// The size of the TSIG record appended when signing with `key`.
+pub fn record_len(key: &TsigKey) -> usize {
+    let data_len = ALGORITHM.len() + 2 + 16 + MAC_LEN;
+    key.name.size_in_packet() + 10 + data_len
+}
+
+fn append(
+    buffer: &mut ArrayBuffer,
+    key: &TsigKey,
+    prior_mac: Option<&[u8]>,
+    variables: &[u8],
+    time_signed: u64,
+) -> Option<Box<[u8]>> {
+    let mut mac = key.hmac();
+    if let Some(prior_mac) = prior_mac {
+        mac.update(&(prior_mac.len() as u16).to_be_bytes());
+        mac.update(prior_mac);
+    }
+    mac.update(buffer.as_slice());
+    mac.update(variables);
+    let mac = mac.finalize().into_bytes();
+
+    let name = wire_name(key.name.labels());
+    let algorithm = wire_name(ALGORITHM.split('.'));
+    let data_len = algorithm.len() + 16 + mac.len();
+    if buffer.remaining_mut() < name.len() + 10 + data_len {
+        return None;
+    }
+
+    let id = [buffer[0], buffer[1]];
+    buffer.put_slice(&name);
+    buffer.put_u16(Type::TSIG.as_u16());
+    buffer.put_u16(QClass::Any.as_u16());
+    buffer.put_u32(0);
+    buffer.put_u16(data_len as u16);
+    buffer.put_slice(&algorithm);
+    buffer.put_u16((time_signed >> 32) as u16);
+    buffer.put_u32(time_signed as u32);
+    buffer.put_u16(FUDGE);
+    buffer.put_u16(mac.len() as u16);
+    buffer.put_slice(&mac);
+    buffer.put_slice(&id);
+    buffer.put_u16(0);
+    buffer.put_u16(0);
+
+    let additional_entries = u16::from_be_bytes([buffer[10], buffer[11]]) + 1;
+    buffer[10..12].copy_from_slice(&additional_entries.to_be_bytes());
+    Some(mac.as_slice().into())
+}
+
+/// The TSIG variables appended to the message when calculating the MAC.
+fn variables(key: &TsigKey, time_signed: u64, fudge: u16, error: u16, other: &[u8]) -> Vec<u8> {
+    let mut variables = wire_name(key.name.labels());
+    variables.put_u16(QClass::Any.as_u16());
+    variables.put_u32(0);
+    variables.put_slice(&wire_name(ALGORITHM.split('.')));
+    variables.put_u16((time_signed >> 32) as u16);
+    variables.put_u32(time_signed as u32);
+    variables.put_u16(fudge);
+    variables.put_u16(error);
+    variables.put_u16(other.len() as u16);
+    variables.put_slice(other);
+    variables
+}
+
+/// A name in its canonical wire format, uncompressed and in lower case.
+fn wire_name<L: AsRef<str>>(labels: impl Iterator<Item = L>) -> Vec<u8> {
+    let mut name = Vec::new();
+    for label in labels {
+        let label = label.as_ref();
+        name.put_u8(label.len() as u8);
+        name.extend(label.bytes().map(|c| c.to_ascii_lowercase()));
+    }
+    name.put_u8(0);
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packet::DNSPacketBuilder, proto::FromPacketBytes, question::Question};
+
+    fn key() -> TsigKey {
+        "transfer-key:c2VjcmV0IGtleSBmb3IgdGVzdGluZw=="
+            .parse()
+            .unwrap()
+    }
+
+    fn signed_query(key: &TsigKey, time_signed: u64) -> ArrayBuffer {
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(1234)
+            .add_question(Question::new(
+                proto::QType::A,
+                QClass::IN,
+                DomainName::from_static("codecrafters.io"),
+            ))
+            .build_into(&mut buffer);
+        assert!(sign(&mut buffer, key, None, time_signed).is_some());
+        buffer
+    }
+
+    #[test]
+    fn signed_query_is_accepted() {
+        let key = key();
+        let buffer = signed_query(&key, now());
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        let signed = verify(&[key], &packet, &buffer, now()).unwrap().unwrap();
+        assert_eq!(signed.mac.len(), 32);
+    }
+
+    #[test]
+    fn tampered_query_is_rejected() {
+        let key = key();
+        let mut buffer = signed_query(&key, now());
+        // Change the question name from codecrafters.io to codecrafters.ip
+        assert_eq!(buffer[27], b'o');
+        buffer[27] = b'p';
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert!(matches!(
+            verify(&[key], &packet, &buffer, now()),
+            Err(TsigError::BadSig)
+        ));
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let buffer = signed_query(&key(), now());
+        let other: TsigKey = "other-key:c2VjcmV0".parse().unwrap();
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert!(matches!(
+            verify(&[other], &packet, &buffer, now()),
+            Err(TsigError::BadKey)
+        ));
+    }
+
+    #[test]
+    fn old_signature_is_rejected() {
+        let key = key();
+        let buffer = signed_query(&key, now() - 3600);
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert!(matches!(
+            verify(&[key], &packet, &buffer, now()),
+            Err(TsigError::BadTime { .. })
+        ));
+    }
+
+    #[test]
+    fn unsigned_query_is_not_verified() {
+        let mut buffer = ArrayBuffer::new();
+        DNSPacketBuilder::query(1).build_into(&mut buffer);
+        let packet = proto::Packet::parse(&buffer, 0).unwrap().unwrap();
+        assert!(verify(&[key()], &packet, &buffer, now()).unwrap().is_none());
+    }
+}
@@ -1,9 +1,21 @@
-use std::sync::Arc;
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+/// The largest amount of bytes that can be stored in [CowData::Inline], which fits an IPv6
+/// address.
+const INLINE_SIZE: usize = 16;
 
 #[derive(Debug, Clone)]
 pub enum CowData<'a> {
     Owned(Arc<[u8]>),
     Borrowed(&'a [u8]),
+    /// Small fixed size data, like addresses, stored without a heap allocation.
+    Inline {
+        len: u8,
+        data: [u8; INLINE_SIZE],
+    },
 }
 
 impl<'a> CowData<'a> {
@@ -11,6 +23,7 @@ impl<'a> CowData<'a> {
         match self {
             Self::Owned(data) => data.len(),
             Self::Borrowed(data) => data.len(),
+            Self::Inline { len, .. } => *len as usize,
         }
     }
 }
@@ -20,6 +33,7 @@ impl<'a> AsRef<[u8]> for CowData<'a> {
         match self {
             Self::Owned(data) => data.as_ref(),
             Self::Borrowed(data) => data,
+            Self::Inline { len, data } => &data[..*len as usize],
         }
     }
 }
@@ -29,3 +43,53 @@ impl<'a> From<&'a [u8]> for CowData<'a> {
         CowData::Borrowed(value)
     }
 }
+
+impl<'a> From<[u8; 4]> for CowData<'a> {
+    fn from(value: [u8; 4]) -> Self {
+        let mut data = [0; INLINE_SIZE];
+        data[..4].copy_from_slice(&value);
+        CowData::Inline { len: 4, data }
+    }
+}
+
+impl<'a> From<[u8; 16]> for CowData<'a> {
+    fn from(value: [u8; 16]) -> Self {
+        CowData::Inline {
+            len: 16,
+            data: value,
+        }
+    }
+}
+
+impl<'a> From<Ipv4Addr> for CowData<'a> {
+    fn from(value: Ipv4Addr) -> Self {
+        Self::from(value.octets())
+    }
+}
+
+impl<'a> From<Ipv6Addr> for CowData<'a> {
+    fn from(value: Ipv6Addr) -> Self {
+        Self::from(value.octets())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_ipv6_data() {
+        let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let data = CowData::from(addr);
+        assert!(matches!(data, CowData::Inline { len: 16, .. }));
+        assert_eq!(data.len(), 16);
+        assert_eq!(data.as_ref(), &addr.octets());
+    }
+
+    #[test]
+    fn inline_ipv4_data() {
+        let data = CowData::from(Ipv4Addr::new(8, 8, 4, 4));
+        assert_eq!(data.len(), 4);
+        assert_eq!(data.as_ref(), &[8, 8, 4, 4]);
+    }
+}
@@ -0,0 +1,717 @@
+//! Transports to the resolvers questions are forwarded to. A resolver is given as a URL whose
+//! scheme selects the transport: `udp://`, `tcp://`, `tls://` (DNS over TLS, RFC 7858) or
+//! `https://` (DNS over HTTPS, RFC 8484). An address without a scheme is a UDP resolver.
+//!
+//! Every UDP resolver gets a single connected socket shared by all forwards, and a response is
+//! routed back to the forward waiting for it by the ID of the query. The other transports open a
+//! connection for every query.
+//!
+//! Resolvers can be probed with a query for the name servers of the root. One that fails
+//! [FAILED_PROBES_UNTIL_DOWN] probes in a row is down until it answers a probe again, so it can
+//! be skipped without waiting for a timeout on every forward.
+
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpStream, UdpSocket},
+    sync::oneshot,
+    task::JoinHandle,
+};
+use tokio_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+
+use crate::{
+    array_buffer::ArrayBuffer,
+    domain_name::DomainName,
+    id::IdGenerator,
+    packet::DNSPacketBuilder,
+    proto::{self, FromPacketBytes, QClass, QType},
+    question::Question,
+};
+
+/// The number of failed probes in a row after which a resolver is down.
+pub const FAILED_PROBES_UNTIL_DOWN: u32 = 3;
+
+/// The path of DNS over HTTPS resolvers given without one.
+pub const DEFAULT_HTTPS_PATH: &str = "/dns-query";
+
+type Pending = Mutex<HashMap<u16, oneshot::Sender<io::Result<Vec<u8>>>>>;
+
+/// A resolver and the transport used to reach it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UpstreamSpec {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    /// `server_name` is checked against the certificate of the resolver.
+    Tls {
+        addr: SocketAddr,
+        server_name: String,
+    },
+    Https {
+        host: String,
+        port: u16,
+        path: String,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum UpstreamSpecParseError {
+    #[error("Unknown resolver scheme {0}, expected udp, tcp, tls or https")]
+    Scheme(String),
+    #[error("Expected an IP address with an optional port")]
+    Address,
+    #[error("Expected a host with an optional port")]
+    Host,
+    #[error("Expected a port from 1 to 65535")]
+    Port,
+}
+
+#[derive(Debug, Error)]
+pub enum ForwardError {
+    #[error(transparent)]
+    ParsePacket(#[from] proto::PacketError),
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error("Failed to connect to the resolver: {0}")]
+    Connect(io::Error),
+    #[error("The resolver did not respond in time")]
+    TimedOut,
+    #[error("The response does not echo the question exactly as it was sent")]
+    QuestionMismatch,
+    #[error("The response has a different ID than the query")]
+    IdMismatch,
+    #[error("The resolver responded with HTTP status {0}")]
+    HttpStatus(u16),
+}
+
+/// A transport to a resolver.
+#[async_trait]
+pub trait Upstream: Send + Sync {
+    /// Send the DNS message `msg` and return the response to it.
+    async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError>;
+}
+
+/// The transports to resolvers, created the first time a resolver is forwarded to.
+#[derive(Default)]
+pub struct Upstreams {
+    upstreams: Mutex<HashMap<UpstreamSpec, Arc<Connection>>>,
+    /// The IDs of health probes, which TCP, TLS and HTTPS resolvers get as they are.
+    ids: IdGenerator,
+}
+
+/// The transport to a resolver and its health.
+struct Connection {
+    upstream: Arc<dyn Upstream>,
+    failed_probes: AtomicU32,
+}
+
+/// A socket connected to a single UDP resolver.
+pub struct UdpUpstream {
+    socket: Arc<UdpSocket>,
+    pending: Arc<Pending>,
+    ids: IdGenerator,
+    dispatcher: JoinHandle<()>,
+}
+
+/// A resolver reached over TCP.
+pub struct TcpUpstream {
+    addr: SocketAddr,
+}
+
+/// A resolver reached over TLS.
+pub struct TlsUpstream {
+    addr: SocketAddr,
+    server_name: ServerName<'static>,
+    connector: TlsConnector,
+}
+
+/// A resolver reached over HTTPS, which is sent queries in POST requests.
+pub struct HttpsUpstream {
+    host: String,
+    port: u16,
+    path: String,
+    server_name: ServerName<'static>,
+    connector: TlsConnector,
+}
+
+/// Removes a query from the pending queries when its forward completes or is cancelled.
+struct PendingQuery<'a> {
+    pending: &'a Pending,
+    id: u16,
+}
+
+impl From<SocketAddr> for UpstreamSpec {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Udp(addr)
+    }
+}
+
+impl std::str::FromStr for UpstreamSpec {
+    type Err = UpstreamSpecParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, rest)) = s.split_once("://") else {
+            return Ok(Self::Udp(parse_addr(s, 53)?));
+        };
+        match scheme {
+            "udp" => Ok(Self::Udp(parse_addr(rest, 53)?)),
+            "tcp" => Ok(Self::Tcp(parse_addr(rest, 53)?)),
+            "tls" => {
+                let (addr, server_name) = match rest.split_once('#') {
+                    Some((addr, server_name)) => (parse_addr(addr, 853)?, server_name.to_owned()),
+                    None => {
+                        let addr = parse_addr(rest, 853)?;
+                        (addr, addr.ip().to_string())
+                    }
+                };
+                ServerName::try_from(server_name.as_str())
+                    .map_err(|_| UpstreamSpecParseError::Host)?;
+                Ok(Self::Tls { addr, server_name })
+            }
+            "https" => {
+                let (authority, path) = match rest.find('/') {
+                    Some(index) => rest.split_at(index),
+                    None => (rest, DEFAULT_HTTPS_PATH),
+                };
+                let (host, port) = match authority.rsplit_once(':') {
+                    Some((host, port)) if !port.contains(']') => (
+                        host,
+                        port.parse().map_err(|_| UpstreamSpecParseError::Port)?,
+                    ),
+                    _ => (authority, 443),
+                };
+                if port == 0 {
+                    return Err(UpstreamSpecParseError::Port);
+                }
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                ServerName::try_from(host).map_err(|_| UpstreamSpecParseError::Host)?;
+                Ok(Self::Https {
+                    host: host.to_owned(),
+                    port,
+                    path: path.to_owned(),
+                })
+            }
+            _ => Err(UpstreamSpecParseError::Scheme(scheme.to_owned())),
+        }
+    }
+}
+
+/// Parse an IP address with an optional port, using `default_port` without one.
+fn parse_addr(s: &str, default_port: u16) -> Result<SocketAddr, UpstreamSpecParseError> {
+    let ip = s.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = ip.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
+    }
+    let (ip, port) = s.rsplit_once(':').ok_or(UpstreamSpecParseError::Address)?;
+    let ip: IpAddr = ip
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|_| UpstreamSpecParseError::Address)?;
+    match port.parse() {
+        Ok(0) | Err(_) => Err(UpstreamSpecParseError::Port),
+        Ok(port) => Ok(SocketAddr::new(ip, port)),
+    }
+}
+
+impl fmt::Display for UpstreamSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Udp(addr) => write!(f, "udp://{addr}"),
+            Self::Tcp(addr) => write!(f, "tcp://{addr}"),
+            Self::Tls { addr, server_name } => write!(f, "tls://{addr}#{server_name}"),
+            Self::Https { host, port, path } if host.contains(':') => {
+                write!(f, "https://[{host}]:{port}{path}")
+            }
+            Self::Https { host, port, path } => write!(f, "https://{host}:{port}{path}"),
+        }
+    }
+}
+
+impl UpstreamSpec {
+    /// Whether queries to the resolver are encrypted, over TLS or HTTPS.
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, Self::Tls { .. } | Self::Https { .. })
+    }
+
+    /// Open a transport to the resolver. Must be called within a Tokio runtime.
+    pub fn connect(&self) -> io::Result<Arc<dyn Upstream>> {
+        Ok(match self {
+            Self::Udp(addr) => Arc::new(UdpUpstream::connect(*addr)?),
+            Self::Tcp(addr) => Arc::new(TcpUpstream { addr: *addr }),
+            Self::Tls { addr, server_name } => Arc::new(TlsUpstream {
+                addr: *addr,
+                server_name: server_name_of(server_name)?,
+                connector: tls_connector(&[]),
+            }),
+            Self::Https { host, port, path } => Arc::new(HttpsUpstream {
+                host: host.clone(),
+                port: *port,
+                path: path.clone(),
+                server_name: server_name_of(host)?,
+                connector: tls_connector(&[b"http/1.1"]),
+            }),
+        })
+    }
+}
+
+fn server_name_of(name: &str) -> io::Result<ServerName<'static>> {
+    ServerName::try_from(name.to_owned())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// A TLS connector trusting the web PKI roots, offering the protocols in `alpn`.
+fn tls_connector(alpn: &[&[u8]]) -> TlsConnector {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let mut config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    TlsConnector::from(Arc::new(config))
+}
+
+impl Upstreams {
+    /// The transport to `resolver`. Must be called within a Tokio runtime.
+    pub fn get(&self, resolver: &UpstreamSpec) -> io::Result<Arc<dyn Upstream>> {
+        Ok(Arc::clone(&self.connection(resolver)?.upstream))
+    }
+
+    /// Use `upstream` as the transport to `resolver` in place of the one for its scheme.
+    pub fn insert(&self, resolver: UpstreamSpec, upstream: Arc<dyn Upstream>) {
+        let connection = Connection {
+            upstream,
+            failed_probes: AtomicU32::new(0),
+        };
+        self.upstreams
+            .lock()
+            .unwrap()
+            .insert(resolver, Arc::new(connection));
+    }
+
+    fn connection(&self, resolver: &UpstreamSpec) -> io::Result<Arc<Connection>> {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        if let Some(connection) = upstreams.get(resolver) {
+            return Ok(Arc::clone(connection));
+        }
+        let connection = Arc::new(Connection {
+            upstream: resolver.connect()?,
+            failed_probes: AtomicU32::new(0),
+        });
+        upstreams.insert(resolver.clone(), Arc::clone(&connection));
+        Ok(connection)
+    }
+
+    /// The number of resolvers with an open transport.
+    pub fn socket_count(&self) -> usize {
+        self.upstreams.lock().unwrap().len()
+    }
+
+    /// Whether `resolver` is up. A resolver that was never probed is up.
+    pub fn is_healthy(&self, resolver: &UpstreamSpec) -> bool {
+        self.upstreams
+            .lock()
+            .unwrap()
+            .get(resolver)
+            .is_none_or(|connection| connection.is_healthy())
+    }
+
+    /// Whether each resolver with an open transport is up.
+    pub fn health(&self) -> Vec<(UpstreamSpec, bool)> {
+        self.upstreams
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(resolver, connection)| (resolver.clone(), connection.is_healthy()))
+            .collect()
+    }
+
+    /// Send a probe to `resolver` and record whether it was answered within `timeout`. Returns
+    /// whether the resolver is up afterwards.
+    pub async fn probe(&self, resolver: &UpstreamSpec, timeout: Duration) -> bool {
+        let connection = match self.connection(resolver) {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::warn!(%resolver, message = %e, "Failed to open a transport to the resolver");
+                return false;
+            }
+        };
+        let mut query = ArrayBuffer::new();
+        DNSPacketBuilder::query(self.ids.next())
+            .add_question(Question::new(
+                QType::NS,
+                QClass::IN,
+                DomainName::from_static(""),
+            ))
+            .build_into(&mut query);
+        let answered = match tokio::time::timeout(timeout, connection.upstream.query(&query)).await
+        {
+            Ok(Ok(response)) => proto::Packet::parse(&response, 0).is_ok_and(|p| p.is_some()),
+            _ => false,
+        };
+
+        let was_healthy = connection.is_healthy();
+        if answered {
+            connection.failed_probes.store(0, Ordering::Relaxed);
+        } else {
+            connection.failed_probes.fetch_add(1, Ordering::Relaxed);
+        }
+        let healthy = connection.is_healthy();
+        if healthy != was_healthy {
+            tracing::warn!(%resolver, healthy, "Resolver health changed");
+        }
+        healthy
+    }
+}
+
+impl Connection {
+    fn is_healthy(&self) -> bool {
+        self.failed_probes.load(Ordering::Relaxed) < FAILED_PROBES_UNTIL_DOWN
+    }
+}
+
+impl UdpUpstream {
+    fn connect(resolver: SocketAddr) -> io::Result<Self> {
+        let local = match resolver {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = std::net::UdpSocket::bind(local)?;
+        socket.connect(resolver)?;
+        socket.set_nonblocking(true)?;
+        let socket = Arc::new(UdpSocket::from_std(socket)?);
+
+        let pending = Arc::default();
+        let dispatcher = tokio::spawn(dispatch(Arc::clone(&socket), Arc::clone(&pending)));
+        Ok(Self {
+            socket,
+            pending,
+            ids: IdGenerator::default(),
+            dispatcher,
+        })
+    }
+}
+
+#[async_trait]
+impl Upstream for UdpUpstream {
+    /// The ID of the query is replaced with a random one no other pending query on this socket
+    /// uses, and restored in the response.
+    async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError> {
+        let Some(&original_id) = msg.first_chunk::<2>() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The query has no ID").into());
+        };
+        let mut query = msg.to_vec();
+        let (tx, rx) = oneshot::channel();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.len() > u16::MAX as usize {
+                return Err(io::Error::other("Every query ID is in use").into());
+            }
+            let id = loop {
+                let id = self.ids.next();
+                if !pending.contains_key(&id) {
+                    break id;
+                }
+            };
+            pending.insert(id, tx);
+            id
+        };
+        let _pending = PendingQuery {
+            pending: &self.pending,
+            id,
+        };
+
+        query[..2].copy_from_slice(&id.to_be_bytes());
+        self.socket.send(&query).await?;
+        let mut response = rx
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))??;
+        response[..2].copy_from_slice(&original_id);
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl Upstream for TcpUpstream {
+    async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError> {
+        let mut stream = TcpStream::connect(self.addr)
+            .await
+            .map_err(ForwardError::Connect)?;
+        Ok(exchange_framed(&mut stream, msg).await?)
+    }
+}
+
+#[async_trait]
+impl Upstream for TlsUpstream {
+    async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError> {
+        let stream = TcpStream::connect(self.addr)
+            .await
+            .map_err(ForwardError::Connect)?;
+        let mut stream = self
+            .connector
+            .connect(self.server_name.clone(), stream)
+            .await?;
+        Ok(exchange_framed(&mut stream, msg).await?)
+    }
+}
+
+#[async_trait]
+impl Upstream for HttpsUpstream {
+    async fn query(&self, msg: &[u8]) -> Result<Vec<u8>, ForwardError> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(ForwardError::Connect)?;
+        let mut stream = self
+            .connector
+            .connect(self.server_name.clone(), stream)
+            .await?;
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        };
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/dns-message\r\n\
+             Accept: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            msg.len(),
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(msg).await?;
+        stream.flush().await?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| invalid_http("Invalid status line"))?;
+        if status != 200 {
+            return Err(ForwardError::HttpStatus(status));
+        }
+        // Chunked responses are not supported, every response must give its length.
+        let mut content_length = None;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(invalid_http("Missing end of the headers").into());
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<u16>().ok();
+                }
+            }
+        }
+        let content_length = content_length.ok_or_else(|| invalid_http("Missing length"))?;
+        let mut response = vec![0; content_length as usize];
+        reader.read_exact(&mut response).await?;
+        Ok(response)
+    }
+}
+
+fn invalid_http(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Send `msg` and read its response on a stream, where both are prefixed with their length as on
+/// TCP (RFC 1035 section 4.2.2).
+async fn exchange_framed(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    msg: &[u8],
+) -> io::Result<Vec<u8>> {
+    let len =
+        u16::try_from(msg.len()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(msg).await?;
+    stream.flush().await?;
+    let len = stream.read_u16().await?;
+    let mut response = vec![0; len as usize];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+impl Drop for UdpUpstream {
+    fn drop(&mut self) {
+        self.dispatcher.abort();
+    }
+}
+
+impl Drop for PendingQuery<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Receive the responses of the resolver and hand them to the queries waiting for them.
+async fn dispatch(socket: Arc<UdpSocket>, pending: Arc<Pending>) {
+    // Resolvers may send more than the payload size advertised to them, so a response is only
+    // cut off by the limit of UDP itself.
+    let mut buffer = vec![0; u16::MAX as usize];
+    loop {
+        match socket.recv(&mut buffer).await {
+            Ok(size) if size >= 2 => {
+                let id = u16::from_be_bytes([buffer[0], buffer[1]]);
+                match pending.lock().unwrap().remove(&id) {
+                    Some(tx) => {
+                        let _ = tx.send(Ok(buffer[..size].to_vec()));
+                    }
+                    None => tracing::warn!(id, "Response to a query that is not pending"),
+                }
+            }
+            Ok(_) => {}
+            // Errors of a connected socket, like an unreachable resolver, are not tied to a
+            // single query, so every pending query fails.
+            Err(e) => {
+                for (_, tx) in pending.lock().unwrap().drain() {
+                    let _ = tx.send(Err(io::Error::from(e.kind())));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn responses_are_routed_by_id() {
+        // Echo the queries back in reverse order of arrival.
+        let resolver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = resolver.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut received = Vec::new();
+            let mut buf = [0; 512];
+            while received.len() < 3 {
+                let (size, source) = resolver.recv_from(&mut buf).await.unwrap();
+                received.push((buf[..size].to_vec(), source));
+            }
+            for (query, source) in received.into_iter().rev() {
+                resolver.send_to(&query, source).await.unwrap();
+            }
+        });
+
+        let upstreams = Upstreams::default();
+        let exchange = |payload: u8| {
+            let upstream = upstreams.get(&UpstreamSpec::Udp(addr)).unwrap();
+            async move { upstream.query(&[0, 7, payload]).await.unwrap() }
+        };
+        let responses = tokio::join!(exchange(1), exchange(2), exchange(3));
+        assert_eq!(responses, (vec![0, 7, 1], vec![0, 7, 2], vec![0, 7, 3]));
+        assert_eq!(upstreams.socket_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_received_whole() {
+        let resolver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = resolver.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (size, source) = resolver.recv_from(&mut buf).await.unwrap();
+            let mut response = buf[..size].to_vec();
+            response.resize(4000, 1);
+            resolver.send_to(&response, source).await.unwrap();
+        });
+
+        let upstreams = Upstreams::default();
+        let upstream = upstreams.get(&UpstreamSpec::Udp(addr)).unwrap();
+        let response = upstream.query(&[0, 7]).await.unwrap();
+        assert_eq!(response.len(), 4000);
+    }
+
+    #[tokio::test]
+    async fn queries_without_an_id_are_rejected() {
+        let resolver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstreams = Upstreams::default();
+        let spec = UpstreamSpec::Udp(resolver.local_addr().unwrap());
+        let upstream = upstreams.get(&spec).unwrap();
+        for query in [&[][..], &[7]] {
+            let error = upstream.query(query).await.unwrap_err();
+            assert!(
+                matches!(&error, ForwardError::IO(e) if e.kind() == io::ErrorKind::InvalidInput),
+                "{error:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn upstream_specs_default_ports_per_scheme() {
+        let parse = |s: &str| s.parse::<UpstreamSpec>().unwrap();
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert_eq!(parse("1.1.1.1"), UpstreamSpec::Udp(addr("1.1.1.1:53")));
+        assert_eq!(
+            parse("1.1.1.1:5353"),
+            UpstreamSpec::Udp(addr("1.1.1.1:5353"))
+        );
+        assert_eq!(parse("udp://[::1]"), UpstreamSpec::Udp(addr("[::1]:53")));
+        assert_eq!(
+            parse("tcp://1.1.1.1"),
+            UpstreamSpec::Tcp(addr("1.1.1.1:53"))
+        );
+        assert_eq!(
+            parse("tls://1.1.1.1:853#cloudflare-dns.com"),
+            UpstreamSpec::Tls {
+                addr: addr("1.1.1.1:853"),
+                server_name: "cloudflare-dns.com".to_owned(),
+            }
+        );
+        assert_eq!(
+            parse("tls://9.9.9.9"),
+            UpstreamSpec::Tls {
+                addr: addr("9.9.9.9:853"),
+                server_name: "9.9.9.9".to_owned(),
+            }
+        );
+        let https = parse("https://dns.google/dns-query");
+        assert_eq!(
+            https,
+            UpstreamSpec::Https {
+                host: "dns.google".to_owned(),
+                port: 443,
+                path: "/dns-query".to_owned(),
+            }
+        );
+        assert_eq!(parse(&https.to_string()), https);
+        assert_eq!(parse("https://dns.google"), https);
+    }
+
+    #[test]
+    fn malformed_upstream_specs_are_rejected() {
+        for spec in [
+            "",
+            "dns.google",
+            "1.1.1.1:",
+            "1.1.1.1:0",
+            "1.1.1.1:65536",
+            "tcp://",
+            "quic://1.1.1.1",
+            "tls://dns.google",
+            "tls://1.1.1.1#",
+            "https://",
+            "https://dns.google:0/dns-query",
+            "https://dns.google:https/dns-query",
+        ] {
+            assert!(spec.parse::<UpstreamSpec>().is_err(), "{spec}");
+        }
+    }
+}
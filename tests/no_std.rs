@@ -0,0 +1,30 @@
+//! Checks that packets can be parsed with the library built as `no_std`. Run with
+//! `cargo test --no-default-features --test no_std`, as the default `std` feature turns this
+//! test off.
+#![cfg(not(feature = "std"))]
+
+use dns_starter_rust::proto::{FromPacketBytes, Packet, QType};
+
+#[test]
+fn parse_response() {
+    #[rustfmt::skip]
+    let response = [
+        // ID 1234, a response with one question and one answer
+        4, 210, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0,
+        // codecrafters.io. A IN
+        12, b'c', b'o', b'd', b'e', b'c', b'r', b'a', b'f', b't', b'e', b'r', b's', 2, b'i', b'o',
+        0, 0, 1, 0, 1,
+        // A pointer to the name of the question, IN A 60 8.8.8.8
+        0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 8, 8, 8, 8,
+    ];
+    let packet = Packet::parse(&response, 0).unwrap().unwrap();
+    assert_eq!(packet.header().id(), 1234);
+
+    let question = packet.questions().next().unwrap();
+    assert_eq!(question.q_type(), QType::A);
+    assert_eq!(question.name().to_string(), "codecrafters.io.");
+
+    let answer = packet.answers().next().unwrap();
+    assert_eq!(answer.ttl(), 60);
+    assert_eq!(answer.data().unwrap(), &[8, 8, 8, 8]);
+}